@@ -0,0 +1,216 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use actix_web::http::StatusCode;
+use actix_web::HttpRequest;
+use ipnet::IpNet;
+use log::{info, warn};
+use serde::Serialize;
+
+use crate::client_ip;
+use crate::config::Config;
+
+/// Format of access log lines.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AccessLogFormat {
+    /// `<time> <client> "<method> <path> <version>" <status> <duration>ms`
+    #[default]
+    Plain,
+    /// One JSON object per line.
+    Json,
+}
+
+/// Writes one line per proxied request, either through the logger or to
+/// the file given in `ACCESS_LOG_FILE`.
+///
+/// File output happens on a background thread, so requests never wait for
+/// the disk.
+///
+/// With a `LOG_SAMPLE_RATE` below 1, only that share of the successful
+/// requests is logged, picked at random. Errors and requests that took at
+/// least `LOG_SLOW_REQUEST_MS` are always logged.
+pub struct AccessLog {
+    format: AccessLogFormat,
+    trust_forwarded_for: bool,
+    trusted_proxies: Vec<IpNet>,
+    sender: Option<Sender<String>>,
+    sample_rate: f64,
+    slow_threshold: Duration,
+    rng: Mutex<fastrand::Rng>,
+}
+
+impl AccessLog {
+    pub fn new(config: &Config) -> Self {
+        Self::with_rng(config, fastrand::Rng::new())
+    }
+
+    /// Creates the access log sampling requests with `rng`, so that tests
+    /// can seed it.
+    pub fn with_rng(config: &Config, rng: fastrand::Rng) -> Self {
+        let sender = config.access_log_file.as_ref().and_then(|path| {
+            match RotatingFile::open(
+                path,
+                config.access_log_max_size,
+                config.access_log_max_files,
+            ) {
+                Ok(file) => {
+                    let (sender, receiver) = mpsc::channel();
+                    thread::spawn(move || write_lines(file, receiver));
+                    Some(sender)
+                }
+                Err(e) => {
+                    warn!("Failed to open access log file {}: {}", path, e);
+                    None
+                }
+            }
+        });
+
+        AccessLog {
+            format: config.access_log_format,
+            trust_forwarded_for: config.trust_forwarded_for,
+            trusted_proxies: config.trusted_proxies.clone(),
+            sender,
+            sample_rate: config.log_sample_rate,
+            slow_threshold: config.log_slow_request_threshold,
+            rng: Mutex::new(rng),
+        }
+    }
+
+    /// Whether a request is logged under the sample rate.
+    fn sampled(&self, status: StatusCode, duration: Duration) -> bool {
+        self.sample_rate >= 1.0
+            || status.is_client_error()
+            || status.is_server_error()
+            || duration >= self.slow_threshold
+            || self.rng.lock().unwrap().f64() < self.sample_rate
+    }
+
+    /// Records a request, `duration` being the time until the response
+    /// headers were ready.
+    pub fn record(&self, req: &HttpRequest, status: StatusCode, duration: Duration) {
+        if !self.sampled(status, duration) {
+            return;
+        }
+        let time = humantime::format_rfc3339_millis(SystemTime::now()).to_string();
+        let client = client_ip::client_ip(req, self.trust_forwarded_for, &self.trusted_proxies)
+            .map(|address| address.to_string())
+            .unwrap_or_else(|| "-".to_string());
+        let method = req.method().as_str();
+        let path = req.uri().to_string();
+        let version = format!("{:?}", req.version());
+        let duration_ms = duration.as_millis();
+
+        let line = match self.format {
+            AccessLogFormat::Plain => format!(
+                "{} {} \"{} {} {}\" {} {}ms",
+                time,
+                client,
+                method,
+                path,
+                version,
+                status.as_u16(),
+                duration_ms
+            ),
+            AccessLogFormat::Json => serde_json::json!({
+                "time": time,
+                "client": client,
+                "method": method,
+                "path": path,
+                "version": version,
+                "status": status.as_u16(),
+                "duration_ms": duration_ms,
+            })
+            .to_string(),
+        };
+
+        match &self.sender {
+            Some(sender) => {
+                let _ = sender.send(line);
+            }
+            None => info!(target: "access", "{}", line),
+        }
+    }
+}
+
+/// Writes lines as they arrive, flushing whenever no more are queued.
+fn write_lines(mut file: RotatingFile, receiver: Receiver<String>) {
+    while let Ok(line) = receiver.recv() {
+        let mut result = file.write_line(&line);
+        while let (Ok(()), Ok(line)) = (&result, receiver.try_recv()) {
+            result = file.write_line(&line);
+        }
+        if let Err(e) = result.and_then(|_| file.flush()) {
+            warn!("Failed to write access log: {}", e);
+        }
+    }
+}
+
+/// A log file that is rotated to `<path>.1`, `<path>.2`, ... once it
+/// exceeds its size limit.
+struct RotatingFile {
+    path: String,
+    writer: BufWriter<File>,
+    size: u64,
+    /// Size in bytes after which the file is rotated, never if 0.
+    max_size: u64,
+    /// Number of rotated files that are kept.
+    max_files: usize,
+}
+
+impl RotatingFile {
+    fn open(path: &str, max_size: u64, max_files: usize) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let size = file.metadata()?.len();
+        Ok(RotatingFile {
+            path: path.to_string(),
+            writer: BufWriter::new(file),
+            size,
+            max_size,
+            max_files,
+        })
+    }
+
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        let length = line.len() as u64 + 1;
+        if self.max_size > 0 && self.size > 0 && self.size + length > self.max_size {
+            self.rotate()?;
+        }
+
+        self.writer.write_all(line.as_bytes())?;
+        self.writer.write_all(b"\n")?;
+        self.size += length;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.writer.flush()?;
+
+        if self.max_files == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            let rotated = |index: usize| format!("{}.{}", self.path, index);
+            let _ = fs::remove_file(rotated(self.max_files));
+            for index in (1..self.max_files).rev() {
+                let _ = fs::rename(rotated(index), rotated(index + 1));
+            }
+            fs::rename(&self.path, rotated(1))?;
+        }
+
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        self.writer = BufWriter::new(file);
+        self.size = 0;
+        Ok(())
+    }
+}
@@ -0,0 +1,251 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use actix_web::dev::ServerHandle;
+use actix_web::http::header::AUTHORIZATION;
+use actix_web::{web, HttpRequest, HttpResponse};
+use log::{info, warn};
+use serde::Deserialize;
+
+use crate::cache::ResponseCache;
+use crate::client::UpstreamClient;
+use crate::clock::Clock;
+use crate::config::Config;
+use crate::upstream_error;
+
+/// Tracks whether the instance is draining ahead of a shutdown.
+#[derive(Default)]
+pub struct DrainState {
+    draining: AtomicBool,
+    server: OnceLock<ServerHandle>,
+}
+
+impl DrainState {
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::SeqCst)
+    }
+
+    /// Registers the server that is stopped once draining is complete.
+    pub fn set_server(&self, server: ServerHandle) {
+        let _ = self.server.set(server);
+    }
+}
+
+/// Checks the bearer token of an admin request against `ADMIN_TOKEN`.
+///
+/// Admin endpoints are disabled and answer with 404 if no token is set.
+pub fn authorize(req: &HttpRequest, config: &Config) -> Result<(), HttpResponse> {
+    let token = match &config.admin_token {
+        Some(token) => token,
+        None => return Err(HttpResponse::NotFound().finish()),
+    };
+
+    let provided = req
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|header| header.to_str().ok())
+        .and_then(|header| header.strip_prefix("Bearer "));
+    if !provided.is_some_and(|provided| constant_time_eq(provided.as_bytes(), token.as_bytes())) {
+        warn!("Unauthorized admin request to {}", req.path());
+        return Err(HttpResponse::Unauthorized().finish());
+    }
+
+    Ok(())
+}
+
+/// Compares secrets in time that depends on their length only, so that
+/// timing doesn't tell how much of a guessed token is right.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+/// The result of the last `CANARY_URL` request, which is reused for
+/// `CANARY_CACHE_SECONDS` so that frequent probes don't hammer the canary.
+/// Only one probe at a time requests the canary, the others meanwhile get
+/// the last result.
+pub struct CanaryCheck {
+    last: Mutex<Option<(Instant, bool)>>,
+    refresh: tokio::sync::Mutex<()>,
+    clock: Arc<dyn Clock>,
+}
+
+impl CanaryCheck {
+    pub fn new(clock: Arc<dyn Clock>) -> Self {
+        CanaryCheck {
+            last: Mutex::new(None),
+            refresh: tokio::sync::Mutex::new(()),
+            clock,
+        }
+    }
+
+    /// Whether the canary answered a HEAD request with a success status
+    /// within `CANARY_TIMEOUT_SECONDS`.
+    async fn healthy(&self, url: &str, config: &Config, client: &UpstreamClient) -> bool {
+        let last = *self.last.lock().unwrap();
+        if let Some((checked, healthy)) = last {
+            if self.clock.now().duration_since(checked) < config.canary_cache_ttl {
+                return healthy;
+            }
+        }
+
+        let _refresh = match (self.refresh.try_lock(), last) {
+            (Ok(refresh), _) => refresh,
+            (Err(_), Some((_, healthy))) => return healthy,
+            // Without a result yet, wait for the running request to get one
+            (Err(_), None) => {
+                let refresh = self.refresh.lock().await;
+                if let Some((_, healthy)) = *self.last.lock().unwrap() {
+                    return healthy;
+                }
+                refresh
+            }
+        };
+
+        let timeout = config.canary_timeout;
+        let result = client
+            .send(url.starts_with("https://"), |client| {
+                client.head(url).timeout(timeout)
+            })
+            .await;
+        let healthy = match result {
+            Ok(response) if response.status().is_success() => true,
+            Ok(response) => {
+                warn!("Canary {} responded with status {}", url, response.status());
+                false
+            }
+            Err(e) => {
+                warn!(
+                    "Canary {} can't be reached: {}",
+                    url,
+                    upstream_error::describe(&e)
+                );
+                false
+            }
+        };
+        *self.last.lock().unwrap() = Some((self.clock.now(), healthy));
+        healthy
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ReadyzQuery {
+    deep: Option<String>,
+}
+
+/// Readiness probe, fails once the instance is draining. With `?deep=1`
+/// it also fails while `CANARY_URL` can't be reached.
+pub async fn readyz(
+    config: web::Data<Config>,
+    drain: web::Data<DrainState>,
+    canary: web::Data<CanaryCheck>,
+    client: web::Data<UpstreamClient>,
+    query: web::Query<ReadyzQuery>,
+) -> HttpResponse {
+    if drain.is_draining() {
+        return HttpResponse::ServiceUnavailable().body("draining");
+    }
+
+    let deep = matches!(query.deep.as_deref(), Some("1" | "true"));
+    if let (true, Some(url)) = (deep, &config.canary_url) {
+        if !canary.healthy(url, &config, &client).await {
+            return HttpResponse::ServiceUnavailable().body("canary unreachable");
+        }
+    }
+    HttpResponse::Ok().body("ready")
+}
+
+/// Marks the instance as draining and stops the server after
+/// `SHUTDOWN_TIMEOUT_SECONDS`, letting in-flight requests finish.
+pub async fn drain(
+    req: HttpRequest,
+    config: web::Data<Config>,
+    drain: web::Data<DrainState>,
+) -> HttpResponse {
+    if let Err(response) = authorize(&req, &config) {
+        return response;
+    }
+
+    if drain.draining.swap(true, Ordering::SeqCst) {
+        return HttpResponse::Accepted().body("already draining");
+    }
+
+    info!(
+        "Draining, shutting down in {} seconds",
+        config.shutdown_timeout
+    );
+    if let Some(server) = drain.server.get().cloned() {
+        let timeout = Duration::from_secs(config.shutdown_timeout);
+        actix_web::rt::spawn(async move {
+            actix_web::rt::time::sleep(timeout).await;
+            server.stop(true).await;
+        });
+    }
+
+    HttpResponse::Accepted().body("draining")
+}
+
+/// Shows the configuration in effect, with secrets redacted.
+pub async fn show_config(req: HttpRequest, config: web::Data<Config>) -> HttpResponse {
+    if let Err(response) = authorize(&req, &config) {
+        return response;
+    }
+
+    HttpResponse::Ok().json(&**config)
+}
+
+/// Lists the cached responses with their body size and remaining lifetime.
+pub async fn cache_entries(
+    req: HttpRequest,
+    config: web::Data<Config>,
+    cache: web::Data<ResponseCache>,
+) -> HttpResponse {
+    if let Err(response) = authorize(&req, &config) {
+        return response;
+    }
+
+    let entries: Vec<_> = cache
+        .entries()
+        .into_iter()
+        .map(|entry| {
+            serde_json::json!({
+                "key": entry.key,
+                "size": entry.size,
+                "ttl_seconds": entry.ttl.as_secs(),
+            })
+        })
+        .collect();
+    HttpResponse::Ok().json(entries)
+}
+
+#[derive(Deserialize)]
+pub struct PurgeQuery {
+    url: Option<String>,
+}
+
+/// Evicts the cached response for the `url` query parameter, or every
+/// cached response without it.
+pub async fn purge_cache(
+    req: HttpRequest,
+    config: web::Data<Config>,
+    cache: web::Data<ResponseCache>,
+    query: web::Query<PurgeQuery>,
+) -> HttpResponse {
+    if let Err(response) = authorize(&req, &config) {
+        return response;
+    }
+
+    let purged = match &query.url {
+        Some(url) => {
+            // Keys are normalized upstream URLs
+            let key = reqwest::Url::parse(url)
+                .map(|url| url.to_string())
+                .unwrap_or_else(|_| url.clone());
+            usize::from(cache.remove(&key))
+        }
+        None => cache.clear(),
+    };
+
+    info!("Purged {} cached responses", purged);
+    HttpResponse::Ok().json(serde_json::json!({ "purged": purged }))
+}
@@ -0,0 +1,245 @@
+use std::collections::{BTreeMap, HashMap};
+use std::time::Instant;
+
+use actix_web::body;
+use actix_web::http::header::{HeaderMap, HeaderName, HeaderValue};
+use actix_web::http::{Method, StatusCode};
+use actix_web::{web, HttpRequest, HttpResponse, Result};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use futures_util::StreamExt;
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::access_log::AccessLog;
+use crate::admin::DrainState;
+use crate::cache::ResponseCache;
+use crate::capture::DebugCapture;
+use crate::client::UpstreamClient;
+use crate::config::Config;
+use crate::cors;
+use crate::metrics::Metrics;
+use crate::proxy::{self, ProxyRequest};
+use crate::rate_limit::{ConcurrencyLimiter, RateLimiter};
+
+/// A request of a batch.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BatchEntry {
+    #[serde(default = "default_method")]
+    method: String,
+    /// A target URL, or a path under a configured route
+    url: String,
+    #[serde(default)]
+    headers: HashMap<String, String>,
+}
+
+fn default_method() -> String {
+    "GET".to_string()
+}
+
+/// The response to a request of a batch. Repeated headers are joined
+/// with commas.
+#[derive(Serialize)]
+struct BatchResult {
+    status: u16,
+    headers: BTreeMap<String, String>,
+    body: String,
+    /// `base64` for bodies that aren't valid UTF-8, which are encoded.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    encoding: Option<&'static str>,
+}
+
+impl BatchResult {
+    fn error(status: StatusCode, message: &str) -> Self {
+        BatchResult {
+            status: status.as_u16(),
+            headers: BTreeMap::new(),
+            body: message.to_string(),
+            encoding: None,
+        }
+    }
+}
+
+/// The proxy request for a batch entry, or the error it is answered with.
+fn proxy_request(config: &Config, entry: BatchEntry) -> Result<ProxyRequest, BatchResult> {
+    let method = Method::from_bytes(entry.method.trim().to_ascii_uppercase().as_bytes())
+        .map_err(|_| BatchResult::error(StatusCode::BAD_REQUEST, "Invalid method"))?;
+    if !config.allowed_methods.contains(&method) {
+        return Err(BatchResult::error(
+            StatusCode::METHOD_NOT_ALLOWED,
+            &format!("Method {} is not allowed", method),
+        ));
+    }
+
+    let mut headers = HeaderMap::new();
+    for (name, value) in entry.headers {
+        match (
+            HeaderName::from_bytes(name.as_bytes()),
+            HeaderValue::from_str(&value),
+        ) {
+            (Ok(name), Ok(value)) => headers.append(name, value),
+            _ => {
+                return Err(BatchResult::error(
+                    StatusCode::BAD_REQUEST,
+                    &format!("Invalid header {}", name),
+                ))
+            }
+        }
+    }
+
+    // Entries name their target like the path of a proxy request does
+    let (path, query) = entry
+        .url
+        .split_once('?')
+        .unwrap_or((entry.url.as_str(), ""));
+    let path = format!("/{}", path.trim_start_matches('/'));
+    let target = Some(path[1..].to_string()).filter(|target| !target.is_empty());
+    Ok(ProxyRequest {
+        method,
+        path,
+        target,
+        query: query.to_string(),
+        headers,
+    })
+}
+
+/// Reads a proxied response into a batch result, failing once its body
+/// exceeds `limit`.
+async fn batch_result(response: HttpResponse, limit: usize) -> BatchResult {
+    let status = response.status();
+    let mut headers = BTreeMap::<String, String>::new();
+    for (name, value) in response.headers() {
+        let value = String::from_utf8_lossy(value.as_bytes());
+        headers
+            .entry(name.to_string())
+            .and_modify(|values| {
+                values.push_str(", ");
+                values.push_str(&value);
+            })
+            .or_insert_with(|| value.into_owned());
+    }
+
+    match body::to_bytes_limited(response.into_body(), limit).await {
+        Ok(Ok(body)) => {
+            let (body, encoding) = match String::from_utf8(body.to_vec()) {
+                Ok(text) => (text, None),
+                Err(_) => (BASE64.encode(&body), Some("base64")),
+            };
+            BatchResult {
+                status: status.as_u16(),
+                headers,
+                body,
+                encoding,
+            }
+        }
+        Ok(Err(e)) => {
+            warn!("Failed to read response of batch entry: {}", e);
+            BatchResult::error(StatusCode::BAD_GATEWAY, "Failed to read upstream response")
+        }
+        Err(_) => BatchResult::error(
+            StatusCode::BAD_GATEWAY,
+            "Upstream response too large for a batch",
+        ),
+    }
+}
+
+/// Answers preflight requests for `/batch`, whose requests carry JSON.
+pub async fn preflight(req: HttpRequest, config: web::Data<Config>) -> HttpResponse {
+    let mut response = HttpResponse::NoContent();
+    cors::add_preflight_headers(&mut response, &req, &config.cors_for(None));
+    response.finish()
+}
+
+/// Forwards a JSON array of requests, each of them like a request of its
+/// own from the same client, and answers with their responses in order.
+///
+/// Up to `BATCH_CONCURRENCY` entries are forwarded at the same time, and
+/// each one is subject to the checks and limits of a proxy request.
+#[allow(clippy::too_many_arguments)]
+pub async fn batch(
+    req: HttpRequest,
+    payload: web::Payload,
+    config: web::Data<Config>,
+    metrics: web::Data<Metrics>,
+    drain: web::Data<DrainState>,
+    rate_limiter: web::Data<RateLimiter>,
+    concurrency: web::Data<ConcurrencyLimiter>,
+    client: web::Data<UpstreamClient>,
+    cache: web::Data<ResponseCache>,
+    access_log: web::Data<AccessLog>,
+    capture: web::Data<DebugCapture>,
+) -> Result<HttpResponse> {
+    let started = Instant::now();
+    let body = match proxy::read_body(&req, payload, config.max_body_size).await {
+        Ok(body) => body,
+        Err(e) => {
+            let response = proxy::payload_error(&metrics, e);
+            access_log.record(&req, response.status(), started.elapsed());
+            return Ok(response);
+        }
+    };
+    let response = match serde_json::from_slice::<Vec<BatchEntry>>(&body) {
+        Err(e) => {
+            warn!("Bad request: invalid batch: {}", e);
+            HttpResponse::BadRequest().body(format!("Invalid batch: {}", e))
+        }
+        Ok(entries) if entries.len() > config.max_batch_size => {
+            warn!(
+                "Bad request: batch of {} requests exceeds limit of {}",
+                entries.len(),
+                config.max_batch_size
+            );
+            HttpResponse::PayloadTooLarge().body(format!(
+                "Batches are limited to {} requests",
+                config.max_batch_size
+            ))
+        }
+        Ok(entries) => {
+            let results = futures_util::stream::iter(entries)
+                .map(|entry| {
+                    let req = &req;
+                    let config = config.clone();
+                    let metrics = metrics.clone();
+                    let drain = drain.clone();
+                    let rate_limiter = rate_limiter.clone();
+                    let concurrency = concurrency.clone();
+                    let client = client.clone();
+                    let cache = cache.clone();
+                    let capture = capture.clone();
+                    async move {
+                        let incoming = match proxy_request(&config, entry) {
+                            Ok(incoming) => incoming,
+                            Err(result) => return result,
+                        };
+                        let limit = config.max_batch_response_size;
+                        let response = proxy::forward(
+                            req,
+                            incoming,
+                            web::Bytes::new(),
+                            config,
+                            metrics,
+                            drain,
+                            rate_limiter,
+                            concurrency,
+                            client,
+                            cache,
+                            capture,
+                        )
+                        .await
+                        .unwrap_or_else(|e| e.error_response());
+                        batch_result(response, limit).await
+                    }
+                })
+                .buffered(config.batch_concurrency)
+                .collect::<Vec<_>>()
+                .await;
+
+            let mut response = HttpResponse::Ok();
+            cors::add_cors_headers(&mut response, &req, &config.cors_for(None));
+            response.json(results)
+        }
+    };
+    access_log.record(&req, response.status(), started.elapsed());
+    Ok(response)
+}
@@ -0,0 +1,334 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use actix_web::web::Bytes;
+use reqwest::header::{HeaderMap, AGE, CACHE_CONTROL, SET_COOKIE, VARY};
+use tokio::sync::{OwnedRwLockWriteGuard, RwLock};
+
+use crate::clock::Clock;
+
+/// An upstream response kept in the cache.
+#[derive(Clone)]
+pub struct CachedResponse {
+    pub status: u16,
+    pub headers: HeaderMap,
+    pub body: Bytes,
+    /// Age of the response as of the lookup, the upstream `Age` plus the
+    /// time since it was stored.
+    pub age: Duration,
+    /// The upstream `Age` of the response when it was stored.
+    initial_age: Duration,
+    stored: Instant,
+    expires: Instant,
+    /// End of the window in which the stale response may still be served
+    /// if the upstream fails.
+    usable_until: Instant,
+}
+
+impl CachedResponse {
+    /// Time until the response is stale.
+    fn ttl(&self, now: Instant) -> Duration {
+        self.expires.saturating_duration_since(now)
+    }
+
+    fn is_fresh(&self, now: Instant) -> bool {
+        now < self.expires
+    }
+
+    fn is_usable(&self, now: Instant) -> bool {
+        now < self.usable_until
+    }
+}
+
+/// Summary of a cache entry for the admin endpoint.
+pub struct CacheEntryInfo {
+    pub key: String,
+    pub size: usize,
+    pub ttl: Duration,
+}
+
+/// Locks held by the requests that are fetching a response others wait
+/// for, keyed like the cache.
+type InFlight = Arc<Mutex<HashMap<String, Arc<RwLock<()>>>>>;
+
+/// A request fetching the response for a key while other requests for it
+/// wait. They continue once it is dropped, which should happen after the
+/// response has been stored.
+pub struct Flight {
+    key: String,
+    in_flight: InFlight,
+    _lock: OwnedRwLockWriteGuard<()>,
+}
+
+impl Drop for Flight {
+    fn drop(&mut self) {
+        self.in_flight.lock().unwrap().remove(&self.key);
+    }
+}
+
+/// The cached responses, with the total size of their bodies.
+#[derive(Default)]
+struct Entries {
+    responses: HashMap<String, CachedResponse>,
+    bytes: usize,
+}
+
+impl Entries {
+    fn get(&self, key: &str) -> Option<&CachedResponse> {
+        self.responses.get(key)
+    }
+
+    fn insert(&mut self, key: String, response: CachedResponse) {
+        self.bytes += response.body.len();
+        if let Some(replaced) = self.responses.insert(key, response) {
+            self.bytes -= replaced.body.len();
+        }
+    }
+
+    fn remove(&mut self, key: &str) -> Option<CachedResponse> {
+        let removed = self.responses.remove(key)?;
+        self.bytes -= removed.body.len();
+        Some(removed)
+    }
+
+    fn retain(&mut self, mut keep: impl FnMut(&CachedResponse) -> bool) {
+        let bytes = &mut self.bytes;
+        self.responses.retain(|_, response| {
+            let kept = keep(response);
+            if !kept {
+                *bytes -= response.body.len();
+            }
+            kept
+        });
+    }
+
+    fn clear(&mut self) -> usize {
+        let count = self.responses.len();
+        self.responses.clear();
+        self.bytes = 0;
+        count
+    }
+}
+
+/// In-memory cache of upstream GET responses, keyed by upstream URL.
+pub struct ResponseCache {
+    entries: Mutex<Entries>,
+    in_flight: InFlight,
+    max_entries: usize,
+    /// Maximum total size of the cached bodies, unlimited if 0.
+    max_bytes: usize,
+    stale_if_error: Duration,
+    clock: Arc<dyn Clock>,
+}
+
+impl ResponseCache {
+    /// Creates a cache holding up to `max_entries` responses, disabled if 0,
+    /// whose bodies add up to at most `max_bytes`, unlimited if 0. Stale
+    /// responses are kept for `stale_if_error` to be served when the
+    /// upstream fails.
+    pub fn new(
+        max_entries: usize,
+        max_bytes: usize,
+        stale_if_error: Duration,
+        clock: Arc<dyn Clock>,
+    ) -> Self {
+        ResponseCache {
+            entries: Mutex::default(),
+            in_flight: Arc::default(),
+            max_entries,
+            max_bytes,
+            stale_if_error,
+            clock,
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.max_entries > 0
+    }
+
+    /// Returns a fresh copy of the response for `key`.
+    pub fn get(&self, key: &str) -> Option<CachedResponse> {
+        self.lookup(key, CachedResponse::is_fresh)
+    }
+
+    /// Returns the response for `key` even if it is stale, as long as it is
+    /// within the stale-if-error window.
+    pub fn get_stale(&self, key: &str) -> Option<CachedResponse> {
+        self.lookup(key, CachedResponse::is_usable)
+    }
+
+    fn lookup(
+        &self,
+        key: &str,
+        valid: fn(&CachedResponse, Instant) -> bool,
+    ) -> Option<CachedResponse> {
+        let now = self.clock.now();
+        let mut entries = self.entries.lock().unwrap();
+        match entries.get(key) {
+            Some(entry) if valid(entry, now) => Some(CachedResponse {
+                age: entry.initial_age + now.saturating_duration_since(entry.stored),
+                ..entry.clone()
+            }),
+            Some(entry) => {
+                if !entry.is_usable(now) {
+                    entries.remove(key);
+                }
+                None
+            }
+            None => None,
+        }
+    }
+
+    /// Coalesces concurrent requests for `key`. The first one gets the
+    /// `Flight` that makes the others wait until it is dropped, the others
+    /// get `None` once it is and may look the response up.
+    pub async fn coalesce(&self, key: &str) -> Option<Flight> {
+        let lock = {
+            let mut in_flight = self.in_flight.lock().unwrap();
+            match in_flight.get(key) {
+                Some(lock) => lock.clone(),
+                None => {
+                    let lock = Arc::new(RwLock::new(()));
+                    let guard = lock.clone().try_write_owned().ok()?;
+                    in_flight.insert(key.to_string(), lock);
+                    return Some(Flight {
+                        key: key.to_string(),
+                        in_flight: self.in_flight.clone(),
+                        _lock: guard,
+                    });
+                }
+            }
+        };
+        drop(lock.read().await);
+        None
+    }
+
+    /// Stores a response for `ttl`. When the cache is full, entries past
+    /// their stale window are dropped first and then the entries that would
+    /// expire soonest. The size of single bodies is limited by the caller,
+    /// see `CACHE_MAX_BODY_BYTES`.
+    pub fn insert(&self, key: String, status: u16, headers: HeaderMap, body: Bytes, ttl: Duration) {
+        if !self.enabled() {
+            return;
+        }
+
+        let now = self.clock.now();
+        let mut entries = self.entries.lock().unwrap();
+        entries.remove(&key);
+        let full = |entries: &Entries| {
+            entries.responses.len() >= self.max_entries
+                || (self.max_bytes > 0 && entries.bytes + body.len() > self.max_bytes)
+        };
+        if full(&entries) {
+            entries.retain(|entry| entry.is_usable(now));
+        }
+        while full(&entries) {
+            match entries
+                .responses
+                .iter()
+                .min_by_key(|(_, entry)| entry.expires)
+                .map(|(key, _)| key.clone())
+            {
+                Some(soonest) => entries.remove(&soonest),
+                None => break,
+            };
+        }
+
+        let initial_age = age(&headers);
+        entries.insert(
+            key,
+            CachedResponse {
+                status,
+                headers,
+                body,
+                age: initial_age,
+                initial_age,
+                stored: now,
+                expires: now + ttl,
+                usable_until: now + ttl + self.stale_if_error,
+            },
+        );
+    }
+
+    /// The fresh entries, sorted by key.
+    pub fn entries(&self) -> Vec<CacheEntryInfo> {
+        let now = self.clock.now();
+        let entries = self.entries.lock().unwrap();
+        let mut infos: Vec<_> = entries
+            .responses
+            .iter()
+            .filter(|(_, entry)| entry.is_fresh(now))
+            .map(|(key, entry)| CacheEntryInfo {
+                key: key.clone(),
+                size: entry.body.len(),
+                ttl: entry.ttl(now),
+            })
+            .collect();
+        infos.sort_by(|a, b| a.key.cmp(&b.key));
+        infos
+    }
+
+    /// Evicts the entry for `key`, returning whether there was one.
+    pub fn remove(&self, key: &str) -> bool {
+        self.entries.lock().unwrap().remove(key).is_some()
+    }
+
+    /// Evicts all entries, returning how many there were.
+    pub fn clear(&self) -> usize {
+        self.entries.lock().unwrap().clear()
+    }
+}
+
+/// The `Age` of a response, the time it has already spent in caches
+/// before reaching the proxy.
+pub fn age(headers: &HeaderMap) -> Duration {
+    headers
+        .get(AGE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse().ok())
+        .map(Duration::from_secs)
+        .unwrap_or_default()
+}
+
+/// How long a response may be kept in a shared cache according to its
+/// headers, falling back to `default_ttl` without an explicit lifetime.
+/// The time the response has already spent in caches upstream counts
+/// against it.
+///
+/// Responses that vary with request headers or set cookies are never
+/// cached, as the cache would serve them to every client.
+pub fn cache_ttl(headers: &HeaderMap, default_ttl: Duration) -> Option<Duration> {
+    if headers.contains_key(SET_COOKIE) || headers.contains_key(VARY) {
+        return None;
+    }
+
+    let directives: Vec<String> = headers
+        .get_all(CACHE_CONTROL)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|directive| directive.trim().to_ascii_lowercase())
+        .collect();
+    if directives
+        .iter()
+        .any(|directive| ["no-store", "no-cache", "private"].contains(&directive.as_str()))
+    {
+        return None;
+    }
+
+    let max_age = |name: &str| {
+        directives.iter().find_map(|directive| {
+            directive
+                .strip_prefix(name)
+                .and_then(|value| value.strip_prefix('='))
+                .and_then(|value| value.trim_matches('"').parse().ok())
+                .map(Duration::from_secs)
+        })
+    };
+    let ttl = max_age("s-maxage")
+        .or_else(|| max_age("max-age"))
+        .unwrap_or(default_ttl)
+        .saturating_sub(age(headers));
+    (!ttl.is_zero()).then_some(ttl)
+}
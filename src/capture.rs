@@ -0,0 +1,196 @@
+use std::fs;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use log::{info, warn};
+use reqwest::header::HeaderMap;
+use reqwest::{Method, Url};
+
+use crate::config::Config;
+use crate::config_file::glob_match;
+use crate::stream::TeeCallback;
+
+/// Writes copies of upstream requests and responses to files in
+/// `DEBUG_CAPTURE_DIR`, for diagnosing integrations.
+///
+/// Each exchange gets a `<millis>-<sequence>-request.txt` and a matching
+/// `-response.txt` file holding the start line, the headers and the body,
+/// which is cut off after `DEBUG_CAPTURE_MAX_BYTES`. The files are written
+/// on a background thread, so requests never wait for the disk.
+pub struct DebugCapture {
+    dir: Option<(PathBuf, Sender<CaptureFile>)>,
+    pattern: String,
+    max_size: usize,
+    redact_headers: Vec<String>,
+    sequence: AtomicU64,
+}
+
+/// A captured exchange whose request has been written.
+pub struct CapturedExchange {
+    dir: PathBuf,
+    sender: Sender<CaptureFile>,
+    name: String,
+    max_size: usize,
+    redact_headers: Vec<String>,
+}
+
+impl DebugCapture {
+    pub fn new(config: &Config) -> Self {
+        let dir = config
+            .debug_capture_dir
+            .as_ref()
+            .and_then(|dir| match fs::create_dir_all(dir) {
+                Ok(()) => {
+                    warn!(
+                        "Capturing requests to paths matching {} in {}",
+                        config.debug_capture_pattern, dir
+                    );
+                    let (sender, receiver) = mpsc::channel();
+                    thread::spawn(move || write_files(receiver));
+                    Some((PathBuf::from(dir), sender))
+                }
+                Err(e) => {
+                    warn!("Failed to create capture directory {}: {}", dir, e);
+                    None
+                }
+            });
+
+        DebugCapture {
+            dir,
+            pattern: config.debug_capture_pattern.clone(),
+            max_size: config.debug_capture_max_size,
+            redact_headers: config.debug_capture_redact_headers.clone(),
+            sequence: AtomicU64::new(0),
+        }
+    }
+
+    /// Writes the request of an exchange with the upstream if its path
+    /// matches the capture pattern.
+    pub fn request(
+        &self,
+        method: &Method,
+        url: &Url,
+        headers: &HeaderMap,
+        body: &[u8],
+    ) -> Option<CapturedExchange> {
+        let (dir, sender) = self.dir.as_ref()?;
+        if !glob_match(self.pattern.as_bytes(), url.path().as_bytes()) {
+            return None;
+        }
+
+        let millis = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        let sequence = self.sequence.fetch_add(1, Ordering::Relaxed);
+        let exchange = CapturedExchange {
+            dir: dir.clone(),
+            sender: sender.clone(),
+            name: format!("{}-{}", millis, sequence),
+            max_size: self.max_size,
+            redact_headers: self.redact_headers.clone(),
+        };
+        exchange.write(
+            "request",
+            &format!("{} {}", method, url),
+            headers,
+            body,
+            false,
+        );
+        Some(exchange)
+    }
+}
+
+impl CapturedExchange {
+    /// The callback that writes the response once its body has passed
+    /// through a `TeeStream`.
+    pub fn response(self, status: u16, headers: &HeaderMap) -> TeeCallback {
+        let headers = headers.clone();
+        Box::new(move |body, incomplete| {
+            self.write("response", &status.to_string(), &headers, &body, incomplete)
+        })
+    }
+
+    /// Body size limit for the `TeeStream` of the response.
+    pub fn max_size(&self) -> usize {
+        self.max_size
+    }
+
+    fn write(&self, kind: &str, start: &str, headers: &HeaderMap, body: &[u8], incomplete: bool) {
+        let incomplete = incomplete || body.len() > self.max_size;
+        let body = &body[..body.len().min(self.max_size)];
+        // Writing to memory doesn't fail
+        if let Ok(contents) = self.contents(start, headers, body, incomplete) {
+            let path = self.dir.join(format!("{}-{}.txt", self.name, kind));
+            let _ = self.sender.send(CaptureFile { path, contents });
+        }
+    }
+
+    fn contents(
+        &self,
+        start: &str,
+        headers: &HeaderMap,
+        body: &[u8],
+        incomplete: bool,
+    ) -> io::Result<Vec<u8>> {
+        let mut contents = Vec::new();
+        writeln!(contents, "{}", start)?;
+        for (name, value) in headers {
+            let redacted = self
+                .redact_headers
+                .iter()
+                .any(|redact| redact.eq_ignore_ascii_case(name.as_str()));
+            if redacted {
+                writeln!(contents, "{}: [redacted]", name)?;
+            } else {
+                writeln!(
+                    contents,
+                    "{}: {}",
+                    name,
+                    String::from_utf8_lossy(value.as_bytes())
+                )?;
+            }
+        }
+        if incomplete {
+            writeln!(
+                contents,
+                "# body incomplete or cut off after {} bytes",
+                self.max_size
+            )?;
+        }
+        writeln!(contents)?;
+        contents.extend_from_slice(body);
+        Ok(contents)
+    }
+}
+
+/// A capture file waiting to be written.
+struct CaptureFile {
+    path: PathBuf,
+    contents: Vec<u8>,
+}
+
+/// Writes capture files as they arrive. Each is written under a temporary
+/// name first, so that readers never see a partial file.
+fn write_files(receiver: Receiver<CaptureFile>) {
+    while let Ok(file) = receiver.recv() {
+        match write_file(&file) {
+            Ok(()) => info!("Captured {}", file.path.display()),
+            Err(e) => warn!(
+                "Failed to write capture file {}: {}",
+                file.path.display(),
+                e
+            ),
+        }
+    }
+}
+
+fn write_file(file: &CaptureFile) -> io::Result<()> {
+    let partial = file.path.with_extension("partial");
+    fs::write(&partial, &file.contents)?;
+    fs::rename(&partial, &file.path)
+}
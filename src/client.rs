@@ -0,0 +1,735 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::future::Future;
+use std::net::{IpAddr, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use actix_web::rt::{task, time};
+use log::warn;
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use reqwest::header::{
+    HeaderMap, HeaderValue, AUTHORIZATION, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE, COOKIE,
+    HOST, LOCATION, PROXY_AUTHORIZATION,
+};
+use reqwest::redirect::Policy;
+use reqwest::tls::Version;
+use reqwest::{Body, Client, ClientBuilder, Method, RequestBuilder, Response, StatusCode, Url};
+use serde::Serialize;
+
+use crate::clock::{Clock, SystemClock};
+use crate::config::{Config, PROXIED_SCHEMES};
+use crate::config_file::find_host;
+use crate::metrics::{Metrics, RedirectMetrics};
+use crate::upstream_error;
+
+/// HTTP version used towards upstreams.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UpstreamHttpVersion {
+    /// HTTP/1.1, or HTTP/2 when the upstream negotiates it.
+    #[default]
+    Auto,
+    /// HTTP/3 for `https://` upstreams, falling back to `Auto` if the
+    /// upstream can't be reached over QUIC. Requires the `h3` feature.
+    Http3,
+}
+
+/// Lowest TLS version accepted from `https://` upstreams.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub enum MinTlsVersion {
+    #[serde(rename = "1.2")]
+    Tls12,
+    #[serde(rename = "1.3")]
+    Tls13,
+}
+
+impl MinTlsVersion {
+    pub fn parse(version: &str) -> Option<Self> {
+        match version.trim() {
+            "1.2" => Some(MinTlsVersion::Tls12),
+            "1.3" => Some(MinTlsVersion::Tls13),
+            _ => None,
+        }
+    }
+
+    fn version(self) -> Version {
+        match self {
+            MinTlsVersion::Tls12 => Version::TLS_1_2,
+            MinTlsVersion::Tls13 => Version::TLS_1_3,
+        }
+    }
+}
+
+/// Why an upstream request failed.
+#[derive(Debug)]
+pub enum SendError {
+    Request(reqwest::Error),
+    /// The upstream didn't send the response headers within
+    /// `RESPONSE_HEADER_TIMEOUT_SECONDS`.
+    HeaderTimeout(Duration),
+    /// The attempts didn't get a response within
+    /// `TOTAL_REQUEST_DEADLINE_SECONDS`.
+    Deadline(Duration),
+}
+
+impl fmt::Display for SendError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SendError::Request(e) => e.fmt(f),
+            SendError::HeaderTimeout(timeout) => {
+                write!(f, "no response headers within {:?}", timeout)
+            }
+            SendError::Deadline(deadline) => {
+                write!(f, "no response within the deadline of {:?}", deadline)
+            }
+        }
+    }
+}
+
+impl Error for SendError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SendError::Request(e) => e.source(),
+            SendError::HeaderTimeout(_) | SendError::Deadline(_) => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for SendError {
+    fn from(error: reqwest::Error) -> Self {
+        SendError::Request(error)
+    }
+}
+
+/// Hosts whose retry budget is tracked at most. Hosts without an entry
+/// have a full budget, so evicting one only gives it its burst back.
+const MAX_RETRY_BUDGET_HOSTS: usize = 10_000;
+
+/// Hosts with an `sni_override` that a client is kept for at most, as
+/// wildcard patterns can match any number of hosts.
+const MAX_SNI_CLIENTS: usize = 256;
+
+/// Limits retries per upstream host to a share of its successful
+/// responses, so that retries can't pile onto an upstream that is failing.
+///
+/// Each host has a bucket of retries that starts full, gains `ratio` for
+/// every successful response and `min_per_second` over time, up to
+/// `burst`. Every retry takes one. Only buckets that aren't full are
+/// kept, up to `MAX_RETRY_BUDGET_HOSTS`.
+struct RetryBudget {
+    ratio: f64,
+    min_per_second: f64,
+    burst: f64,
+    hosts: Mutex<HashMap<String, (f64, Instant)>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl RetryBudget {
+    fn new(config: &Config, clock: Arc<dyn Clock>) -> Option<Self> {
+        (config.retry_budget_ratio > 0.0).then(|| RetryBudget {
+            ratio: config.retry_budget_ratio,
+            min_per_second: config.retry_budget_min_per_second.max(0.0),
+            burst: config.retry_budget_burst as f64,
+            hosts: Mutex::new(HashMap::new()),
+            clock,
+        })
+    }
+
+    /// The tokens of a bucket after refilling it up to `now`.
+    fn refilled(&self, (tokens, updated): (f64, Instant), now: Instant) -> f64 {
+        let elapsed = now.saturating_duration_since(updated).as_secs_f64();
+        (tokens + elapsed * self.min_per_second).min(self.burst)
+    }
+
+    /// Refills the bucket of `host` for the time since its last update and
+    /// applies `change` to it.
+    fn update<T>(&self, host: &str, change: impl FnOnce(&mut f64) -> T) -> T {
+        let now = self.clock.now();
+        let host = host.to_ascii_lowercase();
+        let mut hosts = self.hosts.lock().unwrap();
+        let mut tokens = match hosts.get(&host) {
+            Some(bucket) => self.refilled(*bucket, now),
+            None => self.burst,
+        };
+        let result = change(&mut tokens);
+
+        if tokens >= self.burst {
+            hosts.remove(&host);
+            return result;
+        }
+        if !hosts.contains_key(&host) && hosts.len() >= MAX_RETRY_BUDGET_HOSTS {
+            hosts.retain(|_, bucket| self.refilled(*bucket, now) < self.burst);
+            if hosts.len() >= MAX_RETRY_BUDGET_HOSTS {
+                let fullest = hosts
+                    .iter()
+                    .max_by(|(_, a), (_, b)| {
+                        self.refilled(**a, now).total_cmp(&self.refilled(**b, now))
+                    })
+                    .map(|(host, _)| host.clone());
+                if let Some(fullest) = fullest {
+                    hosts.remove(&fullest);
+                }
+            }
+        }
+        hosts.insert(host, (tokens, now));
+        result
+    }
+
+    fn record_success(&self, host: &str) {
+        self.update(host, |tokens| {
+            *tokens = (*tokens + self.ratio).min(self.burst)
+        });
+    }
+
+    /// Takes a retry from the budget of `host`, if there is one left.
+    fn try_retry(&self, host: &str) -> bool {
+        self.update(host, |tokens| {
+            if *tokens >= 1.0 {
+                *tokens -= 1.0;
+                true
+            } else {
+                false
+            }
+        })
+    }
+}
+
+/// The HTTP clients used for upstream requests, shared by all requests so
+/// that connections are reused.
+///
+/// Redirects are followed by `send` rather than by the clients, so that
+/// each target is validated before it is requested.
+///
+/// The HTTP/3 client binds its QUIC endpoint when created, which has to
+/// happen inside the async runtime.
+pub struct UpstreamClient {
+    client: Client,
+    #[cfg(feature = "h3")]
+    http3: Option<Client>,
+    max_redirects: usize,
+    /// Whether header names are sent in Title-Case.
+    title_case_headers: bool,
+    min_tls_version: Option<MinTlsVersion>,
+    fallback_to_http: bool,
+    response_header_timeout: Option<Duration>,
+    total_request_deadline: Option<Duration>,
+    retries: usize,
+    retry_budget: Option<RetryBudget>,
+    clock: Arc<dyn Clock>,
+    metrics: RedirectMetrics,
+    /// `sni_override` of the hosts in the config file, keyed like them.
+    sni_overrides: HashMap<String, Option<String>>,
+    /// Clients that connect to a host while presenting its SNI override,
+    /// created on first use and keyed by the host without brackets.
+    sni_clients: Mutex<HashMap<String, Client>>,
+}
+
+impl UpstreamClient {
+    pub fn new(config: &Config, metrics: &Metrics) -> Self {
+        Self::with_clock(config, metrics, Arc::new(SystemClock))
+    }
+
+    /// Creates the clients with retry budgets refilled and request
+    /// deadlines measured by `clock`.
+    pub fn with_clock(config: &Config, metrics: &Metrics, clock: Arc<dyn Clock>) -> Self {
+        #[cfg(not(feature = "h3"))]
+        if config.upstream_http_version == UpstreamHttpVersion::Http3 {
+            log::warn!("HTTP/3 requires the h3 feature, using HTTP/1.1 and HTTP/2");
+        }
+
+        UpstreamClient {
+            client: http1_builder(config.title_case_headers, config.min_tls_version)
+                .build()
+                .unwrap_or_default(),
+            #[cfg(feature = "h3")]
+            http3: (config.upstream_http_version == UpstreamHttpVersion::Http3)
+                .then(|| {
+                    Client::builder()
+                        .http3_prior_knowledge()
+                        .redirect(Policy::none())
+                        .build()
+                })
+                .and_then(|client| {
+                    client
+                        .map_err(|e| log::warn!("Failed to set up HTTP/3, not using it: {}", e))
+                        .ok()
+                }),
+            max_redirects: config.max_redirects,
+            title_case_headers: config.title_case_headers,
+            min_tls_version: config.min_tls_version,
+            fallback_to_http: config.fallback_to_http_on_tls_error,
+            response_header_timeout: config.response_header_timeout,
+            total_request_deadline: config.total_request_deadline,
+            retries: config.upstream_retries,
+            retry_budget: RetryBudget::new(config, clock.clone()),
+            clock,
+            metrics: metrics.redirects.clone(),
+            sni_overrides: config
+                .file
+                .hosts
+                .iter()
+                .map(|(pattern, host)| (pattern.clone(), host.sni_override.clone()))
+                .collect(),
+            sni_clients: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Whether `https://` upstreams are contacted over HTTP/3.
+    pub fn http3_enabled(&self) -> bool {
+        #[cfg(feature = "h3")]
+        return self.http3.is_some();
+        #[cfg(not(feature = "h3"))]
+        false
+    }
+
+    /// Sends the request made by `request`, retrying it up to
+    /// `UPSTREAM_RETRIES` times and following up to `MAX_REDIRECTS`
+    /// redirects. Redirects that aren't followed, because of the limit or
+    /// because their target isn't allowed, are returned. All attempts
+    /// together have to finish within `TOTAL_REQUEST_DEADLINE_SECONDS`.
+    ///
+    /// With `FALLBACK_TO_HTTP_ON_TLS_ERROR`, `https://` requests whose TLS
+    /// handshake fails are sent once more over plain HTTP.
+    pub async fn send(
+        &self,
+        secure: bool,
+        request: impl Fn(&Client) -> RequestBuilder,
+    ) -> Result<Response, SendError> {
+        let deadline = self
+            .total_request_deadline
+            .map(|budget| (self.clock.now() + budget, budget));
+        let template = request(&self.client).build()?;
+        let mut first = template.url().clone();
+        let mut method = template.method().clone();
+        let mut headers = template.headers().clone();
+        let mut body = template
+            .body()
+            .and_then(Body::as_bytes)
+            .map(|body| body.to_vec());
+        let timeout = template.timeout().copied();
+
+        let mut response = match self
+            .send_retrying(secure, template.method(), &first, &request, deadline)
+            .await
+        {
+            Err(SendError::Request(e))
+                if self.fallback_to_http
+                    && first.scheme() == "https"
+                    && upstream_error::classify(&e).allows_http_fallback() =>
+            {
+                let fallback = match http_fallback(&first) {
+                    Some(fallback) => fallback,
+                    None => return Err(SendError::Request(e)),
+                };
+                warn!(
+                    "TLS handshake with {} failed, falling back to {} in plain text",
+                    first, fallback
+                );
+                first = fallback;
+                self.send_retrying(
+                    false,
+                    &method,
+                    &first,
+                    &|client: &Client| rebuild(client, &method, &first, &headers, &body, timeout),
+                    deadline,
+                )
+                .await?
+            }
+            result => result?,
+        };
+
+        let mut url = first.clone();
+
+        let mut followed = 0;
+        while let Some(target) = redirect_target(&response, &url) {
+            if followed >= self.max_redirects {
+                warn!(
+                    "Not following redirect from {}, reached the limit of {}",
+                    url, self.max_redirects
+                );
+                break;
+            }
+            if !redirect_allowed(&first, &target).await {
+                warn!("Not following redirect from {} to {}", url, target);
+                self.metrics.blocked.inc();
+                break;
+            }
+
+            redirect_request(
+                response.status(),
+                &url,
+                &target,
+                &mut method,
+                &mut headers,
+                &mut body,
+            );
+            url = target;
+            followed += 1;
+            self.metrics.followed.inc();
+            response = self
+                .send_retrying(
+                    url.scheme() == "https",
+                    &method,
+                    &url,
+                    &|client: &Client| rebuild(client, &method, &url, &headers, &body, timeout),
+                    deadline,
+                )
+                .await?;
+        }
+
+        self.metrics.chain_length.observe(followed as f64);
+        Ok(response)
+    }
+
+    /// Sends the request made by `request` without following redirects,
+    /// sending idempotent requests again while they fail in a way that may
+    /// be temporary and retries are left, in the request and in the retry
+    /// budget of the host. Gives up once `deadline`, the end of the request
+    /// budget, has passed.
+    async fn send_retrying(
+        &self,
+        secure: bool,
+        method: &Method,
+        url: &Url,
+        request: &impl Fn(&Client) -> RequestBuilder,
+        deadline: Option<(Instant, Duration)>,
+    ) -> Result<Response, SendError> {
+        let idempotent = [
+            Method::GET,
+            Method::HEAD,
+            Method::OPTIONS,
+            Method::PUT,
+            Method::DELETE,
+        ]
+        .contains(method);
+        let mut attempt = 0;
+        loop {
+            let result =
+                within_deadline(self.clock.now(), deadline, self.send_once(secure, request)).await;
+            let temporary = match &result {
+                Ok(response) => [
+                    StatusCode::BAD_GATEWAY,
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    StatusCode::GATEWAY_TIMEOUT,
+                ]
+                .contains(&response.status()),
+                Err(SendError::Request(e)) => e.is_connect(),
+                Err(SendError::HeaderTimeout(_)) => true,
+                Err(SendError::Deadline(_)) => false,
+            };
+            let host = url.host_str().unwrap_or_default();
+            if !temporary {
+                if let (Some(budget), Ok(_)) = (&self.retry_budget, &result) {
+                    budget.record_success(host);
+                }
+                return result;
+            }
+            if !idempotent || attempt >= self.retries {
+                return result;
+            }
+            if self
+                .retry_budget
+                .as_ref()
+                .is_some_and(|budget| !budget.try_retry(host))
+            {
+                warn!(
+                    "Not retrying request to {}, retry budget of {} exhausted",
+                    url, host
+                );
+                return result;
+            }
+            attempt += 1;
+            warn!(
+                "Retrying request to {}, attempt {} of {}",
+                url, attempt, self.retries
+            );
+        }
+    }
+
+    /// Sends the request made by `request` without following redirects,
+    /// waiting at most `RESPONSE_HEADER_TIMEOUT_SECONDS` for the response.
+    async fn send_once(
+        &self,
+        secure: bool,
+        request: &impl Fn(&Client) -> RequestBuilder,
+    ) -> Result<Response, SendError> {
+        let send = self.send_with_fallback(secure, request);
+        match self.response_header_timeout {
+            Some(timeout) => time::timeout(timeout, send)
+                .await
+                .map_err(|_| SendError::HeaderTimeout(timeout))?
+                .map_err(SendError::from),
+            None => send.await.map_err(SendError::from),
+        }
+    }
+
+    /// Sends the request made by `request`, which may be called a second
+    /// time to retry over the fallback client.
+    async fn send_with_fallback(
+        &self,
+        secure: bool,
+        request: &impl Fn(&Client) -> RequestBuilder,
+    ) -> reqwest::Result<Response> {
+        if let Some(result) = self.send_with_sni_override(request).await {
+            return result;
+        }
+
+        #[cfg(feature = "h3")]
+        if let (Some(http3), true) = (&self.http3, secure) {
+            match request(http3)
+                .version(reqwest::Version::HTTP_3)
+                .send()
+                .await
+            {
+                // The request never reached the upstream, so it is safe to send it again
+                Err(e) if e.is_connect() => {
+                    log::warn!("HTTP/3 connection failed, falling back: {}", e);
+                }
+                result => return result,
+            }
+        }
+        #[cfg(not(feature = "h3"))]
+        let _ = secure;
+
+        request(&self.client).send().await
+    }
+
+    /// Sends an `https://` request to a host with an `sni_override` by
+    /// addressing it by the overriding name, which a dedicated client
+    /// resolves to the addresses of the host. The `Host` header keeps
+    /// naming the host. Returns `None` for other requests.
+    async fn send_with_sni_override(
+        &self,
+        request: &impl Fn(&Client) -> RequestBuilder,
+    ) -> Option<reqwest::Result<Response>> {
+        let mut built = match request(&self.client).build() {
+            Ok(built) => built,
+            Err(e) => return Some(Err(e)),
+        };
+        if built.url().scheme() != "https" {
+            return None;
+        }
+        let host = built.url().host_str()?.to_string();
+        let sni = find_host(&self.sni_overrides, &host)?.as_ref()?;
+
+        let authority = match built.url().port() {
+            Some(port) => format!("{}:{}", host, port),
+            None => host.clone(),
+        };
+        let authority = HeaderValue::from_str(&authority).ok()?;
+        let client = self.sni_client(&host)?;
+        built.url_mut().set_host(Some(sni)).ok()?;
+        built.headers_mut().insert(HOST, authority);
+        Some(client.execute(built).await)
+    }
+
+    fn sni_client(&self, host: &str) -> Option<Client> {
+        let host = host
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .to_ascii_lowercase();
+        let mut clients = self.sni_clients.lock().unwrap();
+        if let Some(client) = clients.get(&host) {
+            return Some(client.clone());
+        }
+
+        let client = http1_builder(self.title_case_headers, self.min_tls_version)
+            .dns_resolver(Arc::new(ResolveTo(host.clone())))
+            .build()
+            .map_err(|e| warn!("Failed to set up client for {}: {}", host, e))
+            .ok()?;
+        if clients.len() >= MAX_SNI_CLIENTS {
+            // Requests in flight keep their client
+            let evicted = clients.keys().next().cloned();
+            if let Some(evicted) = evicted {
+                clients.remove(&evicted);
+            }
+        }
+        clients.insert(host, client.clone());
+        Some(client)
+    }
+}
+
+/// A builder for clients that speak HTTP/1.1 or HTTP/2 and don't follow
+/// redirects themselves.
+///
+/// They use rustls, whose errors tell why a handshake failed, see
+/// `upstream_error::classify`.
+fn http1_builder(
+    title_case_headers: bool,
+    min_tls_version: Option<MinTlsVersion>,
+) -> ClientBuilder {
+    let mut builder = Client::builder().use_rustls_tls().redirect(Policy::none());
+    if title_case_headers {
+        builder = builder.http1_title_case_headers();
+    }
+    if let Some(version) = min_tls_version {
+        builder = builder.min_tls_version(version.version());
+    }
+    builder
+}
+
+/// Resolves every name to the addresses of a fixed host.
+struct ResolveTo(String);
+
+impl Resolve for ResolveTo {
+    fn resolve(&self, _: Name) -> Resolving {
+        let host = self.0.clone();
+        Box::pin(async move {
+            // The port is replaced by the one of the URL
+            let addrs = task::spawn_blocking(move || (host.as_str(), 0).to_socket_addrs())
+                .await??
+                .collect::<Vec<_>>();
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+/// Runs an upstream attempt started at `now`, failing with
+/// `SendError::Deadline` once the deadline of the request has passed.
+async fn within_deadline<T>(
+    now: Instant,
+    deadline: Option<(Instant, Duration)>,
+    attempt: impl Future<Output = Result<T, SendError>>,
+) -> Result<T, SendError> {
+    match deadline {
+        // Attempts aren't started once the deadline has passed
+        Some((deadline, budget)) if deadline <= now => Err(SendError::Deadline(budget)),
+        Some((deadline, budget)) => time::timeout(deadline - now, attempt)
+            .await
+            .map_err(|_| SendError::Deadline(budget))?,
+        None => attempt.await,
+    }
+}
+
+/// The plain HTTP URL for the same host and path as an `https://` URL on
+/// the default port, on port 80. URLs naming a port have no fallback, as
+/// nothing says that a plain HTTP server listens on the same port.
+fn http_fallback(url: &Url) -> Option<Url> {
+    if url.port().is_some() {
+        return None;
+    }
+    let mut fallback = url.clone();
+    fallback.set_scheme("http").ok()?;
+    Some(fallback)
+}
+
+/// The URL a redirect response to a request for `url` points to.
+fn redirect_target(response: &Response, url: &Url) -> Option<Url> {
+    if !matches!(response.status().as_u16(), 301 | 302 | 303 | 307 | 308) {
+        return None;
+    }
+    let location = response.headers().get(LOCATION)?.to_str().ok()?;
+    url.join(location).ok()
+}
+
+/// Whether a redirect to `target` may be followed in a chain that started
+/// at `first`. Redirects have to stay on HTTP, and may only lead to
+/// loopback, private or link-local addresses if the chain started at one,
+/// so that a public upstream can't point the proxy at internal services.
+/// Chains that started at an internal address may lead to any address, as
+/// their client could already reach internal services.
+async fn redirect_allowed(first: &Url, target: &Url) -> bool {
+    PROXIED_SCHEMES.contains(&target.scheme())
+        && (!is_internal(target).await || is_internal(first).await)
+}
+
+/// Whether a URL refers to a loopback, private or link-local address. Host
+/// names are resolved, and are internal if any of their addresses is.
+async fn is_internal(url: &Url) -> bool {
+    let host = match url.host_str() {
+        Some(host) => host.trim_start_matches('[').trim_end_matches(']'),
+        None => return false,
+    };
+    if let Ok(ip) = host.parse::<IpAddr>() {
+        return is_internal_ip(ip);
+    }
+    let name = host.trim_end_matches('.').to_ascii_lowercase();
+    if name == "localhost" || name.ends_with(".localhost") {
+        return true;
+    }
+    let port = url.port_or_known_default().unwrap_or(0);
+    match task::spawn_blocking(move || (name.as_str(), port).to_socket_addrs()).await {
+        Ok(Ok(mut addrs)) => addrs.any(|addr| is_internal_ip(addr.ip())),
+        // Names that don't resolve can't be connected to either
+        _ => false,
+    }
+}
+
+/// Whether an address is a loopback, private or link-local one.
+fn is_internal_ip(ip: IpAddr) -> bool {
+    let ip = match ip {
+        IpAddr::V6(ip) => ip.to_ipv4_mapped().map_or(IpAddr::V6(ip), IpAddr::V4),
+        ip => ip,
+    };
+    match ip {
+        IpAddr::V4(ip) => {
+            ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+        }
+        IpAddr::V6(ip) => {
+            let first = ip.segments()[0];
+            ip.is_loopback()
+                || ip.is_unspecified()
+                // Unique local and link-local addresses
+                || first & 0xfe00 == 0xfc00
+                || first & 0xffc0 == 0xfe80
+        }
+    }
+}
+
+/// Adjusts the request for following a redirect from `from` to `to`.
+///
+/// Like browsers, `303` and `POST` requests redirected with `301` or `302`
+/// continue as `GET` without a body. Credentials are only sent to the
+/// origin they were meant for.
+fn redirect_request(
+    status: StatusCode,
+    from: &Url,
+    to: &Url,
+    method: &mut Method,
+    headers: &mut HeaderMap,
+    body: &mut Option<Vec<u8>>,
+) {
+    let to_get = (status == StatusCode::SEE_OTHER && *method != Method::HEAD)
+        || (matches!(status, StatusCode::MOVED_PERMANENTLY | StatusCode::FOUND)
+            && *method == Method::POST);
+    if to_get {
+        *method = Method::GET;
+        *body = None;
+        for header in [CONTENT_TYPE, CONTENT_LENGTH, CONTENT_ENCODING] {
+            headers.remove(header);
+        }
+    }
+
+    if from.origin() != to.origin() {
+        for header in [AUTHORIZATION, PROXY_AUTHORIZATION, COOKIE] {
+            headers.remove(header);
+        }
+    }
+}
+
+fn rebuild(
+    client: &Client,
+    method: &Method,
+    url: &Url,
+    headers: &HeaderMap,
+    body: &Option<Vec<u8>>,
+    timeout: Option<Duration>,
+) -> RequestBuilder {
+    let mut request = client
+        .request(method.clone(), url.clone())
+        .headers(headers.clone());
+    if let Some(body) = body {
+        request = request.body(body.clone());
+    }
+    match timeout {
+        Some(timeout) => request.timeout(timeout),
+        None => request,
+    }
+}
@@ -0,0 +1,95 @@
+use std::net::IpAddr;
+
+use actix_web::HttpRequest;
+use ipnet::IpNet;
+
+/// Whether forwarded headers of a request are believed: with
+/// `trusted_proxies` only if its peer is in one of the ranges, which is
+/// enough to trust them, otherwise if `trusted` is set.
+fn forwarded_trusted(req: &HttpRequest, trusted: bool, trusted_proxies: &[IpNet]) -> bool {
+    if trusted_proxies.is_empty() {
+        return trusted;
+    }
+    req.peer_addr()
+        .is_some_and(|address| is_allowed(trusted_proxies, address.ip().to_canonical()))
+}
+
+/// Determines the address of the client that sent a request.
+///
+/// With `trust_forwarded_for` the first valid address of `X-Forwarded-For`
+/// is used, falling back to the peer address of the connection. With
+/// `trusted_proxies` the header is only used if the request comes from one
+/// of them, and the client is the last address not added by one of them.
+pub fn client_ip(
+    req: &HttpRequest,
+    trust_forwarded_for: bool,
+    trusted_proxies: &[IpNet],
+) -> Option<IpAddr> {
+    let forwarded = forwarded_trusted(req, trust_forwarded_for, trusted_proxies)
+        .then(|| {
+            let addresses: Vec<IpAddr> = req
+                .headers()
+                .get_all("x-forwarded-for")
+                .filter_map(|value| value.to_str().ok())
+                .flat_map(|value| value.split(','))
+                .filter_map(|address| address.trim().parse::<IpAddr>().ok())
+                .map(|address| address.to_canonical())
+                .collect();
+            // Each trusted proxy appends the address it got the request from
+            let client = if trusted_proxies.is_empty() {
+                None
+            } else {
+                addresses
+                    .iter()
+                    .rev()
+                    .find(|address| !is_allowed(trusted_proxies, **address))
+            };
+            client.or(addresses.first()).copied()
+        })
+        .flatten();
+
+    forwarded
+        .or_else(|| req.peer_addr().map(|address| address.ip()))
+        .map(|address| address.to_canonical())
+}
+
+/// Determines the scheme the client used to reach the proxy.
+///
+/// With `trust_forwarded_proto` the first `X-Forwarded-Proto` value set by
+/// a TLS terminating load balancer is used, falling back to the scheme of
+/// the connection. With `trusted_proxies` the header is only used if the
+/// request comes from one of them.
+pub fn client_scheme(
+    req: &HttpRequest,
+    trust_forwarded_proto: bool,
+    trusted_proxies: &[IpNet],
+) -> &'static str {
+    let forwarded = forwarded_trusted(req, trust_forwarded_proto, trusted_proxies)
+        .then(|| {
+            req.headers()
+                .get("x-forwarded-proto")
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.split(',').next())
+                .map(|scheme| scheme.trim().to_ascii_lowercase())
+        })
+        .flatten();
+
+    match forwarded.as_deref() {
+        Some("https") => "https",
+        Some("http") => "http",
+        _ if req.app_config().secure() => "https",
+        _ => "http",
+    }
+}
+
+/// Parses a CIDR range, treating a plain address as a single host range.
+pub fn parse_cidr(cidr: &str) -> Option<IpNet> {
+    cidr.parse::<IpNet>()
+        .ok()
+        .or_else(|| cidr.parse::<IpAddr>().ok().map(IpNet::from))
+}
+
+/// Checks if an address is within any of the ranges.
+pub fn is_allowed(ranges: &[IpNet], address: IpAddr) -> bool {
+    ranges.iter().any(|range| range.contains(&address))
+}
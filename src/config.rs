@@ -0,0 +1,853 @@
+use std::borrow::Cow;
+use std::env;
+use std::io;
+use std::time::Duration;
+
+use actix_web::http::Method;
+use ipnet::IpNet;
+use log::warn;
+use serde::{Serialize, Serializer};
+
+use crate::access_log::AccessLogFormat;
+use crate::client::{MinTlsVersion, UpstreamHttpVersion};
+use crate::client_ip;
+use crate::config_file::{glob_match, ConfigFile};
+use crate::cors::{CorsConfig, OriginPattern};
+use crate::error_body::ErrorBodyTemplate;
+
+/// Runtime configuration of the proxy, read from environment variables.
+///
+/// Serializes with secrets redacted and durations in seconds, for
+/// `GET /admin/config`.
+#[derive(Serialize)]
+pub struct Config {
+    /// Base path inserted before the path of full target URLs.
+    pub upstream_path_prefix: Option<String>,
+    /// Whether duplicate slashes in upstream paths are collapsed.
+    pub normalize_path_slashes: bool,
+    /// Target URL schemes accepted in addition to `http` and `https`,
+    /// lowercase. Requests for them pass validation but are answered with
+    /// `501`, as only HTTP is proxied.
+    pub allow_extra_schemes: Vec<String>,
+    /// Methods that are proxied, others are answered with `405`.
+    #[serde(serialize_with = "method_names")]
+    pub allowed_methods: Vec<Method>,
+    /// Query parameter names removed before the request is forwarded.
+    pub strip_query_params: Vec<String>,
+    /// Media type patterns of upstream responses that are passed on, any
+    /// type if empty.
+    pub allowed_content_types: Vec<String>,
+    /// CORS headers added to proxied responses.
+    pub cors: CorsConfig,
+    /// How OPTIONS requests are handled.
+    pub options_mode: OptionsMode,
+    /// Bearer token for the admin endpoints, which are disabled without it.
+    #[serde(serialize_with = "redacted")]
+    pub admin_token: Option<String>,
+    /// Seconds to wait for in-flight requests when shutting down.
+    pub shutdown_timeout: u64,
+    /// Whether new proxy requests are rejected while draining.
+    pub drain_reject_requests: bool,
+    /// Maximum time to connect to the upstream and receive the response
+    /// headers. Reading the body is bounded by `stream_idle_timeout` only.
+    #[serde(serialize_with = "optional_seconds")]
+    pub request_timeout: Option<Duration>,
+    /// Maximum time to wait for the response headers of each upstream
+    /// request, including redirects.
+    #[serde(serialize_with = "optional_seconds")]
+    pub response_header_timeout: Option<Duration>,
+    /// Maximum time for all attempts of an upstream request, including
+    /// retries and redirects, until the response headers arrive.
+    #[serde(serialize_with = "optional_seconds")]
+    pub total_request_deadline: Option<Duration>,
+    /// Number of times idempotent requests are sent again after failing
+    /// to connect or getting a 502, 503 or 504.
+    pub upstream_retries: usize,
+    /// Retries per upstream host earned by each successful response, no
+    /// budget if 0.
+    pub retry_budget_ratio: f64,
+    /// Retries per upstream host earned per second regardless of successes.
+    pub retry_budget_min_per_second: f64,
+    /// Maximum number of retries per upstream host saved up in the budget.
+    pub retry_budget_burst: usize,
+    /// Maximum time to wait for the next chunk of a streamed response.
+    #[serde(serialize_with = "optional_seconds")]
+    pub stream_idle_timeout: Option<Duration>,
+    /// Responses with a Content-Length below this are buffered instead of
+    /// streamed, none if 0.
+    pub stream_threshold: usize,
+    /// Whether header names are sent in Title-Case over HTTP/1.1, to the
+    /// upstream and to the client, instead of lowercase.
+    pub title_case_headers: bool,
+    /// Whether proxied responses carry a `Server-Timing` header with the
+    /// time spent waiting for the upstream and in the proxy.
+    pub send_server_timing: bool,
+    /// Whether credentials in target URLs are sent as basic authentication
+    /// instead of rejecting the request.
+    pub allow_url_credentials: bool,
+    /// Maximum number of upstream redirects followed per request.
+    pub max_redirects: usize,
+    /// Maximum size of request bodies in bytes.
+    pub max_body_size: usize,
+    /// Handling of requests with both `Content-Length` and
+    /// `Transfer-Encoding`.
+    pub ambiguous_framing: AmbiguousFraming,
+    /// Maximum length of the decoded target URL, including the query.
+    pub max_url_length: usize,
+    /// Maximum number of upstream response headers, counting every value.
+    pub max_response_headers: usize,
+    /// Client address ranges allowed to use the proxy, everyone if empty.
+    pub client_ip_allowlist: Vec<IpNet>,
+    /// Whether the client address is taken from `X-Forwarded-For`.
+    pub trust_forwarded_for: bool,
+    /// Whether the client's scheme is taken from `X-Forwarded-Proto`.
+    pub trust_forwarded_proto: bool,
+    /// Peers whose forwarded headers are believed, which takes precedence
+    /// over the `trust_forwarded_*` switches if not empty.
+    pub trusted_proxies: Vec<IpNet>,
+    /// Whether the client address is sent upstream as `X-Real-IP`.
+    pub send_x_real_ip: bool,
+    /// Hosts the proxy is expected to be reached at, any host if empty.
+    pub enforce_host: Vec<String>,
+    /// Whether requests other than preflights need an `Origin` header.
+    pub require_origin: bool,
+    /// Whether proxied responses ask crawlers not to index them.
+    pub add_noindex: bool,
+    /// File that access log lines are written to instead of the logger.
+    pub access_log_file: Option<String>,
+    /// Format of access log lines.
+    pub access_log_format: AccessLogFormat,
+    /// Size in bytes after which the access log file is rotated, never if 0.
+    pub access_log_max_size: u64,
+    /// Number of rotated access log files that are kept.
+    pub access_log_max_files: usize,
+    /// Share of successful requests that get an access log line, from 0
+    /// to 1. Errors and slow requests are always logged.
+    pub log_sample_rate: f64,
+    /// Duration from which requests are logged whatever the sample rate.
+    #[serde(serialize_with = "seconds")]
+    pub log_slow_request_threshold: Duration,
+    /// Maximum number of requests waiting for an upstream response, no
+    /// limit if 0.
+    pub max_concurrent_requests: usize,
+    /// Maximum number of requests a single client address may have waiting
+    /// for an upstream response, no limit if 0.
+    pub max_concurrent_per_client: usize,
+    /// Number of requests after which a client connection is closed, no
+    /// limit if 0.
+    pub max_requests_per_connection: usize,
+    /// Maximum number of open connections from one client IP address,
+    /// further ones are closed right away. No limit if 0.
+    pub max_connections_per_ip: usize,
+    /// Maximum number of requests in a `/batch` request, which is only
+    /// served if this is above 0.
+    pub max_batch_size: usize,
+    /// Number of requests of a batch forwarded at the same time.
+    pub batch_concurrency: usize,
+    /// Maximum size in bytes of the response body of a batch entry.
+    pub max_batch_response_size: usize,
+    /// Seconds clients are asked to wait when the proxy is overloaded.
+    pub overload_retry_after: u64,
+    /// Upper bound of the random seconds added to `overload_retry_after`,
+    /// so that shed clients don't all retry at once.
+    pub retry_after_jitter: u64,
+    /// HTTP version used towards upstreams.
+    pub upstream_http_version: UpstreamHttpVersion,
+    /// Lowest TLS version accepted from `https://` upstreams, that of the
+    /// TLS backend if none.
+    pub min_tls_version: Option<MinTlsVersion>,
+    /// Whether `https://` upstreams whose TLS handshake fails are requested
+    /// again over plain HTTP.
+    pub fallback_to_http_on_tls_error: bool,
+    /// Body sent instead of an empty upstream JSON body.
+    pub empty_body_placeholder: Option<String>,
+    /// Whether HTML error pages of upstreams are replaced by a JSON error.
+    pub replace_upstream_error_bodies: bool,
+    /// Lowest upstream status whose HTML body is replaced.
+    pub replace_error_min_status: u16,
+    /// Template for the bodies of errors answered by the proxy, which are
+    /// plain text if none.
+    pub error_body_template: Option<ErrorBodyTemplate>,
+    /// Maximum number of responses in the cache, which is disabled if 0.
+    pub cache_max_entries: usize,
+    /// Maximum body size of a cached response in bytes, the only limit of
+    /// single entries. At most `cache_max_bytes`.
+    pub cache_max_body_size: usize,
+    /// Maximum total size of the cached bodies in bytes, unlimited if 0.
+    pub cache_max_bytes: usize,
+    /// How long responses without an explicit lifetime are cached.
+    #[serde(serialize_with = "seconds")]
+    pub cache_default_ttl: Duration,
+    /// How long stale responses are served when the upstream fails.
+    #[serde(serialize_with = "seconds")]
+    pub cache_stale_if_error: Duration,
+    /// Whether concurrent cache misses for the same URL wait for the
+    /// first of them instead of all being forwarded.
+    pub cache_coalesce_requests: bool,
+    /// Whether gzip encoded upstream responses are decompressed.
+    pub decompress_upstream: bool,
+    /// Whether requests with an Accept-Encoding forward it unchanged and
+    /// get the upstream body without decompression.
+    pub preserve_client_accept_encoding: bool,
+    /// Maximum size in bytes of a decompressed response body.
+    pub max_decompressed_size: u64,
+    /// Whether URLs in HTML and CSS responses are rewritten to go through
+    /// the proxy. Requires the `rewrite` feature.
+    pub rewrite_html_urls: bool,
+    /// Maximum size in bytes of a document whose URLs are rewritten.
+    pub rewrite_max_body_size: usize,
+    /// Directory that upstream exchanges are captured to for debugging.
+    pub debug_capture_dir: Option<String>,
+    /// Pattern of the upstream paths whose exchanges are captured, in which
+    /// `*` matches any characters.
+    pub debug_capture_pattern: String,
+    /// Maximum number of body bytes captured per request and response.
+    pub debug_capture_max_size: usize,
+    /// Headers whose values are left out of captures, lowercase.
+    pub debug_capture_redact_headers: Vec<String>,
+    /// URL fetched once at startup to verify outbound connectivity.
+    #[serde(serialize_with = "redacted_url")]
+    pub startup_check_url: Option<String>,
+    /// Whether a failed startup check aborts startup.
+    pub startup_check_fatal: bool,
+    /// Timeout of the startup check.
+    #[serde(serialize_with = "seconds")]
+    pub startup_check_timeout: Duration,
+    /// URL requested with HEAD by `/readyz?deep=1` to verify that
+    /// upstreams can be reached.
+    #[serde(serialize_with = "redacted_url")]
+    pub canary_url: Option<String>,
+    /// Timeout of the canary request.
+    #[serde(serialize_with = "seconds")]
+    pub canary_timeout: Duration,
+    /// How long the result of a canary request is reused.
+    #[serde(serialize_with = "seconds")]
+    pub canary_cache_ttl: Duration,
+    /// Settings from the file given in `CONFIG_FILE`.
+    pub file: ConfigFile,
+}
+
+/// Handling of OPTIONS requests.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OptionsMode {
+    /// Answer preflight requests locally without contacting the upstream.
+    #[default]
+    Preflight,
+    /// Forward OPTIONS requests to the upstream like any other method.
+    Passthrough,
+}
+
+/// Handling of requests whose body length is given by both
+/// `Content-Length` and `Transfer-Encoding`, which servers may read
+/// differently to smuggle a second request in the body of the first.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AmbiguousFraming {
+    /// Reject the request with a `400` and close the connection.
+    #[default]
+    Reject,
+    /// Read the body by its `Transfer-Encoding` and ignore the
+    /// `Content-Length`.
+    Normalize,
+}
+
+impl Default for Config {
+    /// The configuration used when no environment variables are set.
+    fn default() -> Self {
+        Config {
+            upstream_path_prefix: None,
+            normalize_path_slashes: false,
+            allow_extra_schemes: Vec::new(),
+            allowed_methods: default_allowed_methods(),
+            strip_query_params: Vec::new(),
+            allowed_content_types: Vec::new(),
+            cors: CorsConfig {
+                allowed_methods: cors_method_names(&default_allowed_methods()),
+                ..CorsConfig::default()
+            },
+            options_mode: OptionsMode::Preflight,
+            admin_token: None,
+            shutdown_timeout: 30,
+            drain_reject_requests: false,
+            request_timeout: None,
+            response_header_timeout: None,
+            total_request_deadline: None,
+            upstream_retries: 0,
+            retry_budget_ratio: 0.0,
+            retry_budget_min_per_second: 1.0,
+            retry_budget_burst: 10,
+            stream_idle_timeout: None,
+            stream_threshold: 0,
+            title_case_headers: false,
+            send_server_timing: false,
+            allow_url_credentials: false,
+            max_redirects: 10,
+            max_body_size: 256 * 1024,
+            ambiguous_framing: AmbiguousFraming::Reject,
+            max_url_length: 8192,
+            max_response_headers: 100,
+            client_ip_allowlist: Vec::new(),
+            trust_forwarded_for: false,
+            trust_forwarded_proto: false,
+            trusted_proxies: Vec::new(),
+            send_x_real_ip: false,
+            enforce_host: Vec::new(),
+            require_origin: false,
+            add_noindex: false,
+            access_log_file: None,
+            access_log_format: AccessLogFormat::Plain,
+            access_log_max_size: 100 * 1024 * 1024,
+            access_log_max_files: 5,
+            log_sample_rate: 1.0,
+            log_slow_request_threshold: Duration::from_secs(1),
+            max_concurrent_requests: 0,
+            max_concurrent_per_client: 0,
+            max_requests_per_connection: 0,
+            max_connections_per_ip: 0,
+            max_batch_size: 0,
+            batch_concurrency: 4,
+            max_batch_response_size: 256 * 1024,
+            overload_retry_after: 1,
+            retry_after_jitter: 5,
+            upstream_http_version: UpstreamHttpVersion::Auto,
+            min_tls_version: None,
+            fallback_to_http_on_tls_error: false,
+            empty_body_placeholder: None,
+            replace_upstream_error_bodies: false,
+            replace_error_min_status: 400,
+            error_body_template: None,
+            cache_max_entries: 0,
+            cache_max_body_size: 1024 * 1024,
+            cache_max_bytes: 0,
+            cache_default_ttl: Duration::ZERO,
+            cache_stale_if_error: Duration::ZERO,
+            cache_coalesce_requests: false,
+            decompress_upstream: false,
+            preserve_client_accept_encoding: false,
+            max_decompressed_size: 100 * 1024 * 1024,
+            rewrite_html_urls: false,
+            rewrite_max_body_size: 5 * 1024 * 1024,
+            debug_capture_dir: None,
+            debug_capture_pattern: "*".to_string(),
+            debug_capture_max_size: 1024 * 1024,
+            debug_capture_redact_headers: default_redact_headers(),
+            startup_check_url: None,
+            startup_check_fatal: false,
+            startup_check_timeout: Duration::from_secs(5),
+            canary_url: None,
+            canary_timeout: Duration::from_secs(2),
+            canary_cache_ttl: Duration::from_secs(10),
+            file: ConfigFile::default(),
+        }
+    }
+}
+
+fn redacted<S: Serializer>(secret: &Option<String>, serializer: S) -> Result<S::Ok, S::Error> {
+    secret.as_ref().map(|_| "[redacted]").serialize(serializer)
+}
+
+/// Serializes a URL without the credentials it may contain.
+fn redacted_url<S: Serializer>(url: &Option<String>, serializer: S) -> Result<S::Ok, S::Error> {
+    url.as_deref().map(redact_userinfo).serialize(serializer)
+}
+
+fn seconds<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    duration.as_secs_f64().serialize(serializer)
+}
+
+fn optional_seconds<S: Serializer>(
+    duration: &Option<Duration>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    duration
+        .map(|duration| duration.as_secs_f64())
+        .serialize(serializer)
+}
+
+fn method_names<S: Serializer>(methods: &[Method], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(methods.iter().map(Method::as_str))
+}
+
+/// Removes the user information from a URL that may not parse, so it can
+/// be logged or shown without leaking credentials.
+pub(crate) fn redact_userinfo(url: &str) -> String {
+    let start = url.find("://").map(|index| index + 3).unwrap_or(0);
+    let end = url[start..]
+        .find(['/', '?', '#'])
+        .map(|index| start + index)
+        .unwrap_or(url.len());
+    match url[start..end].rfind('@') {
+        Some(at) => format!("{}{}", &url[..start], &url[start + at + 1..]),
+        None => url.to_string(),
+    }
+}
+
+/// Target URL schemes that are proxied.
+pub const PROXIED_SCHEMES: [&str; 2] = ["http", "https"];
+
+impl Config {
+    /// Whether target URLs with `scheme` are accepted.
+    pub fn scheme_allowed(&self, scheme: &str) -> bool {
+        PROXIED_SCHEMES.contains(&scheme)
+            || self
+                .allow_extra_schemes
+                .iter()
+                .any(|allowed| allowed.eq_ignore_ascii_case(scheme))
+    }
+
+    /// Whether responses of `content_type` from an upstream host are passed
+    /// on, by the `allowed_content_types` of the host or the global ones.
+    pub fn content_type_allowed(&self, host: Option<&str>, content_type: &str) -> bool {
+        let allowed = host
+            .and_then(|host| self.file.host(host))
+            .and_then(|host| host.allowed_content_types.as_ref())
+            .unwrap_or(&self.allowed_content_types);
+        let media_type = content_type
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase();
+        allowed.is_empty()
+            || allowed.iter().any(|pattern| {
+                glob_match(
+                    pattern.trim().to_ascii_lowercase().as_bytes(),
+                    media_type.as_bytes(),
+                )
+            })
+    }
+
+    /// The CORS policy for responses from an upstream host.
+    pub fn cors_for(&self, host: Option<&str>) -> Cow<'_, CorsConfig> {
+        match host
+            .and_then(|host| self.file.host(host))
+            .and_then(|host| host.cors.as_ref())
+        {
+            Some(host_cors) => Cow::Owned(self.cors.with_overrides(host_cors)),
+            None => Cow::Borrowed(&self.cors),
+        }
+    }
+
+    pub fn from_env() -> io::Result<Self> {
+        let file = match env::var("CONFIG_FILE") {
+            Ok(path) => ConfigFile::load(&path)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+            Err(_) => ConfigFile::default(),
+        };
+
+        let allow_extra_schemes: Vec<String> = env_list("ALLOW_EXTRA_SCHEMES")
+            .into_iter()
+            .map(|scheme| scheme.to_ascii_lowercase())
+            .filter(|scheme| !PROXIED_SCHEMES.contains(&scheme.as_str()))
+            .collect();
+        if !allow_extra_schemes.is_empty() {
+            warn!(
+                "ALLOW_EXTRA_SCHEMES accepts target URLs with {}, only http and https are proxied",
+                allow_extra_schemes.join(", ")
+            );
+        }
+
+        let fallback_to_http_on_tls_error = env::var("FALLBACK_TO_HTTP_ON_TLS_ERROR")
+            .map(|val| val == "true")
+            .unwrap_or(false);
+        if fallback_to_http_on_tls_error {
+            warn!(
+                "FALLBACK_TO_HTTP_ON_TLS_ERROR sends requests to https upstreams in plain text when \
+                 their TLS handshake fails, anyone on the network can read and change them"
+            );
+        }
+
+        let mut allowed_methods = Vec::new();
+        for name in env_list("ALLOWED_METHODS") {
+            match Method::from_bytes(name.to_ascii_uppercase().as_bytes()) {
+                Ok(method) if !allowed_methods.contains(&method) => allowed_methods.push(method),
+                Ok(_) => {}
+                Err(_) => warn!("Ignoring invalid allowed method: {}", name),
+            }
+        }
+        if allowed_methods.is_empty() {
+            allowed_methods = default_allowed_methods();
+        }
+        let cors_allowed_methods = cors_method_names(&allowed_methods);
+        let cache_max_bytes = env::var("CACHE_MAX_BYTES")
+            .map(|val| val.parse().unwrap_or(0))
+            .unwrap_or(0);
+        // A single body can't take more than the whole cache
+        let cache_max_body_size = env::var("CACHE_MAX_BODY_BYTES")
+            .map(|val| val.parse().unwrap_or(1024 * 1024))
+            .unwrap_or(1024 * 1024)
+            .min(if cache_max_bytes > 0 {
+                cache_max_bytes
+            } else {
+                usize::MAX
+            });
+
+        Ok(Config {
+            upstream_path_prefix: env::var("UPSTREAM_PATH_PREFIX")
+                .ok()
+                .filter(|prefix| !prefix.trim_matches('/').is_empty()),
+            normalize_path_slashes: env::var("NORMALIZE_PATH_SLASHES")
+                .map(|val| val == "true")
+                .unwrap_or(false),
+            allow_extra_schemes,
+            allowed_methods,
+            strip_query_params: env_list("STRIP_QUERY_PARAMS"),
+            allowed_content_types: env_list("ALLOWED_CONTENT_TYPES"),
+            cors: CorsConfig {
+                allowed_origins: env_list("ALLOWED_ORIGINS")
+                    .iter()
+                    .filter_map(|pattern| {
+                        let parsed = OriginPattern::parse(pattern);
+                        if parsed.is_none() {
+                            warn!("Ignoring invalid allowed origin: {}", pattern);
+                        }
+                        parsed
+                    })
+                    .collect(),
+                max_age: env::var("CORS_MAX_AGE")
+                    .map(|val| val.parse().unwrap_or(3600))
+                    .unwrap_or(3600),
+                expose_headers: match env::var("CORS_EXPOSE_HEADERS") {
+                    Ok(_) => env_list("CORS_EXPOSE_HEADERS"),
+                    Err(_) => CorsConfig::default().expose_headers,
+                },
+                timing_allow_origin: env::var("TIMING_ALLOW_ORIGIN")
+                    .ok()
+                    .filter(|origins| !origins.trim().is_empty()),
+                allow_private_network: env::var("CORS_ALLOW_PRIVATE_NETWORK")
+                    .map(|val| val == "true")
+                    .unwrap_or(false),
+                allowed_methods: cors_allowed_methods,
+                ..CorsConfig::default()
+            },
+            options_mode: match env::var("OPTIONS_MODE").as_deref() {
+                Ok("passthrough") => OptionsMode::Passthrough,
+                Ok("preflight") | Err(_) => OptionsMode::Preflight,
+                Ok(mode) => {
+                    warn!("Unknown OPTIONS_MODE {}, using preflight", mode);
+                    OptionsMode::Preflight
+                }
+            },
+            admin_token: env::var("ADMIN_TOKEN")
+                .ok()
+                .filter(|token| !token.is_empty()),
+            shutdown_timeout: env::var("SHUTDOWN_TIMEOUT_SECONDS")
+                .map(|val| val.parse().unwrap_or(30))
+                .unwrap_or(30),
+            drain_reject_requests: env::var("DRAIN_REJECT_REQUESTS")
+                .map(|val| val == "true")
+                .unwrap_or(false),
+            request_timeout: env::var("REQUEST_TIMEOUT_SECONDS")
+                .ok()
+                .and_then(|val| val.parse().ok())
+                .filter(|secs| *secs > 0)
+                .map(Duration::from_secs),
+            response_header_timeout: env::var("RESPONSE_HEADER_TIMEOUT_SECONDS")
+                .ok()
+                .and_then(|val| val.parse().ok())
+                .filter(|secs| *secs > 0)
+                .map(Duration::from_secs),
+            total_request_deadline: env::var("TOTAL_REQUEST_DEADLINE_SECONDS")
+                .ok()
+                .and_then(|val| val.parse().ok())
+                .filter(|secs| *secs > 0)
+                .map(Duration::from_secs),
+            upstream_retries: env::var("UPSTREAM_RETRIES")
+                .map(|val| val.parse().unwrap_or(0))
+                .unwrap_or(0),
+            retry_budget_ratio: env::var("RETRY_BUDGET_RATIO")
+                .map(|val| val.parse().unwrap_or(0.0))
+                .unwrap_or(0.0),
+            retry_budget_min_per_second: env::var("RETRY_BUDGET_MIN_PER_SECOND")
+                .map(|val| val.parse().unwrap_or(1.0))
+                .unwrap_or(1.0),
+            retry_budget_burst: env::var("RETRY_BUDGET_BURST")
+                .map(|val| val.parse().unwrap_or(10))
+                .unwrap_or(10),
+            stream_idle_timeout: env::var("STREAM_IDLE_TIMEOUT_SECONDS")
+                .ok()
+                .and_then(|val| val.parse().ok())
+                .filter(|secs| *secs > 0)
+                .map(Duration::from_secs),
+            stream_threshold: env::var("STREAM_THRESHOLD_BYTES")
+                .map(|val| val.parse().unwrap_or(0))
+                .unwrap_or(0),
+            title_case_headers: env::var("TITLE_CASE_HEADERS")
+                .map(|val| val == "true")
+                .unwrap_or(false),
+            send_server_timing: env::var("SEND_SERVER_TIMING")
+                .map(|val| val == "true")
+                .unwrap_or(false),
+            allow_url_credentials: env::var("ALLOW_URL_CREDENTIALS")
+                .map(|val| val == "true")
+                .unwrap_or(false),
+            max_redirects: env::var("MAX_REDIRECTS")
+                .map(|val| val.parse().unwrap_or(10))
+                .unwrap_or(10),
+            max_body_size: env::var("MAX_BODY_SIZE_BYTES")
+                .map(|val| val.parse().unwrap_or(256 * 1024))
+                .unwrap_or(256 * 1024),
+            ambiguous_framing: match env::var("AMBIGUOUS_FRAMING").as_deref() {
+                Ok("normalize") => AmbiguousFraming::Normalize,
+                Ok("reject") | Err(_) => AmbiguousFraming::Reject,
+                Ok(mode) => {
+                    warn!("Unknown AMBIGUOUS_FRAMING {}, using reject", mode);
+                    AmbiguousFraming::Reject
+                }
+            },
+            max_url_length: env::var("MAX_URL_LENGTH")
+                .map(|val| val.parse().unwrap_or(8192))
+                .unwrap_or(8192),
+            max_response_headers: env::var("MAX_RESPONSE_HEADERS")
+                .map(|val| val.parse().unwrap_or(100))
+                .unwrap_or(100),
+            client_ip_allowlist: env_list("CLIENT_IP_ALLOWLIST")
+                .iter()
+                .map(|cidr| {
+                    client_ip::parse_cidr(cidr).ok_or_else(|| {
+                        io::Error::new(
+                            io::ErrorKind::InvalidInput,
+                            format!("Invalid CIDR in CLIENT_IP_ALLOWLIST: {}", cidr),
+                        )
+                    })
+                })
+                .collect::<io::Result<_>>()?,
+            trust_forwarded_for: env::var("TRUST_FORWARDED_FOR")
+                .map(|val| val == "true")
+                .unwrap_or(false),
+            trust_forwarded_proto: env::var("TRUST_FORWARDED_PROTO")
+                .map(|val| val == "true")
+                .unwrap_or(false),
+            trusted_proxies: env_list("TRUSTED_PROXY_CIDRS")
+                .iter()
+                .map(|cidr| {
+                    client_ip::parse_cidr(cidr).ok_or_else(|| {
+                        io::Error::new(
+                            io::ErrorKind::InvalidInput,
+                            format!("Invalid CIDR in TRUSTED_PROXY_CIDRS: {}", cidr),
+                        )
+                    })
+                })
+                .collect::<io::Result<_>>()?,
+            send_x_real_ip: env::var("SEND_X_REAL_IP")
+                .map(|val| val == "true")
+                .unwrap_or(false),
+            enforce_host: env_list("ENFORCE_HOST"),
+            require_origin: env::var("REQUIRE_ORIGIN")
+                .map(|val| val == "true")
+                .unwrap_or(false),
+            add_noindex: env::var("ADD_NOINDEX")
+                .map(|val| val == "true")
+                .unwrap_or(false),
+            access_log_file: env::var("ACCESS_LOG_FILE").ok(),
+            access_log_format: match env::var("ACCESS_LOG_FORMAT").as_deref() {
+                Ok("json") => AccessLogFormat::Json,
+                Ok("plain") | Err(_) => AccessLogFormat::Plain,
+                Ok(format) => {
+                    warn!("Unknown ACCESS_LOG_FORMAT {}, using plain", format);
+                    AccessLogFormat::Plain
+                }
+            },
+            access_log_max_size: env::var("ACCESS_LOG_MAX_SIZE_MB")
+                .map(|val| val.parse().unwrap_or(100))
+                .unwrap_or(100)
+                * 1024
+                * 1024,
+            access_log_max_files: env::var("ACCESS_LOG_MAX_FILES")
+                .map(|val| val.parse().unwrap_or(5))
+                .unwrap_or(5),
+            log_sample_rate: env::var("LOG_SAMPLE_RATE")
+                .ok()
+                .and_then(|val| val.parse().ok())
+                .filter(|rate: &f64| rate.is_finite())
+                .unwrap_or(1.0)
+                .clamp(0.0, 1.0),
+            log_slow_request_threshold: Duration::from_millis(
+                env::var("LOG_SLOW_REQUEST_MS")
+                    .map(|val| val.parse().unwrap_or(1000))
+                    .unwrap_or(1000),
+            ),
+            max_concurrent_requests: env::var("MAX_CONCURRENT_REQUESTS")
+                .map(|val| val.parse().unwrap_or(0))
+                .unwrap_or(0),
+            max_concurrent_per_client: env::var("MAX_CONCURRENT_PER_CLIENT")
+                .map(|val| val.parse().unwrap_or(0))
+                .unwrap_or(0),
+            max_requests_per_connection: env::var("MAX_REQUESTS_PER_CONNECTION")
+                .map(|val| val.parse().unwrap_or(0))
+                .unwrap_or(0),
+            max_connections_per_ip: env::var("MAX_CONNECTIONS_PER_IP")
+                .map(|val| val.parse().unwrap_or(0))
+                .unwrap_or(0),
+            max_batch_size: env::var("MAX_BATCH_SIZE")
+                .map(|val| val.parse().unwrap_or(0))
+                .unwrap_or(0),
+            batch_concurrency: env::var("BATCH_CONCURRENCY")
+                .map(|val| val.parse().unwrap_or(4))
+                .unwrap_or(4)
+                .max(1),
+            max_batch_response_size: env::var("MAX_BATCH_RESPONSE_BYTES")
+                .map(|val| val.parse().unwrap_or(256 * 1024))
+                .unwrap_or(256 * 1024),
+            overload_retry_after: env::var("OVERLOAD_RETRY_AFTER_SECONDS")
+                .map(|val| val.parse().unwrap_or(1))
+                .unwrap_or(1),
+            retry_after_jitter: env::var("RETRY_AFTER_JITTER_SECONDS")
+                .map(|val| val.parse().unwrap_or(5))
+                .unwrap_or(5),
+            upstream_http_version: match env::var("UPSTREAM_HTTP_VERSION").as_deref() {
+                Ok("http3") => UpstreamHttpVersion::Http3,
+                Ok("auto") | Err(_) => UpstreamHttpVersion::Auto,
+                Ok(version) => {
+                    warn!("Unknown UPSTREAM_HTTP_VERSION {}, using auto", version);
+                    UpstreamHttpVersion::Auto
+                }
+            },
+            min_tls_version: match env::var("MIN_TLS_VERSION") {
+                Ok(version) => Some(MinTlsVersion::parse(&version).ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("Invalid MIN_TLS_VERSION {}, expected 1.2 or 1.3", version),
+                    )
+                })?),
+                Err(_) => None,
+            },
+            fallback_to_http_on_tls_error,
+            empty_body_placeholder: match env::var("EMPTY_BODY_PLACEHOLDER").as_deref() {
+                Ok("true") => Some("{}".to_string()),
+                Ok("false") | Ok("") | Err(_) => None,
+                Ok(placeholder) => Some(placeholder.to_string()),
+            },
+            replace_upstream_error_bodies: env::var("REPLACE_UPSTREAM_ERROR_BODIES")
+                .map(|val| val == "true")
+                .unwrap_or(false),
+            replace_error_min_status: env::var("REPLACE_ERROR_MIN_STATUS")
+                .map(|val| val.parse().unwrap_or(400))
+                .unwrap_or(400),
+            error_body_template: match env::var("ERROR_BODY_TEMPLATE") {
+                Ok(template) => {
+                    let content_type = env::var("ERROR_BODY_CONTENT_TYPE")
+                        .unwrap_or_else(|_| "application/json".to_string());
+                    Some(
+                        ErrorBodyTemplate::new(&template, &content_type).map_err(|e| {
+                            io::Error::new(
+                                io::ErrorKind::InvalidInput,
+                                format!("Invalid ERROR_BODY_TEMPLATE: {}", e),
+                            )
+                        })?,
+                    )
+                }
+                Err(_) => None,
+            },
+            cache_max_entries: env::var("CACHE_MAX_ENTRIES")
+                .map(|val| val.parse().unwrap_or(0))
+                .unwrap_or(0),
+            cache_max_body_size,
+            cache_max_bytes,
+            cache_default_ttl: Duration::from_secs(
+                env::var("CACHE_DEFAULT_TTL_SECONDS")
+                    .map(|val| val.parse().unwrap_or(0))
+                    .unwrap_or(0),
+            ),
+            cache_stale_if_error: Duration::from_secs(
+                env::var("CACHE_STALE_IF_ERROR_SECONDS")
+                    .map(|val| val.parse().unwrap_or(0))
+                    .unwrap_or(0),
+            ),
+            cache_coalesce_requests: env::var("CACHE_COALESCE_REQUESTS")
+                .map(|val| val == "true")
+                .unwrap_or(false),
+            decompress_upstream: env::var("DECOMPRESS_UPSTREAM")
+                .map(|val| val == "true")
+                .unwrap_or(false),
+            preserve_client_accept_encoding: env::var("PRESERVE_CLIENT_ACCEPT_ENCODING")
+                .map(|val| val == "true")
+                .unwrap_or(false),
+            max_decompressed_size: env::var("MAX_DECOMPRESSED_SIZE_BYTES")
+                .map(|val| val.parse().unwrap_or(100 * 1024 * 1024))
+                .unwrap_or(100 * 1024 * 1024),
+            rewrite_html_urls: env::var("REWRITE_HTML_URLS")
+                .map(|val| val == "true")
+                .unwrap_or(false),
+            rewrite_max_body_size: env::var("REWRITE_MAX_BODY_BYTES")
+                .map(|val| val.parse().unwrap_or(5 * 1024 * 1024))
+                .unwrap_or(5 * 1024 * 1024),
+            debug_capture_dir: env::var("DEBUG_CAPTURE_DIR").ok(),
+            debug_capture_pattern: env::var("DEBUG_CAPTURE_PATTERN")
+                .unwrap_or_else(|_| "*".to_string()),
+            debug_capture_max_size: env::var("DEBUG_CAPTURE_MAX_BYTES")
+                .map(|val| val.parse().unwrap_or(1024 * 1024))
+                .unwrap_or(1024 * 1024),
+            debug_capture_redact_headers: match env::var("DEBUG_CAPTURE_REDACT_HEADERS") {
+                Ok(_) => env_list("DEBUG_CAPTURE_REDACT_HEADERS")
+                    .into_iter()
+                    .map(|name| name.to_ascii_lowercase())
+                    .collect(),
+                Err(_) => default_redact_headers(),
+            },
+            startup_check_url: env::var("STARTUP_CHECK_URL").ok(),
+            startup_check_fatal: env::var("STARTUP_CHECK_FATAL")
+                .map(|val| val == "true")
+                .unwrap_or(false),
+            startup_check_timeout: Duration::from_secs(
+                env::var("STARTUP_CHECK_TIMEOUT_SECONDS")
+                    .map(|val| val.parse().unwrap_or(5))
+                    .unwrap_or(5),
+            ),
+            canary_url: env::var("CANARY_URL").ok(),
+            canary_timeout: Duration::from_secs(
+                env::var("CANARY_TIMEOUT_SECONDS")
+                    .map(|val| val.parse().unwrap_or(2))
+                    .unwrap_or(2),
+            ),
+            canary_cache_ttl: Duration::from_secs(
+                env::var("CANARY_CACHE_SECONDS")
+                    .map(|val| val.parse().unwrap_or(10))
+                    .unwrap_or(10),
+            ),
+            file,
+        })
+    }
+}
+
+/// The names of `methods`, as announced in `Access-Control-Allow-Methods`.
+fn cors_method_names(methods: &[Method]) -> Vec<String> {
+    methods.iter().map(Method::to_string).collect()
+}
+
+/// Methods that are proxied unless `ALLOWED_METHODS` is set.
+fn default_allowed_methods() -> Vec<Method> {
+    vec![
+        Method::GET,
+        Method::HEAD,
+        Method::POST,
+        Method::PUT,
+        Method::DELETE,
+        Method::OPTIONS,
+    ]
+}
+
+/// Headers that carry credentials and are redacted from captures.
+fn default_redact_headers() -> Vec<String> {
+    [
+        "authorization",
+        "proxy-authorization",
+        "cookie",
+        "set-cookie",
+    ]
+    .map(String::from)
+    .to_vec()
+}
+
+/// Reads a comma separated list from an environment variable.
+fn env_list(name: &str) -> Vec<String> {
+    env::var(name)
+        .map(|val| {
+            val.split(',')
+                .map(|item| item.trim().to_string())
+                .filter(|item| !item.is_empty())
+                .collect()
+        })
+        .unwrap_or_default()
+}
@@ -0,0 +1,312 @@
+use std::collections::HashMap;
+use std::fs;
+
+use percent_encoding::percent_decode_str;
+use serde::{Deserialize, Serialize, Serializer};
+
+use crate::config::redact_userinfo;
+use crate::cors::OriginPattern;
+
+/// Settings read from the TOML file given in `CONFIG_FILE`.
+///
+/// ```toml
+/// [routes]
+/// "/api" = "https://api.internal/"
+///
+/// [[path_limits]]
+/// pattern = "/*/login"
+/// rate_limit = { requests_per_second = 1.0, burst = 5 }
+///
+/// [hosts."api.example.com"]
+/// rate_limit = { requests_per_second = 5.0, burst = 10 }
+///
+/// [hosts."*.example.org"]
+/// rate_limit = { requests_per_second = 20.0 }
+///
+/// [hosts."private.example.com".cors]
+/// allowed_origins = ["https://app.example.com"]
+/// allow_credentials = true
+///
+/// [hosts."origin.example.net"]
+/// sni_override = "www.example.com"
+///
+/// [hosts."json.example.com"]
+/// allowed_content_types = ["application/json", "application/*+json"]
+/// ```
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigFile {
+    /// Settings for upstream hosts, keyed by exact host or `*.domain` pattern.
+    #[serde(default)]
+    pub hosts: HashMap<String, HostConfig>,
+    /// Upstream base URLs, keyed by the path prefix that is forwarded to them.
+    #[serde(default, serialize_with = "redacted_urls")]
+    pub routes: HashMap<String, String>,
+    /// Rate limits for upstream paths, independent of the host.
+    #[serde(default)]
+    pub path_limits: Vec<PathLimit>,
+    /// Whether paths that match no route may name the full target URL.
+    #[serde(default = "default_full_url_targets")]
+    pub full_url_targets: bool,
+}
+
+impl Default for ConfigFile {
+    fn default() -> Self {
+        ConfigFile {
+            hosts: HashMap::new(),
+            routes: HashMap::new(),
+            path_limits: Vec::new(),
+            full_url_targets: true,
+        }
+    }
+}
+
+fn redacted_urls<S: Serializer>(
+    urls: &HashMap<String, String>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_map(
+        urls.iter()
+            .map(|(prefix, url)| (prefix, redact_userinfo(url))),
+    )
+}
+
+fn default_full_url_targets() -> bool {
+    true
+}
+
+/// Settings that apply to requests towards a specific upstream host.
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct HostConfig {
+    /// Outgoing request budget for the host.
+    pub rate_limit: Option<RateLimit>,
+    /// CORS policy for responses from the host, replacing the global one.
+    pub cors: Option<HostCors>,
+    /// Server name sent in the TLS handshake with the host instead of the
+    /// host itself.
+    pub sni_override: Option<String>,
+    /// Media types of responses the host may send, replacing the global
+    /// `ALLOWED_CONTENT_TYPES`.
+    pub allowed_content_types: Option<Vec<String>>,
+}
+
+/// CORS settings of a host, unset fields fall back to the global settings.
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct HostCors {
+    pub allowed_origins: Option<Vec<OriginPattern>>,
+    pub allowed_methods: Option<Vec<String>>,
+    pub allow_credentials: Option<bool>,
+}
+
+/// A rate limit shared by all upstream paths matching a pattern, in which
+/// `*` matches any characters.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct PathLimit {
+    pub pattern: String,
+    pub rate_limit: RateLimit,
+}
+
+impl PathLimit {
+    pub fn matches(&self, path: &str) -> bool {
+        glob_match(self.pattern.as_bytes(), path.as_bytes())
+    }
+}
+
+/// Matches `text` against a pattern in which `*` matches any characters.
+pub(crate) fn glob_match(pattern: &[u8], text: &[u8]) -> bool {
+    let (mut p, mut t) = (0, 0);
+    // Position of the last `*` and the text position it was tried at
+    let mut backtrack = None;
+    while t < text.len() {
+        if p < pattern.len() && pattern[p] == b'*' {
+            backtrack = Some((p, t));
+            p += 1;
+        } else if p < pattern.len() && pattern[p] == text[t] {
+            p += 1;
+            t += 1;
+        } else if let Some((star, tried)) = backtrack {
+            backtrack = Some((star, tried + 1));
+            p = star + 1;
+            t = tried + 1;
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+/// Token bucket parameters of a rate limit.
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct RateLimit {
+    /// Rate at which the budget refills.
+    pub requests_per_second: f64,
+    /// Maximum number of requests that can be made at once, defaults to
+    /// one second worth of requests.
+    pub burst: Option<u32>,
+}
+
+impl RateLimit {
+    pub fn capacity(&self) -> f64 {
+        self.burst
+            .map(f64::from)
+            .unwrap_or(self.requests_per_second)
+            .max(1.0)
+    }
+}
+
+impl ConfigFile {
+    pub fn load(path: &str) -> Result<Self, String> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read config file {}: {}", path, e))?;
+        Self::parse(&contents).map_err(|e| format!("Invalid config file {}: {}", path, e))
+    }
+
+    /// Parses and validates the contents of a config file.
+    pub fn parse(contents: &str) -> Result<Self, String> {
+        let mut config: ConfigFile = toml::from_str(contents).map_err(|e| e.to_string())?;
+        config.hosts = config
+            .hosts
+            .into_iter()
+            .map(|(pattern, host)| (pattern.to_ascii_lowercase(), host))
+            .collect();
+        config.routes = config
+            .routes
+            .into_iter()
+            .map(|(prefix, base)| (prefix.trim_end_matches('/').to_string(), base))
+            .collect();
+
+        for (prefix, base) in &config.routes {
+            if !prefix.starts_with('/') {
+                return Err(format!("Invalid route {}: must start with /", prefix));
+            }
+            match reqwest::Url::parse(base) {
+                Ok(url) if url.scheme() == "http" || url.scheme() == "https" => {}
+                _ => return Err(format!("Invalid base URL for route {}: {}", prefix, base)),
+            }
+        }
+
+        let rate_limits = config
+            .hosts
+            .iter()
+            .filter_map(|(pattern, host)| host.rate_limit.map(|limit| (pattern, limit)))
+            .chain(
+                config
+                    .path_limits
+                    .iter()
+                    .map(|limit| (&limit.pattern, limit.rate_limit)),
+            );
+        for (pattern, rate_limit) in rate_limits {
+            let rate = rate_limit.requests_per_second;
+            if !rate.is_finite() || rate <= 0.0 {
+                return Err(format!(
+                    "Invalid rate limit for {}: requests_per_second must be positive",
+                    pattern
+                ));
+            }
+        }
+        for (pattern, host) in &config.hosts {
+            // Credentials for every origin would let any website read
+            // responses with the cookies of its visitors
+            if let Some(cors) = host
+                .cors
+                .as_ref()
+                .filter(|cors| cors.allow_credentials == Some(true))
+            {
+                let origins = cors.allowed_origins.as_deref().unwrap_or_default();
+                if origins.is_empty() || origins.contains(&OriginPattern::Any) {
+                    return Err(format!(
+                        "Invalid cors for {}: allow_credentials needs allowed_origins without *",
+                        pattern
+                    ));
+                }
+            }
+            if let Some(sni) = &host.sni_override {
+                let valid = reqwest::Url::parse(&format!("https://{}/", sni))
+                    .ok()
+                    .and_then(|url| url.host_str().map(|host| host == sni.to_ascii_lowercase()))
+                    .unwrap_or(false);
+                if !valid {
+                    return Err(format!("Invalid sni_override for {}: {}", pattern, sni));
+                }
+            }
+        }
+        if let Some(limit) = config
+            .path_limits
+            .iter()
+            .find(|limit| !limit.pattern.starts_with(['/', '*']))
+        {
+            return Err(format!(
+                "Invalid path limit {}: must start with / or *",
+                limit.pattern
+            ));
+        }
+
+        Ok(config)
+    }
+
+    /// Maps a request path to the upstream URL of the longest matching route
+    /// prefix, joining the base with the rest of the path.
+    ///
+    /// Fails for paths with `.` or `..` segments, also percent-encoded,
+    /// which would otherwise lead out of the base path of the route once
+    /// the URL is resolved.
+    pub fn route(&self, path: &str) -> Option<Result<String, String>> {
+        self.routes
+            .iter()
+            .filter_map(|(prefix, base)| {
+                let rest = path.strip_prefix(prefix.as_str())?;
+                if !rest.is_empty() && !rest.starts_with('/') {
+                    return None;
+                }
+                Some((prefix, base, rest.trim_start_matches('/')))
+            })
+            .max_by_key(|(prefix, _, _)| prefix.len())
+            .map(|(prefix, base, rest)| match rest {
+                "" => Ok(base.clone()),
+                rest if has_dot_segments(rest) => {
+                    Err(format!("dot segments in path under route {}", prefix))
+                }
+                rest => Ok(format!("{}/{}", base.trim_end_matches('/'), rest)),
+            })
+    }
+
+    /// Finds the settings for an upstream host, preferring an exact match
+    /// over the most specific `*.domain` pattern.
+    pub fn host(&self, host: &str) -> Option<&HostConfig> {
+        find_host(&self.hosts, host)
+    }
+}
+
+/// Whether a path has `.` or `..` segments once percent-decoded. The
+/// decoded path is split at encoded slashes and at backslashes too, which
+/// upstreams may treat as separators.
+fn has_dot_segments(path: &str) -> bool {
+    percent_decode_str(path)
+        .decode_utf8_lossy()
+        .split(['/', '\\'])
+        .any(|segment| segment == "." || segment == "..")
+}
+
+/// Looks up a host in a map keyed by exact hosts and `*.domain` patterns,
+/// preferring an exact match over the most specific pattern.
+pub(crate) fn find_host<'a, V>(hosts: &'a HashMap<String, V>, host: &str) -> Option<&'a V> {
+    let host = host.to_ascii_lowercase();
+    if let Some(value) = hosts.get(&host) {
+        return Some(value);
+    }
+
+    hosts
+        .iter()
+        .filter(|(pattern, _)| {
+            pattern
+                .strip_prefix("*.")
+                .map(|domain| host.ends_with(&format!(".{}", domain)))
+                .unwrap_or(false)
+        })
+        .max_by_key(|(pattern, _)| pattern.len())
+        .map(|(_, value)| value)
+}
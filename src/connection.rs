@@ -0,0 +1,144 @@
+use std::any::Any;
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::net::{IpAddr, Shutdown};
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+
+use actix_web::body::MessageBody;
+use actix_web::dev::{Extensions, ServiceRequest, ServiceResponse};
+use actix_web::http::ConnectionType;
+use actix_web::middleware::Next;
+use actix_web::rt::net::TcpStream;
+use actix_web::{web, Error, HttpRequest};
+use log::warn;
+use socket2::SockRef;
+
+use crate::config::Config;
+
+/// Number of requests received on a client connection so far.
+#[derive(Clone, Default)]
+struct RequestCount(Rc<Cell<usize>>);
+
+/// Set once a request on a client connection was rejected for its
+/// framing. The requests the client sent behind it can't be told apart
+/// from a body that was meant to hide them.
+#[derive(Clone, Default)]
+struct Untrusted(Rc<Cell<bool>>);
+
+/// Open connections per client IP address, shared by all workers.
+#[derive(Clone, Default)]
+struct ConnectionCounts(Arc<Mutex<HashMap<IpAddr, usize>>>);
+
+impl ConnectionCounts {
+    /// Counts a connection from `address`, unless there are already `max`.
+    fn acquire(&self, address: IpAddr, max: usize) -> Option<ConnectionGuard> {
+        let mut counts = self.0.lock().unwrap();
+        let count = counts.entry(address).or_default();
+        if *count >= max {
+            return None;
+        }
+        *count += 1;
+        Some(ConnectionGuard {
+            address,
+            counts: self.clone(),
+        })
+    }
+}
+
+/// Uncounts its connection when the connection data is dropped.
+struct ConnectionGuard {
+    address: IpAddr,
+    counts: ConnectionCounts,
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        let mut counts = self.counts.0.lock().unwrap();
+        if let Some(count) = counts.get_mut(&self.address) {
+            *count -= 1;
+            if *count == 0 {
+                counts.remove(&self.address);
+            }
+        }
+    }
+}
+
+/// Returns the hook that sets up the data kept per client connection, to
+/// be passed to `HttpServer::on_connect`.
+///
+/// With `MAX_CONNECTIONS_PER_IP` it also shuts down connections from
+/// addresses that already have as many open, before a request is read.
+pub fn on_connect(config: &Config) -> impl Fn(&dyn Any, &mut Extensions) + Send + Sync + 'static {
+    let max = config.max_connections_per_ip;
+    let counts = ConnectionCounts::default();
+    move |io, extensions| {
+        extensions.insert(RequestCount::default());
+        extensions.insert(Untrusted::default());
+        if max == 0 {
+            return;
+        }
+        let stream = match io.downcast_ref::<TcpStream>() {
+            Some(stream) => stream,
+            None => return,
+        };
+        let address = match stream.peer_addr() {
+            Ok(address) => address.ip(),
+            Err(_) => return,
+        };
+        match counts.acquire(address, max) {
+            Some(guard) => {
+                extensions.insert(guard);
+            }
+            None => {
+                warn!("Refusing connection, {} already has {} open", address, max);
+                let _ = SockRef::from(stream).shutdown(Shutdown::Both);
+            }
+        }
+    }
+}
+
+/// Stops handling requests on the connection of `req`, which is closed
+/// after its response. Actix still answers the requests that were
+/// pipelined behind it, but `is_untrusted` tells them apart.
+pub fn distrust(req: &HttpRequest) {
+    if let Some(Untrusted(untrusted)) = req.conn_data::<Untrusted>() {
+        untrusted.set(true);
+    }
+}
+
+/// Whether an earlier request on the connection of `req` was rejected for
+/// its framing.
+pub fn is_untrusted(req: &HttpRequest) -> bool {
+    req.conn_data::<Untrusted>()
+        .is_some_and(|Untrusted(untrusted)| untrusted.get())
+}
+
+/// Closes keep-alive connections after `MAX_REQUESTS_PER_CONNECTION`
+/// requests, so that a single client can't hold on to a worker.
+pub async fn limit_requests(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let max = req
+        .app_data::<web::Data<Config>>()
+        .map(|config| config.max_requests_per_connection)
+        .unwrap_or(0);
+    // Connections that weren't set up by `on_connect` are never closed
+    let last = match req.conn_data::<RequestCount>() {
+        Some(RequestCount(count)) if max > 0 => {
+            count.set(count.get() + 1);
+            count.get() >= max
+        }
+        _ => false,
+    };
+
+    let mut response = next.call(req).await?;
+    if last {
+        response
+            .response_mut()
+            .head_mut()
+            .set_connection_type(ConnectionType::Close);
+    }
+    Ok(response)
+}
@@ -0,0 +1,294 @@
+use std::fmt;
+
+use actix_web::http::header::ORIGIN;
+use actix_web::{HttpRequest, HttpResponseBuilder};
+use serde::{Deserialize, Serialize, Serializer};
+
+use crate::config_file::HostCors;
+
+/// An entry of the `ALLOWED_ORIGINS` list.
+///
+/// Patterns are either `*`, an exact origin like `https://app.example.com`
+/// or a wildcard subdomain origin like `https://*.example.com:8443`.
+/// Scheme and port always have to match exactly.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum OriginPattern {
+    Any,
+    Exact(Origin),
+    Subdomain(Origin),
+}
+
+/// The scheme, host and optional port of an origin, lowercased.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Origin {
+    scheme: String,
+    host: String,
+    port: Option<String>,
+}
+
+impl Origin {
+    fn parse(origin: &str) -> Option<Self> {
+        let (scheme, authority) = origin.trim().split_once("://")?;
+        if scheme.is_empty() || authority.is_empty() || authority.contains('/') {
+            return None;
+        }
+
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) if !port.is_empty() && port.bytes().all(|b| b.is_ascii_digit()) => {
+                (host, Some(port.to_string()))
+            }
+            _ => (authority, None),
+        };
+        if host.is_empty() {
+            return None;
+        }
+
+        Some(Origin {
+            scheme: scheme.to_ascii_lowercase(),
+            host: host.to_ascii_lowercase(),
+            port,
+        })
+    }
+}
+
+impl OriginPattern {
+    pub fn parse(pattern: &str) -> Option<Self> {
+        if pattern.trim() == "*" {
+            return Some(OriginPattern::Any);
+        }
+
+        let origin = Origin::parse(pattern)?;
+        match origin.host.strip_prefix("*.") {
+            Some(apex) if !apex.is_empty() && !apex.contains('*') => {
+                Some(OriginPattern::Subdomain(Origin {
+                    host: apex.to_string(),
+                    ..origin
+                }))
+            }
+            Some(_) => None,
+            None if origin.host.contains('*') => None,
+            None => Some(OriginPattern::Exact(origin)),
+        }
+    }
+
+    /// Checks if the given `Origin` header value is allowed by this pattern.
+    pub fn matches(&self, origin: &str) -> bool {
+        let pattern = match self {
+            OriginPattern::Any => return true,
+            OriginPattern::Exact(pattern) | OriginPattern::Subdomain(pattern) => pattern,
+        };
+        let origin = match Origin::parse(origin) {
+            Some(origin) => origin,
+            None => return false,
+        };
+
+        if origin.scheme != pattern.scheme || origin.port != pattern.port {
+            return false;
+        }
+
+        match self {
+            OriginPattern::Subdomain(_) => origin
+                .host
+                .strip_suffix(&pattern.host)
+                .map(|sub| sub.len() > 1 && sub.ends_with('.'))
+                .unwrap_or(false),
+            _ => origin.host == pattern.host,
+        }
+    }
+}
+
+impl fmt::Display for OriginPattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (origin, wildcard) = match self {
+            OriginPattern::Any => return f.write_str("*"),
+            OriginPattern::Exact(origin) => (origin, ""),
+            OriginPattern::Subdomain(origin) => (origin, "*."),
+        };
+        write!(f, "{}://{}{}", origin.scheme, wildcard, origin.host)?;
+        match &origin.port {
+            Some(port) => write!(f, ":{}", port),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Serialize for OriginPattern {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl TryFrom<String> for OriginPattern {
+    type Error = String;
+
+    fn try_from(pattern: String) -> Result<Self, Self::Error> {
+        OriginPattern::parse(&pattern).ok_or_else(|| format!("invalid origin pattern {}", pattern))
+    }
+}
+
+/// Determines the value of the `Access-Control-Allow-Origin` header.
+///
+/// Without any configured patterns every origin is allowed with `*`.
+/// Otherwise the request origin is echoed back if it matches one of the
+/// patterns and `None` is returned if it doesn't.
+pub fn allow_origin(patterns: &[OriginPattern], origin: Option<&str>) -> Option<String> {
+    if patterns.is_empty() {
+        return Some("*".to_string());
+    }
+
+    let origin = match origin {
+        Some(origin) => origin,
+        None => {
+            return patterns
+                .contains(&OriginPattern::Any)
+                .then(|| "*".to_string())
+        }
+    };
+    patterns
+        .iter()
+        .any(|pattern| pattern.matches(origin))
+        .then(|| origin.to_string())
+}
+
+/// Methods announced in `Access-Control-Allow-Methods` by default.
+pub const DEFAULT_ALLOWED_METHODS: [&str; 5] = ["GET", "POST", "PUT", "DELETE", "OPTIONS"];
+
+/// Response headers exposed to scripts unless `CORS_EXPOSE_HEADERS` is set.
+pub const DEFAULT_EXPOSE_HEADERS: [&str; 8] = [
+    "Content-Disposition",
+    "Content-Length",
+    "Content-Range",
+    "ETag",
+    "Last-Modified",
+    "Link",
+    "Location",
+    "Retry-After",
+];
+
+/// The CORS behavior of the proxy.
+#[derive(Debug, Clone, Serialize)]
+pub struct CorsConfig {
+    /// Origins allowed to access proxied responses, all origins if empty.
+    pub allowed_origins: Vec<OriginPattern>,
+    /// Seconds browsers may cache preflight results.
+    pub max_age: u64,
+    /// Non-simple response headers that scripts are allowed to read.
+    pub expose_headers: Vec<String>,
+    /// Methods announced in `Access-Control-Allow-Methods`.
+    pub allowed_methods: Vec<String>,
+    /// Whether credentialed requests are allowed.
+    pub allow_credentials: bool,
+    /// Value of the `Timing-Allow-Origin` header, which lets these origins
+    /// read detailed Resource Timing data. Not sent if unset.
+    pub timing_allow_origin: Option<String>,
+    /// Whether preflights asking for Private Network Access are allowed.
+    pub allow_private_network: bool,
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        CorsConfig {
+            allowed_origins: Vec::new(),
+            max_age: 3600,
+            expose_headers: DEFAULT_EXPOSE_HEADERS
+                .iter()
+                .map(|header| header.to_string())
+                .collect(),
+            allowed_methods: DEFAULT_ALLOWED_METHODS
+                .iter()
+                .map(|method| method.to_string())
+                .collect(),
+            allow_credentials: false,
+            timing_allow_origin: None,
+            allow_private_network: false,
+        }
+    }
+}
+
+impl CorsConfig {
+    /// Applies the CORS settings of a host from the config file.
+    pub fn with_overrides(&self, host: &HostCors) -> CorsConfig {
+        CorsConfig {
+            allowed_origins: host
+                .allowed_origins
+                .clone()
+                .unwrap_or_else(|| self.allowed_origins.clone()),
+            allowed_methods: host
+                .allowed_methods
+                .clone()
+                .unwrap_or_else(|| self.allowed_methods.clone()),
+            allow_credentials: host.allow_credentials.unwrap_or(self.allow_credentials),
+            ..self.clone()
+        }
+    }
+}
+
+/// Adds the CORS headers of the proxy to a response.
+pub fn add_cors_headers(response: &mut HttpResponseBuilder, req: &HttpRequest, cors: &CorsConfig) {
+    let origin = req
+        .headers()
+        .get(ORIGIN)
+        .and_then(|origin| origin.to_str().ok());
+    let mut allow_origin = allow_origin(&cors.allowed_origins, origin);
+    let mut vary = !cors.allowed_origins.is_empty();
+    if cors.allow_credentials {
+        // Only origins on the allowlist get credentials, never any origin
+        // that asks, and browsers refuse them for the `*` origin
+        let listed = origin.filter(|origin| {
+            cors.allowed_origins
+                .iter()
+                .any(|pattern| *pattern != OriginPattern::Any && pattern.matches(origin))
+        });
+        if let Some(origin) = listed {
+            allow_origin = Some(origin.to_string());
+            response.append_header(("Access-Control-Allow-Credentials", "true"));
+        }
+        vary = true;
+    }
+
+    if let Some(allow_origin) = allow_origin {
+        response.append_header(("Access-Control-Allow-Origin", allow_origin));
+    }
+    if vary {
+        response.append_header(("Vary", "Origin"));
+    }
+    if let Some(timing_allow_origin) = &cors.timing_allow_origin {
+        // Replaces the header of the upstream, whose origins don't apply
+        response.insert_header(("Timing-Allow-Origin", timing_allow_origin.as_str()));
+    }
+    if !cors.expose_headers.is_empty() {
+        response.append_header((
+            "Access-Control-Expose-Headers",
+            cors.expose_headers.join(", "),
+        ));
+    }
+
+    response
+        .append_header((
+            "Access-Control-Allow-Methods",
+            cors.allowed_methods.join(", "),
+        ))
+        .append_header((
+            "Access-Control-Allow-Headers",
+            "Content-Type, X-HTTP-Method-Override, X-Rcp-Strip-Response-Headers",
+        ))
+        .append_header(("Access-Control-Max-Age", cors.max_age.to_string()));
+}
+
+/// Adds the CORS headers of the proxy to a preflight response, granting
+/// Private Network Access to preflights that ask for it if allowed.
+pub fn add_preflight_headers(
+    response: &mut HttpResponseBuilder,
+    req: &HttpRequest,
+    cors: &CorsConfig,
+) {
+    add_cors_headers(response, req, cors);
+    let private_network = req
+        .headers()
+        .get("Access-Control-Request-Private-Network")
+        .is_some_and(|value| value.as_bytes().eq_ignore_ascii_case(b"true"));
+    if cors.allow_private_network && private_network {
+        response.append_header(("Access-Control-Allow-Private-Network", "true"));
+    }
+}
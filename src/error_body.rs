@@ -0,0 +1,149 @@
+use actix_web::body::{BoxBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{HeaderValue, CONTENT_TYPE};
+use actix_web::http::StatusCode;
+use actix_web::middleware::Next;
+use actix_web::{web, Error};
+use serde::Serialize;
+
+use crate::config::Config;
+
+/// Placeholders an error body template may contain.
+const PLACEHOLDERS: [&str; 3] = ["status", "message", "code"];
+
+/// Marks responses that carry an upstream status and body, whose errors
+/// are passed on instead of rendered.
+pub struct UpstreamResponse;
+
+/// The template that bodies of errors answered by the proxy are rendered
+/// with, as set by `ERROR_BODY_TEMPLATE`.
+#[derive(Debug, Clone, Serialize)]
+pub struct ErrorBodyTemplate {
+    template: String,
+    content_type: String,
+}
+
+impl ErrorBodyTemplate {
+    /// Checks that `template` only uses known placeholders and, for JSON
+    /// content types, renders valid JSON.
+    pub fn new(template: &str, content_type: &str) -> Result<Self, String> {
+        let template = ErrorBodyTemplate {
+            template: template.to_string(),
+            content_type: content_type.to_string(),
+        };
+        let mut rest = template.template.as_str();
+        while let Some(start) = rest.find('{') {
+            rest = &rest[start..];
+            if let Some(name) = placeholder(rest).filter(|name| !PLACEHOLDERS.contains(name)) {
+                return Err(format!("unknown placeholder {{{}}}", name));
+            }
+            rest = &rest[1..];
+        }
+        if template.is_json() {
+            let sample = template.render(StatusCode::BAD_GATEWAY, "Sample \"message\"");
+            serde_json::from_str::<serde_json::Value>(&sample)
+                .map_err(|e| format!("doesn't render valid JSON: {}", e))?;
+        }
+        Ok(template)
+    }
+
+    pub fn content_type(&self) -> &str {
+        &self.content_type
+    }
+
+    fn is_json(&self) -> bool {
+        self.content_type.to_ascii_lowercase().contains("json")
+    }
+
+    /// Renders the body of an error. The message is escaped for JSON
+    /// content types, so templates put quotes around `{message}`.
+    pub fn render(&self, status: StatusCode, message: &str) -> String {
+        let message = if self.is_json() {
+            let quoted = serde_json::to_string(message).unwrap_or_default();
+            quoted[1..quoted.len() - 1].to_string()
+        } else {
+            message.to_string()
+        };
+        let mut rendered = String::with_capacity(self.template.len());
+        let mut rest = self.template.as_str();
+        while let Some(start) = rest.find('{') {
+            rendered.push_str(&rest[..start]);
+            rest = &rest[start..];
+            match placeholder(rest) {
+                Some("status") => rendered.push_str(&status.as_u16().to_string()),
+                Some("message") => rendered.push_str(&message),
+                Some("code") => rendered.push_str(&code(status)),
+                // Other braces are kept, so that JSON needs no escaping
+                _ => {
+                    rendered.push('{');
+                    rest = &rest[1..];
+                    continue;
+                }
+            }
+            rest = &rest[rest.find('}').unwrap_or(0) + 1..];
+        }
+        rendered.push_str(rest);
+        rendered
+    }
+}
+
+/// The name of the placeholder `rest` starts with, like `status` for
+/// `{status}`.
+fn placeholder(rest: &str) -> Option<&str> {
+    let end = rest.find('}')?;
+    Some(&rest[1..end])
+        .filter(|name| !name.is_empty() && name.chars().all(|c| c.is_ascii_lowercase() || c == '_'))
+}
+
+/// A machine readable name for a status, like `bad_gateway`.
+fn code(status: StatusCode) -> String {
+    status
+        .canonical_reason()
+        .unwrap_or("error")
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric() || *c == ' ' || *c == '-')
+        .map(|c| match c {
+            ' ' | '-' => '_',
+            c => c.to_ascii_lowercase(),
+        })
+        .collect()
+}
+
+/// Renders the bodies of errors answered by the proxy itself with
+/// `ERROR_BODY_TEMPLATE`, taking their plain text body as the message.
+/// Upstream errors and streamed bodies are passed on unchanged.
+pub async fn render_errors(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, Error> {
+    let template = req
+        .app_data::<web::Data<Config>>()
+        .and_then(|config| config.error_body_template.clone());
+    let response = next.call(req).await?;
+    let template = match template {
+        Some(template)
+            if (response.status().is_client_error() || response.status().is_server_error())
+                && !response
+                    .response()
+                    .extensions()
+                    .contains::<UpstreamResponse>() =>
+        {
+            template
+        }
+        _ => return Ok(response.map_into_boxed_body()),
+    };
+
+    let (req, response) = response.into_parts();
+    let (mut response, body) = response.into_parts();
+    let message = match body.try_into_bytes() {
+        Ok(message) => message,
+        Err(body) => {
+            return Ok(ServiceResponse::new(req, response.set_body(body)).map_into_boxed_body())
+        }
+    };
+    let body = template.render(response.status(), &String::from_utf8_lossy(&message));
+    if let Ok(content_type) = HeaderValue::from_str(template.content_type()) {
+        response.headers_mut().insert(CONTENT_TYPE, content_type);
+    }
+    Ok(ServiceResponse::new(req, response.set_body(body)).map_into_boxed_body())
+}
@@ -0,0 +1,166 @@
+use actix_web::http::header::{HeaderMap, CONTENT_LENGTH, HOST, TRANSFER_ENCODING};
+use actix_web::{HttpRequest, HttpResponseBuilder};
+use reqwest::header::{HeaderName, HeaderValue};
+
+/// Hop-by-hop headers that only apply to a single connection, plus the
+/// headers that reqwest derives from the target URL and body.
+///
+/// `Expect: 100-continue` is answered by actix before the body is read, and
+/// the upstream request is only sent once the whole body is there, so the
+/// expectation is already met and not forwarded. `X-HTTP-Method-Override`
+/// and `X-Rcp-Strip-Response-Headers` are consumed by the proxy itself.
+const SKIPPED_REQUEST_HEADERS: [&str; 14] = [
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "proxy-connection",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+    "host",
+    "content-length",
+    "expect",
+    "x-http-method-override",
+    STRIP_RESPONSE_HEADERS,
+];
+
+/// Request header listing upstream response headers that the client
+/// should not get.
+const STRIP_RESPONSE_HEADERS: &str = "x-rcp-strip-response-headers";
+
+/// Hop-by-hop headers of upstream responses, plus the framing headers that
+/// actix sets for the streamed body.
+const SKIPPED_RESPONSE_HEADERS: [&str; 10] = [
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "proxy-connection",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+    "content-length",
+];
+
+/// Lowercase header names listed in a header like `Connection`, whose
+/// names are hop-by-hop as well.
+fn connection_headers<'a>(values: impl Iterator<Item = &'a [u8]>) -> Vec<String> {
+    values
+        .filter_map(|value| std::str::from_utf8(value).ok())
+        .flat_map(|value| value.split(','))
+        .map(|name| name.trim().to_ascii_lowercase())
+        .filter(|name| !name.is_empty())
+        .collect()
+}
+
+/// Copies the headers of an incoming request for the upstream request.
+///
+/// Every value of a repeated header is forwarded in its original order.
+pub fn forward_request_headers(headers: &HeaderMap) -> reqwest::header::HeaderMap {
+    let connection =
+        connection_headers(headers.get_all("connection").map(|value| value.as_bytes()));
+    let mut forwarded = reqwest::header::HeaderMap::new();
+
+    for (name, value) in headers.iter() {
+        let name = name.as_str();
+        if SKIPPED_REQUEST_HEADERS.contains(&name) || connection.iter().any(|c| c == name) {
+            continue;
+        }
+
+        if let (Ok(name), Ok(value)) = (
+            HeaderName::from_bytes(name.as_bytes()),
+            HeaderValue::from_bytes(value.as_bytes()),
+        ) {
+            forwarded.append(name, value);
+        }
+    }
+
+    forwarded
+}
+
+/// Copies the headers of an upstream response onto the client response.
+///
+/// CORS headers of the upstream are dropped, the proxy sets its own.
+/// Repeated headers are appended one value at a time in the order the
+/// upstream sent them, never merged or deduplicated, since the order of
+/// `Set-Cookie` headers decides which cookie wins.
+///
+/// Headers named in the `X-Rcp-Strip-Response-Headers` of `request` are
+/// dropped as well.
+pub fn forward_response_headers(
+    request: &HeaderMap,
+    headers: &reqwest::header::HeaderMap,
+    response: &mut HttpResponseBuilder,
+) {
+    let connection = connection_headers(
+        headers
+            .get_all("connection")
+            .iter()
+            .map(|value| value.as_bytes()),
+    );
+    let stripped = connection_headers(
+        request
+            .get_all(STRIP_RESPONSE_HEADERS)
+            .map(|value| value.as_bytes()),
+    );
+
+    for (name, value) in headers.iter() {
+        let name = name.as_str();
+        if SKIPPED_RESPONSE_HEADERS.contains(&name)
+            || name.starts_with("access-control-")
+            || connection.iter().any(|c| c == name)
+            || stripped.iter().any(|s| s == name)
+        {
+            continue;
+        }
+
+        response.append_header((name, value.as_bytes()));
+    }
+}
+
+/// The protocol a request asks to upgrade its connection to, if its
+/// `Connection` header lists `upgrade`.
+pub fn requested_upgrade(headers: &HeaderMap) -> Option<String> {
+    let connection =
+        connection_headers(headers.get_all("connection").map(|value| value.as_bytes()));
+    if !connection.iter().any(|name| name == "upgrade") {
+        return None;
+    }
+    headers
+        .get("upgrade")
+        .and_then(|upgrade| upgrade.to_str().ok())
+        .map(|upgrade| upgrade.trim().to_string())
+        .filter(|upgrade| !upgrade.is_empty())
+}
+
+/// Whether a request gives the length of its body with both
+/// `Content-Length` and `Transfer-Encoding`.
+pub fn has_ambiguous_framing(headers: &HeaderMap) -> bool {
+    headers.contains_key(CONTENT_LENGTH) && headers.contains_key(TRANSFER_ENCODING)
+}
+
+/// Whether the host a request was sent to is one of `hosts`, which may
+/// include a port. Entries without a port match the host on any port.
+pub fn host_allowed(req: &HttpRequest, hosts: &[String]) -> bool {
+    let authority = match req
+        .uri()
+        .authority()
+        .map(|authority| authority.as_str())
+        .or_else(|| req.headers().get(HOST).and_then(|host| host.to_str().ok()))
+    {
+        Some(authority) => authority.to_ascii_lowercase(),
+        None => return false,
+    };
+    let host = match authority.rsplit_once(':') {
+        Some((host, port)) if !port.contains(']') => host,
+        _ => authority.as_str(),
+    };
+
+    hosts.iter().any(|expected| {
+        let expected = expected.to_ascii_lowercase();
+        expected == authority || expected == host
+    })
+}
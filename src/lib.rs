@@ -0,0 +1,182 @@
+pub mod access_log;
+pub mod admin;
+pub mod batch;
+pub mod cache;
+pub mod capture;
+pub mod client;
+pub mod client_ip;
+pub mod clock;
+pub mod config;
+pub mod config_file;
+pub mod connection;
+pub mod cors;
+pub mod error_body;
+pub mod headers;
+pub mod metrics;
+pub mod proxy;
+pub mod rate_limit;
+pub mod recovery;
+#[cfg(feature = "rewrite")]
+pub mod rewrite;
+pub mod stream;
+pub mod upstream_error;
+
+use std::io;
+use std::sync::Arc;
+
+use actix_web::http::Method;
+use actix_web::{middleware, web, Resource};
+use log::{info, warn};
+
+use access_log::AccessLog;
+use admin::{CanaryCheck, DrainState};
+use cache::ResponseCache;
+use capture::DebugCapture;
+use client::UpstreamClient;
+use clock::{Clock, SystemClock};
+use config::Config;
+use metrics::Metrics;
+use rate_limit::{ConcurrencyLimiter, RateLimiter};
+
+/// State shared by all workers of the proxy.
+#[derive(Clone)]
+pub struct AppState {
+    pub config: web::Data<Config>,
+    pub metrics: web::Data<Metrics>,
+    pub drain: web::Data<DrainState>,
+    pub canary: web::Data<CanaryCheck>,
+    pub rate_limiter: web::Data<RateLimiter>,
+    pub concurrency: web::Data<ConcurrencyLimiter>,
+    pub client: web::Data<UpstreamClient>,
+    pub cache: web::Data<ResponseCache>,
+    pub access_log: web::Data<AccessLog>,
+    pub capture: web::Data<DebugCapture>,
+}
+
+impl AppState {
+    pub fn new(config: Config) -> Self {
+        Self::with_clock(config, Arc::new(SystemClock))
+    }
+
+    /// Creates the state with rate limits, cache lifetimes and canary
+    /// results measured by `clock`.
+    pub fn with_clock(config: Config, clock: Arc<dyn Clock>) -> Self {
+        #[cfg(not(feature = "rewrite"))]
+        if config.rewrite_html_urls {
+            warn!("Rewriting URLs requires the rewrite feature, not rewriting them");
+        }
+
+        let access_log = AccessLog::new(&config);
+        let capture = DebugCapture::new(&config);
+        let metrics = Metrics::new();
+        let client = UpstreamClient::with_clock(&config, &metrics, clock.clone());
+        let cache = ResponseCache::new(
+            config.cache_max_entries,
+            config.cache_max_bytes,
+            config.cache_stale_if_error,
+            clock.clone(),
+        );
+        AppState {
+            config: web::Data::new(config),
+            metrics: web::Data::new(metrics),
+            drain: web::Data::new(DrainState::default()),
+            canary: web::Data::new(CanaryCheck::new(clock.clone())),
+            rate_limiter: web::Data::new(RateLimiter::new(clock)),
+            concurrency: web::Data::new(ConcurrencyLimiter::default()),
+            client: web::Data::new(client),
+            cache: web::Data::new(cache),
+            access_log: web::Data::new(access_log),
+            capture: web::Data::new(capture),
+        }
+    }
+
+    /// Fetches `STARTUP_CHECK_URL` once to verify outbound connectivity.
+    /// Failures are logged, and returned only if `STARTUP_CHECK_FATAL` is set.
+    pub async fn startup_check(&self) -> io::Result<()> {
+        let url = match &self.config.startup_check_url {
+            Some(url) => url,
+            None => return Ok(()),
+        };
+
+        let timeout = self.config.startup_check_timeout;
+        let result = self
+            .client
+            .send(url.starts_with("https://"), |client| {
+                client.get(url).timeout(timeout)
+            })
+            .await;
+        let error = match result {
+            Ok(response) => {
+                info!(
+                    "Startup check of {} succeeded with status {}",
+                    url,
+                    response.status()
+                );
+                return Ok(());
+            }
+            Err(e) => e,
+        };
+
+        warn!(
+            "Startup check of {} failed: {}",
+            url,
+            upstream_error::describe(&error)
+        );
+        if self.config.startup_check_fatal {
+            return Err(io::Error::other(format!(
+                "Startup check of {} failed: {}",
+                url, error
+            )));
+        }
+        Ok(())
+    }
+
+    /// Registers the shared state and all routes of the proxy on an app.
+    pub fn configure(&self, cfg: &mut web::ServiceConfig) {
+        cfg.app_data(self.config.clone())
+            .app_data(self.metrics.clone())
+            .app_data(self.drain.clone())
+            .app_data(self.canary.clone())
+            .app_data(self.rate_limiter.clone())
+            .app_data(self.concurrency.clone())
+            .app_data(self.client.clone())
+            .app_data(self.cache.clone())
+            .app_data(self.access_log.clone())
+            .app_data(self.capture.clone())
+            .service(
+                // All endpoints count towards the requests of a connection
+                web::scope("")
+                    .wrap(middleware::from_fn(recovery::catch_panics))
+                    .wrap(middleware::from_fn(error_body::render_errors))
+                    .wrap(middleware::from_fn(connection::limit_requests))
+                    .route("/metrics", web::get().to(metrics::metrics_endpoint))
+                    .route("/robots.txt", web::get().to(proxy::robots_txt))
+                    .route("/readyz", web::get().to(admin::readyz))
+                    .route("/admin/drain", web::post().to(admin::drain))
+                    .route("/admin/config", web::get().to(admin::show_config))
+                    .route("/admin/cache", web::get().to(admin::cache_entries))
+                    .route("/admin/cache", web::delete().to(admin::purge_cache))
+                    .configure(|cfg| self.batch_resource(cfg))
+                    .service(self.proxy_resource()),
+            );
+    }
+
+    /// The `/batch` endpoint, only served with `MAX_BATCH_SIZE` set.
+    fn batch_resource(&self, cfg: &mut web::ServiceConfig) {
+        if self.config.max_batch_size > 0 {
+            cfg.service(
+                web::resource("/batch")
+                    .route(web::post().to(batch::batch))
+                    .route(web::method(Method::OPTIONS).to(batch::preflight)),
+            );
+        }
+    }
+
+    /// The catch-all resource that proxies the methods in `ALLOWED_METHODS`.
+    fn proxy_resource(&self) -> Resource {
+        self.config.allowed_methods.iter().fold(
+            web::resource("/{url:.+}").default_service(web::to(proxy::method_not_allowed)),
+            |resource, method| resource.route(web::method(method.clone()).to(proxy::cors_proxy)),
+        )
+    }
+}
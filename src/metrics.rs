@@ -0,0 +1,189 @@
+use actix_web::{web, HttpResponse};
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, IntCounter, IntCounterVec, Opts, Registry, TextEncoder,
+};
+
+/// Top-level media types tracked as their own label value, everything
+/// else is counted as `other`.
+const CONTENT_TYPES: [&str; 9] = [
+    "application",
+    "audio",
+    "font",
+    "image",
+    "message",
+    "model",
+    "multipart",
+    "text",
+    "video",
+];
+
+/// Metrics of the redirects followed by the upstream client.
+#[derive(Clone)]
+pub struct RedirectMetrics {
+    /// Redirects that were followed.
+    pub followed: IntCounter,
+    /// Redirects that were passed to the client because their target
+    /// failed validation.
+    pub blocked: IntCounter,
+    /// Number of redirects followed per upstream request.
+    pub chain_length: Histogram,
+}
+
+/// Prometheus metrics collected by the proxy.
+pub struct Metrics {
+    registry: Registry,
+    /// Size of the proxied response bodies in bytes.
+    pub response_body_bytes: Histogram,
+    /// Proxied responses by top-level content type.
+    pub responses_by_content_type: IntCounterVec,
+    /// Requests whose body could not be read.
+    pub request_body_errors: IntCounter,
+    /// Requests by how the cache answered them.
+    pub cache_requests: IntCounterVec,
+    /// Requests answered from the cache after waiting for another request.
+    pub cache_coalesced: IntCounter,
+    /// Requests whose handler panicked.
+    pub panics: IntCounter,
+    pub redirects: RedirectMetrics,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let response_body_bytes = Histogram::with_opts(
+            HistogramOpts::new(
+                "rcp_response_body_bytes",
+                "Size of proxied response bodies in bytes",
+            )
+            .buckets(prometheus::exponential_buckets(64.0, 4.0, 10).unwrap()),
+        )
+        .unwrap();
+        let responses_by_content_type = IntCounterVec::new(
+            Opts::new(
+                "rcp_responses_by_content_type_total",
+                "Proxied responses by top-level content type",
+            ),
+            &["content_type"],
+        )
+        .unwrap();
+        let request_body_errors = IntCounter::new(
+            "rcp_request_body_errors_total",
+            "Requests whose body could not be read",
+        )
+        .unwrap();
+        let cache_requests = IntCounterVec::new(
+            Opts::new(
+                "rcp_cache_requests_total",
+                "Requests by cache result: hit, miss, stale or bypass",
+            ),
+            &["result"],
+        )
+        .unwrap();
+        let cache_coalesced = IntCounter::new(
+            "rcp_cache_coalesced_requests_total",
+            "Requests answered from the cache after waiting for a concurrent request",
+        )
+        .unwrap();
+        let panics =
+            IntCounter::new("rcp_panics_total", "Requests whose handler panicked").unwrap();
+        let redirects = RedirectMetrics {
+            followed: IntCounter::new(
+                "rcp_redirects_followed_total",
+                "Upstream redirects that were followed",
+            )
+            .unwrap(),
+            blocked: IntCounter::new(
+                "rcp_redirects_blocked_total",
+                "Upstream redirects that were not followed because their target is not allowed",
+            )
+            .unwrap(),
+            chain_length: Histogram::with_opts(
+                HistogramOpts::new(
+                    "rcp_redirect_chain_length",
+                    "Number of redirects followed per upstream request",
+                )
+                .buckets(vec![0.0, 1.0, 2.0, 3.0, 5.0, 10.0]),
+            )
+            .unwrap(),
+        };
+
+        let registry = Registry::new();
+        registry
+            .register(Box::new(response_body_bytes.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(responses_by_content_type.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(request_body_errors.clone()))
+            .unwrap();
+        registry.register(Box::new(cache_requests.clone())).unwrap();
+        registry
+            .register(Box::new(cache_coalesced.clone()))
+            .unwrap();
+        registry.register(Box::new(panics.clone())).unwrap();
+        registry
+            .register(Box::new(redirects.followed.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(redirects.blocked.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(redirects.chain_length.clone()))
+            .unwrap();
+
+        Metrics {
+            registry,
+            response_body_bytes,
+            responses_by_content_type,
+            request_body_errors,
+            cache_requests,
+            cache_coalesced,
+            panics,
+            redirects,
+        }
+    }
+
+    /// Counts a response by the top-level type of its `Content-Type`.
+    pub fn observe_content_type(&self, content_type: &str) {
+        let top_level = content_type
+            .split('/')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase();
+        let label = CONTENT_TYPES
+            .iter()
+            .find(|known| **known == top_level)
+            .copied()
+            .unwrap_or("other");
+        self.responses_by_content_type
+            .with_label_values(&[label])
+            .inc();
+    }
+
+    /// Counts a request by how the cache answered it.
+    pub fn observe_cache(&self, result: &str) {
+        self.cache_requests.with_label_values(&[result]).inc();
+    }
+
+    /// Renders all metrics in the Prometheus text format.
+    pub fn render(&self) -> String {
+        let mut buffer = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .unwrap();
+        String::from_utf8(buffer).unwrap()
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub async fn metrics_endpoint(metrics: web::Data<Metrics>) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(metrics.render())
+}
@@ -0,0 +1,1078 @@
+use std::time::{Duration, Instant};
+
+use actix_web::body::{self, SizedStream};
+use actix_web::error::PayloadError;
+use actix_web::http::header::{HeaderName, HeaderValue, ACCESS_CONTROL_EXPOSE_HEADERS};
+use actix_web::http::StatusCode;
+use actix_web::rt::time;
+use actix_web::{web, HttpMessage, HttpRequest, HttpResponse, HttpResponseBuilder, Result};
+use futures_util::StreamExt;
+use log::{info, warn};
+use percent_encoding::percent_decode_str;
+use reqwest::Client;
+
+use crate::access_log::AccessLog;
+use crate::admin::DrainState;
+use crate::cache::{self, CachedResponse, ResponseCache};
+use crate::capture::DebugCapture;
+use crate::client::{SendError, UpstreamClient};
+use crate::client_ip;
+use crate::config::{redact_userinfo, AmbiguousFraming, Config, OptionsMode, PROXIED_SCHEMES};
+use crate::connection;
+use crate::cors::{self, CorsConfig};
+use crate::error_body::UpstreamResponse;
+use crate::headers;
+use crate::metrics::Metrics;
+use crate::rate_limit::{ConcurrencyLimiter, RateLimiter};
+#[cfg(feature = "rewrite")]
+use crate::rewrite::{self, DocumentKind};
+use crate::stream::{
+    BodyLimitStream, CachingStream, CountingStream, DecompressStream, IdleTimeoutStream,
+    PlaceholderStream, TeeStream,
+};
+use crate::upstream_error::{self, UpstreamErrorKind};
+
+/// Removes the given parameters from a raw query string, keeping the
+/// remaining parameters in their original order and encoding.
+fn strip_query_params(query: &str, names: &[String]) -> String {
+    query
+        .split('&')
+        .filter(|pair| {
+            !pair.is_empty()
+                && !form_urlencoded::parse(pair.as_bytes())
+                    .next()
+                    .map(|(key, _)| names.iter().any(|name| *name == key))
+                    .unwrap_or(false)
+        })
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+/// Collapses runs of slashes in an upstream path into a single slash.
+fn collapse_slashes(path: &str) -> String {
+    let mut collapsed = String::with_capacity(path.len());
+    for c in path.chars() {
+        if c != '/' || !collapsed.ends_with('/') {
+            collapsed.push(c);
+        }
+    }
+    collapsed
+}
+
+/// Inserts `prefix` in front of an upstream path, with exactly one slash
+/// around it however either is written.
+fn prefix_path(prefix: &str, path: &str) -> String {
+    match prefix.trim_matches('/') {
+        "" => path.to_string(),
+        prefix => format!("/{}/{}", prefix, path.trim_start_matches('/')),
+    }
+}
+
+/// Starts the client response from the status and headers of an upstream
+/// response, adding the CORS headers. Also returns the content type.
+fn build_response(
+    req: &HttpRequest,
+    config: &Config,
+    cors: &CorsConfig,
+    metrics: &Metrics,
+    status: StatusCode,
+    upstream_headers: &reqwest::header::HeaderMap,
+) -> (HttpResponseBuilder, String) {
+    // Get the Content-Type header from the response
+    let content_type = upstream_headers
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|header| header.to_str().ok())
+        .unwrap_or("application/json")
+        .to_string();
+
+    metrics.observe_content_type(&content_type);
+
+    let mut builder = HttpResponse::build(status);
+    builder.extensions_mut().insert(UpstreamResponse);
+    headers::forward_response_headers(req.headers(), upstream_headers, &mut builder);
+    cors::add_cors_headers(&mut builder, req, cors);
+    builder.insert_header(("Content-Type", content_type.as_str()));
+    if config.add_noindex {
+        builder.insert_header(("X-Robots-Tag", "noindex"));
+    }
+
+    (builder, content_type)
+}
+
+/// Answers a request with a response from the cache, `x_cache` telling
+/// the client whether it is fresh.
+fn cached_response(
+    req: &HttpRequest,
+    config: &Config,
+    cors: &CorsConfig,
+    metrics: &Metrics,
+    cached: CachedResponse,
+    x_cache: &'static str,
+) -> HttpResponse {
+    let status = StatusCode::from_u16(cached.status).unwrap_or(StatusCode::OK);
+    let (mut builder, _) = build_response(req, config, cors, metrics, status, &cached.headers);
+    builder
+        .insert_header(("Age", cached.age.as_secs().to_string()))
+        .insert_header(("X-Cache", x_cache));
+    builder.body(cached.body)
+}
+
+/// Whether a Content-Type has the given media type, ignoring parameters.
+fn has_media_type(content_type: &str, media_type: &str) -> bool {
+    content_type
+        .split(';')
+        .next()
+        .map(|mime| mime.trim().eq_ignore_ascii_case(media_type))
+        .unwrap_or(false)
+}
+
+fn is_event_stream(content_type: &str) -> bool {
+    has_media_type(content_type, "text/event-stream")
+}
+
+/// Whether an upstream response body is gzip encoded.
+fn is_gzip(headers: &reqwest::header::HeaderMap) -> bool {
+    headers
+        .get(reqwest::header::CONTENT_ENCODING)
+        .and_then(|encoding| encoding.to_str().ok())
+        .map(|encoding| {
+            let encoding = encoding.trim();
+            encoding.eq_ignore_ascii_case("gzip") || encoding.eq_ignore_ascii_case("x-gzip")
+        })
+        .unwrap_or(false)
+}
+
+fn is_json(content_type: &str) -> bool {
+    has_media_type(content_type, "application/json")
+}
+
+/// Whether a response with the status may have a body.
+fn body_allowed(status: StatusCode) -> bool {
+    !status.is_informational()
+        && status != StatusCode::NO_CONTENT
+        && status != StatusCode::NOT_MODIFIED
+}
+
+/// Asks crawlers not to index anything served through the proxy.
+pub async fn robots_txt() -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/plain; charset=utf-8")
+        .body("User-agent: *\nDisallow: /\n")
+}
+
+/// Answers requests with methods that aren't in `ALLOWED_METHODS`.
+pub async fn method_not_allowed(req: HttpRequest, config: web::Data<Config>) -> HttpResponse {
+    not_allowed(&config, req.method().as_str())
+}
+
+/// Answers a request for `method` with `405`, naming the allowed methods.
+fn not_allowed(config: &Config, method: &str) -> HttpResponse {
+    warn!("Method not allowed: {}", method);
+    let allowed: Vec<_> = config
+        .allowed_methods
+        .iter()
+        .map(|method| method.as_str())
+        .collect();
+    HttpResponse::MethodNotAllowed()
+        .insert_header(("Allow", allowed.join(", ")))
+        .body(format!("Method {} is not allowed", method))
+}
+
+/// Answers a request whose body could not be read, for example because
+/// the client disconnected or sent malformed chunked encoding.
+pub(crate) fn payload_error(metrics: &Metrics, error: PayloadError) -> HttpResponse {
+    warn!("Failed to read request body: {}", error);
+    metrics.request_body_errors.inc();
+    match error {
+        // The rest of the body isn't read, so the connection can't be reused
+        PayloadError::Overflow => HttpResponse::PayloadTooLarge()
+            .force_close()
+            .body("Request body too large"),
+        _ => HttpResponse::BadRequest().body(format!("Failed to read request body: {}", error)),
+    }
+}
+
+/// Reads the request body, failing with `PayloadError::Overflow` once it
+/// exceeds `MAX_BODY_SIZE_BYTES`. A larger Content-Length fails before
+/// any of the body is read. Chunked bodies without a Content-Length are
+/// counted while they arrive, so the limit holds for them as well.
+pub(crate) async fn read_body(
+    req: &HttpRequest,
+    payload: web::Payload,
+    limit: usize,
+) -> Result<web::Bytes, PayloadError> {
+    let declared = req
+        .headers()
+        .get(actix_web::http::header::CONTENT_LENGTH)
+        .and_then(|length| length.to_str().ok())
+        .and_then(|length| length.parse::<usize>().ok());
+    if declared.is_some_and(|length| length > limit) {
+        return Err(PayloadError::Overflow);
+    }
+
+    let mut body = web::BytesMut::new();
+    let mut payload = BodyLimitStream::new(payload, limit);
+    while let Some(chunk) = payload.next().await {
+        body.extend_from_slice(&chunk?);
+    }
+    Ok(body.freeze())
+}
+
+/// What decides where and how a request is forwarded. Client requests
+/// supply their own, batch entries describe one each, and everything
+/// about the client, such as its address, comes from the request it sent.
+pub(crate) struct ProxyRequest {
+    pub method: actix_web::http::Method,
+    /// The path that names a route or the target URL
+    pub path: String,
+    /// The target URL given in the path, if any
+    pub target: Option<String>,
+    pub query: String,
+    pub headers: actix_web::http::header::HeaderMap,
+}
+
+impl ProxyRequest {
+    fn from_request(req: &HttpRequest) -> Self {
+        ProxyRequest {
+            method: req.method().clone(),
+            path: req.path().to_string(),
+            target: req.match_info().get("url").map(str::to_string),
+            query: req.query_string().to_string(),
+            headers: req.headers().clone(),
+        }
+    }
+}
+
+/// Time from sending a request upstream until its response headers arrived,
+/// kept in the extensions of the request.
+struct UpstreamTime(Duration);
+
+/// Adds a `Server-Timing` header that splits `total` into the time spent
+/// on the upstream, if it was contacted, and in the proxy, and lets
+/// scripts read it.
+fn add_server_timing(response: &mut HttpResponse, upstream: Option<Duration>, total: Duration) {
+    let milliseconds = |duration: Duration| duration.as_secs_f64() * 1000.0;
+    let mut timing = String::new();
+    if let Some(upstream) = upstream {
+        timing.push_str(&format!("upstream;dur={:.1}, ", milliseconds(upstream)));
+    }
+    let proxy = total.saturating_sub(upstream.unwrap_or_default());
+    timing.push_str(&format!("proxy;dur={:.1}", milliseconds(proxy)));
+
+    let headers = response.headers_mut();
+    if let Ok(timing) = HeaderValue::from_str(&timing) {
+        headers.insert(HeaderName::from_static("server-timing"), timing);
+    }
+    let exposed = match headers
+        .get(ACCESS_CONTROL_EXPOSE_HEADERS)
+        .and_then(|exposed| exposed.to_str().ok())
+    {
+        Some(exposed)
+            if exposed
+                .split(',')
+                .any(|name| name.trim().eq_ignore_ascii_case("server-timing")) =>
+        {
+            return;
+        }
+        Some(exposed) => format!("{}, Server-Timing", exposed),
+        None => "Server-Timing".to_string(),
+    };
+    if let Ok(exposed) = HeaderValue::from_str(&exposed) {
+        headers.insert(ACCESS_CONTROL_EXPOSE_HEADERS, exposed);
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub async fn cors_proxy(
+    req: HttpRequest,
+    payload: web::Payload,
+    config: web::Data<Config>,
+    metrics: web::Data<Metrics>,
+    drain: web::Data<DrainState>,
+    rate_limiter: web::Data<RateLimiter>,
+    concurrency: web::Data<ConcurrencyLimiter>,
+    client: web::Data<UpstreamClient>,
+    cache: web::Data<ResponseCache>,
+    access_log: web::Data<AccessLog>,
+    capture: web::Data<DebugCapture>,
+) -> Result<HttpResponse> {
+    let started = Instant::now();
+    let title_case_headers = config.title_case_headers;
+    let send_server_timing = config.send_server_timing;
+
+    // Upgraded connections can't be relayed, and the body of an upgrade
+    // request is the rest of the connection, so it isn't read
+    if let Some(protocol) = headers::requested_upgrade(req.headers()) {
+        warn!("Not implemented: upgrade to {}", protocol);
+        let mut response = HttpResponse::NotImplemented();
+        cors::add_cors_headers(&mut response, &req, &config.cors_for(None));
+        let response = response.force_close().body(format!(
+            "Upgrading the connection to {} is not supported",
+            protocol
+        ));
+        access_log.record(&req, response.status(), started.elapsed());
+        return Ok(response);
+    }
+    // Actix reads such bodies by their Transfer-Encoding, and the framing
+    // headers aren't forwarded, so normalizing needs nothing else
+    let framing_error = if config.ambiguous_framing == AmbiguousFraming::Reject
+        && headers::has_ambiguous_framing(req.headers())
+    {
+        connection::distrust(&req);
+        Some("Both Content-Length and Transfer-Encoding are set")
+    } else if connection::is_untrusted(&req) {
+        Some("Request follows a request with ambiguous framing")
+    } else {
+        None
+    };
+    if let Some(message) = framing_error {
+        warn!("Bad request: {}", message);
+        let mut response = HttpResponse::BadRequest();
+        cors::add_cors_headers(&mut response, &req, &config.cors_for(None));
+        let response = response.force_close().body(message);
+        access_log.record(&req, response.status(), started.elapsed());
+        return Ok(response);
+    }
+
+    let mut response = match read_body(&req, payload, config.max_body_size).await {
+        Ok(body) => {
+            forward(
+                &req,
+                ProxyRequest::from_request(&req),
+                body,
+                config,
+                metrics,
+                drain,
+                rate_limiter,
+                concurrency,
+                client,
+                cache,
+                capture,
+            )
+            .await?
+        }
+        Err(e) => payload_error(&metrics, e),
+    };
+    if title_case_headers {
+        response.head_mut().set_camel_case_headers(true);
+    }
+    if send_server_timing {
+        let upstream = req.extensions().get::<UpstreamTime>().map(|time| time.0);
+        add_server_timing(&mut response, upstream, started.elapsed());
+    }
+    access_log.record(&req, response.status(), started.elapsed());
+    Ok(response)
+}
+
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn forward(
+    req: &HttpRequest,
+    incoming: ProxyRequest,
+    body: web::Bytes,
+    config: web::Data<Config>,
+    metrics: web::Data<Metrics>,
+    drain: web::Data<DrainState>,
+    rate_limiter: web::Data<RateLimiter>,
+    concurrency: web::Data<ConcurrencyLimiter>,
+    client: web::Data<UpstreamClient>,
+    cache: web::Data<ResponseCache>,
+    capture: web::Data<DebugCapture>,
+) -> Result<HttpResponse> {
+    if !config.client_ip_allowlist.is_empty() {
+        let address =
+            client_ip::client_ip(req, config.trust_forwarded_for, &config.trusted_proxies);
+        if !address
+            .is_some_and(|address| client_ip::is_allowed(&config.client_ip_allowlist, address))
+        {
+            warn!("Forbidden: client address {:?} is not allowed", address);
+            return Ok(HttpResponse::Forbidden().body("Client address not allowed"));
+        }
+    }
+
+    // The Host header is never forwarded, but requests for other hosts
+    // indicate spoofing or a misrouted request
+    if !config.enforce_host.is_empty() && !headers::host_allowed(req, &config.enforce_host) {
+        warn!(
+            "Misdirected request for host {:?}",
+            req.headers().get(actix_web::http::header::HOST)
+        );
+        return Ok(HttpResponse::MisdirectedRequest().body("Unexpected Host header"));
+    }
+
+    // Browsers send an Origin with every cross-origin request
+    if config.require_origin
+        && incoming.method != actix_web::http::Method::OPTIONS
+        && !req.headers().contains_key(actix_web::http::header::ORIGIN)
+    {
+        warn!("Forbidden: request without Origin header");
+        return Ok(HttpResponse::Forbidden().body("Origin header required"));
+    }
+
+    if config.drain_reject_requests && drain.is_draining() {
+        warn!("Rejecting request while draining");
+        return Ok(HttpResponse::ServiceUnavailable().body("Proxy is shutting down"));
+    }
+
+    // Keep a single client from taking all slots of the global limit
+    let _client_permit =
+        match client_ip::client_ip(req, config.trust_forwarded_for, &config.trusted_proxies) {
+            Some(address) => {
+                match concurrency.try_acquire_client(address, config.max_concurrent_per_client) {
+                    Some(permit) => Some(permit),
+                    None => {
+                        warn!(
+                            "Rejecting request, too many requests in flight for {}",
+                            address
+                        );
+                        return Ok(HttpResponse::TooManyRequests()
+                            .append_header(("Retry-After", config.overload_retry_after.to_string()))
+                            .body("Too many concurrent requests"));
+                    }
+                }
+            }
+            None => None,
+        };
+
+    // Shed load instead of queueing once too many requests are in flight
+    let _permit = match concurrency.try_acquire(config.max_concurrent_requests) {
+        Some(permit) => permit,
+        None => {
+            warn!("Rejecting request, too many requests in flight");
+            let retry_after =
+                config.overload_retry_after + fastrand::u64(0..=config.retry_after_jitter);
+            return Ok(HttpResponse::ServiceUnavailable()
+                .append_header(("Retry-After", retry_after.to_string()))
+                .body("Proxy is overloaded"));
+        }
+    };
+
+    // Forward paths under a configured route to its upstream, requests
+    // that match no route name the full target URL
+    let route = match config.file.route(&incoming.path) {
+        Some(Ok(url)) => Some(url),
+        Some(Err(e)) => {
+            warn!("Bad request: {} - {}", e, incoming.path);
+            return Ok(HttpResponse::BadRequest().body("Invalid path"));
+        }
+        None => None,
+    };
+    let full_url = route.is_none();
+    if full_url && !config.file.full_url_targets {
+        warn!("Not found: no route for {}", incoming.path);
+        return Ok(HttpResponse::NotFound().body("No route for this path"));
+    }
+
+    let mut url = match route {
+        Some(url) => url,
+        None => match incoming.target.as_deref() {
+            Some(url) => {
+                // Basic URL validation
+                let scheme = url.split_once("://").map(|(scheme, _)| scheme);
+                if scheme.is_some_and(|scheme| !config.scheme_allowed(scheme)) {
+                    return {
+                        warn!("Bad request: unsupported protocol");
+                        Ok(HttpResponse::BadRequest()
+                            .body("Unsupported protocol. Only HTTP and HTTPS are allowed."))
+                    };
+                }
+
+                // Reject URLs like `https://` or `https:///path`, which the
+                // URL parser would otherwise read a host out of the path for
+                let domain = url.split("://").last().unwrap_or(url);
+                let authority = domain.split(['/', '?', '#']).next().unwrap_or_default();
+                let host = authority.rsplit('@').next().unwrap_or_default();
+                if host.is_empty() {
+                    return {
+                        warn!("Bad request: missing host - {}", redact_userinfo(url));
+                        Ok(HttpResponse::BadRequest().body("Missing host"))
+                    };
+                }
+
+                // Ensure we have a domain name with at least one dot
+                if !domain.contains('.') {
+                    return {
+                        warn!("Bad request: invalid domain - {}", redact_userinfo(url));
+                        Ok(HttpResponse::BadRequest().body("Invalid domain name"))
+                    };
+                }
+
+                // Prepend https:// if no protocol is specified
+                if scheme.is_none() {
+                    format!("https://{}", url)
+                } else {
+                    url.to_string()
+                }
+            }
+            None => {
+                return {
+                    warn!("Bad request: no url specified");
+                    Ok(HttpResponse::BadRequest().body("No URL specified"))
+                }
+            }
+        },
+    };
+
+    // Append the query string, without the parameters that should not be forwarded
+    let query = strip_query_params(&incoming.query, &config.strip_query_params);
+    if !query.is_empty() {
+        url.push('?');
+        url.push_str(&query);
+    }
+
+    // Limit the length of the decoded target URL
+    let url_length = percent_decode_str(&url).count();
+    if url_length > config.max_url_length {
+        warn!(
+            "Bad request: URL of {} bytes exceeds limit of {}",
+            url_length, config.max_url_length
+        );
+        return Ok(HttpResponse::UriTooLong().body("URL too long"));
+    }
+
+    let mut url = match reqwest::Url::parse(&url) {
+        Ok(url) => url,
+        Err(e) => {
+            warn!(
+                "Bad request: invalid URL - {}: {}",
+                redact_userinfo(&url),
+                e
+            );
+            return Ok(HttpResponse::BadRequest().body("Invalid URL"));
+        }
+    };
+
+    // Extra schemes are accepted on request, but there is no client for them
+    if !PROXIED_SCHEMES.contains(&url.scheme()) {
+        warn!("Not implemented: proxying {} URLs", url.scheme());
+        return Ok(HttpResponse::NotImplemented()
+            .body(format!("Proxying {} URLs is not supported", url.scheme())));
+    }
+
+    // Full target URLs are joined with the fixed base path of the upstream
+    if let Some(prefix) = config.upstream_path_prefix.as_ref().filter(|_| full_url) {
+        let path = prefix_path(prefix, url.path());
+        url.set_path(&path);
+    }
+
+    // Only the path is touched, the query and the scheme keep their slashes
+    if config.normalize_path_slashes && url.path().contains("//") {
+        let path = collapse_slashes(url.path());
+        url.set_path(&path);
+    }
+
+    // Credentials are never forwarded as part of the URL
+    let credentials = if !url.username().is_empty() || url.password().is_some() {
+        let username = percent_decode_str(url.username())
+            .decode_utf8_lossy()
+            .into_owned();
+        let password = url.password().map(|password| {
+            percent_decode_str(password)
+                .decode_utf8_lossy()
+                .into_owned()
+        });
+        let _ = url.set_username("");
+        let _ = url.set_password(None);
+
+        if !config.allow_url_credentials {
+            warn!("Bad request: credentials in URL - {}", url);
+            return Ok(HttpResponse::BadRequest().body("Credentials in URLs are not allowed"));
+        }
+        Some((username, password))
+    } else {
+        None
+    };
+
+    let cors = config.cors_for(url.host_str());
+
+    // Answer preflight requests locally unless they should reach the upstream
+    if incoming.method == actix_web::http::Method::OPTIONS
+        && config.options_mode == OptionsMode::Preflight
+    {
+        let mut response = HttpResponse::NoContent();
+        cors::add_preflight_headers(&mut response, req, &cors);
+        return Ok(response.finish());
+    }
+
+    // Serve GET requests from the cache while the stored copy is fresh.
+    // Requests with credentials are never answered from or stored in the
+    // shared cache.
+    let cache_key = (cache.enabled()
+        && incoming.method == actix_web::http::Method::GET
+        && credentials.is_none()
+        && !incoming
+            .headers
+            .contains_key(actix_web::http::header::AUTHORIZATION)
+        && !incoming
+            .headers
+            .contains_key(actix_web::http::header::COOKIE))
+    .then(|| url.to_string());
+    if cache.enabled() && cache_key.is_none() {
+        metrics.observe_cache("bypass");
+    }
+    if let Some(cached) = cache_key.as_ref().and_then(|key| cache.get(key)) {
+        metrics.observe_cache("hit");
+        return Ok(cached_response(
+            req, &config, &cors, &metrics, cached, "HIT",
+        ));
+    }
+    // Concurrent misses wait for the first of them to fill the cache. The
+    // flight lasts until its response is stored or turns out uncacheable.
+    let mut flight = None;
+    if let Some(key) = cache_key
+        .as_ref()
+        .filter(|_| config.cache_coalesce_requests)
+    {
+        flight = cache.coalesce(key).await;
+        if flight.is_none() {
+            if let Some(cached) = cache.get(key) {
+                metrics.observe_cache("hit");
+                metrics.cache_coalesced.inc();
+                return Ok(cached_response(
+                    req, &config, &cors, &metrics, cached, "HIT",
+                ));
+            }
+        }
+    }
+    if cache_key.is_some() {
+        metrics.observe_cache("miss");
+    }
+    // Stale copies are served instead of an error while the upstream fails
+    let stale = |cache_key: &Option<String>| {
+        cache_key
+            .as_ref()
+            .and_then(|key| cache.get_stale(key))
+            .map(|cached| {
+                warn!("Upstream for {} failed, serving stale response", url);
+                metrics.observe_cache("stale");
+                cached_response(req, &config, &cors, &metrics, cached, "STALE")
+            })
+    };
+
+    // Throttle sensitive upstream paths, whatever the host
+    for limit in config
+        .file
+        .path_limits
+        .iter()
+        .filter(|limit| limit.matches(url.path()))
+    {
+        if let Err(retry_after) = rate_limiter.try_acquire_path(&limit.pattern, &limit.rate_limit) {
+            warn!("Rate limit for path pattern {} exceeded", limit.pattern);
+            return Ok(HttpResponse::TooManyRequests()
+                .append_header(("Retry-After", retry_after.as_secs().max(1).to_string()))
+                .body("Path rate limit exceeded"));
+        }
+    }
+
+    // Respect the request budget of the upstream host
+    if let Some(host) = url.host_str() {
+        if let Some(rate_limit) = config.file.host(host).and_then(|host| host.rate_limit) {
+            if let Err(retry_after) = rate_limiter.try_acquire_host(host, &rate_limit) {
+                warn!("Rate limit for upstream host {} exceeded", host);
+                return Ok(HttpResponse::ServiceUnavailable()
+                    .append_header(("Retry-After", retry_after.as_secs().max(1).to_string()))
+                    .body("Upstream rate limit exceeded"));
+            }
+        }
+    }
+
+    info!("Forwarding request to {}", url);
+
+    // Determine the HTTP method, clients that can only send GET and POST
+    // may ask for another one on a POST
+    let method_override = incoming
+        .headers
+        .get("x-http-method-override")
+        .filter(|_| incoming.method == actix_web::http::Method::POST);
+    let method = match method_override {
+        Some(value) => match value
+            .to_str()
+            .map(|value| value.trim().to_ascii_uppercase())
+        {
+            Ok(value) if value == "PUT" => reqwest::Method::PUT,
+            Ok(value) if value == "PATCH" => reqwest::Method::PATCH,
+            Ok(value) if value == "DELETE" => reqwest::Method::DELETE,
+            _ => {
+                return {
+                    warn!("Bad request: invalid method override {:?}", value);
+                    Ok(HttpResponse::BadRequest().body(
+                        "Invalid X-HTTP-Method-Override. Only PUT, PATCH and DELETE are allowed.",
+                    ))
+                }
+            }
+        },
+        // Only methods from `ALLOWED_METHODS` are routed here
+        None => match reqwest::Method::from_bytes(incoming.method.as_str().as_bytes()) {
+            Ok(method) => method,
+            Err(_) => {
+                return {
+                    warn!("Bad request: not valid HTTP method specified");
+                    Ok(HttpResponse::MethodNotAllowed().finish())
+                }
+            }
+        },
+    };
+    // The override has to be allowed like a method sent directly
+    if !config
+        .allowed_methods
+        .iter()
+        .any(|allowed| allowed.as_str() == method.as_str())
+    {
+        return Ok(not_allowed(&config, method.as_str()));
+    }
+
+    // Forward the request to the specified URL
+    let mut forwarded_headers = headers::forward_request_headers(&incoming.headers);
+    forwarded_headers.insert(
+        "x-forwarded-proto",
+        reqwest::header::HeaderValue::from_static(client_ip::client_scheme(
+            req,
+            config.trust_forwarded_proto,
+            &config.trusted_proxies,
+        )),
+    );
+    if config.send_x_real_ip {
+        // A value sent by the client is never passed on
+        forwarded_headers.remove("x-real-ip");
+        if let Some(address) =
+            client_ip::client_ip(req, config.trust_forwarded_for, &config.trusted_proxies)
+        {
+            if let Ok(value) = reqwest::header::HeaderValue::from_str(&address.to_string()) {
+                forwarded_headers.insert("x-real-ip", value);
+            }
+        }
+    }
+    // Clients whose Accept-Encoding has to reach the upstream verbatim get
+    // the encoded body as is
+    let decompress_upstream = config.decompress_upstream
+        && !(config.preserve_client_accept_encoding
+            && incoming
+                .headers
+                .contains_key(actix_web::http::header::ACCEPT_ENCODING));
+    if decompress_upstream {
+        // Only ask for encodings the proxy can decompress
+        forwarded_headers.insert(
+            reqwest::header::ACCEPT_ENCODING,
+            reqwest::header::HeaderValue::from_static("gzip"),
+        );
+    }
+    let request = |client: &Client| {
+        let request = client
+            .request(method.clone(), url.clone())
+            .headers(forwarded_headers.clone())
+            .body(body.clone());
+        match &credentials {
+            Some((username, password)) => request.basic_auth(username, password.as_ref()),
+            None => request,
+        }
+    };
+
+    let exchange = capture.request(&method, &url, &forwarded_headers, &body);
+    let upstream_started = Instant::now();
+    let send = client.send(url.scheme() == "https", request);
+    let sent = match config.request_timeout {
+        Some(timeout) => match time::timeout(timeout, send).await {
+            Ok(sent) => sent,
+            Err(_) => {
+                warn!("Upstream {} didn't respond within {:?}", url, timeout);
+                if let Some(response) = stale(&cache_key) {
+                    return Ok(response);
+                }
+                return Ok(HttpResponse::GatewayTimeout()
+                    .body(format!("Upstream didn't respond within {:?}", timeout)));
+            }
+        },
+        None => send.await,
+    };
+    req.extensions_mut()
+        .insert(UpstreamTime(upstream_started.elapsed()));
+    let response = match sent {
+        Ok(response) => response,
+        Err(SendError::HeaderTimeout(timeout)) => {
+            warn!(
+                "Upstream {} didn't send response headers within {:?}",
+                url, timeout
+            );
+            if let Some(response) = stale(&cache_key) {
+                return Ok(response);
+            }
+            return Ok(HttpResponse::GatewayTimeout().body(format!(
+                "Upstream didn't send response headers within {:?}",
+                timeout
+            )));
+        }
+        Err(SendError::Deadline(deadline)) => {
+            warn!(
+                "Upstream {} didn't respond within the deadline of {:?}",
+                url, deadline
+            );
+            if let Some(response) = stale(&cache_key) {
+                return Ok(response);
+            }
+            return Ok(HttpResponse::GatewayTimeout().body(format!(
+                "Upstream didn't respond within the request deadline of {:?}",
+                deadline
+            )));
+        }
+        Err(SendError::Request(e)) => {
+            warn!(
+                "Failed to forward request to {}: {}",
+                url,
+                upstream_error::describe(&e)
+            );
+            if let Some(response) = stale(&cache_key) {
+                return Ok(response);
+            }
+            // Details of TLS failures stay in the log
+            let message = match upstream_error::classify(&e) {
+                UpstreamErrorKind::Tls => "Upstream TLS handshake failed".to_string(),
+                UpstreamErrorKind::TlsPolicy => "TLS policy not met".to_string(),
+                UpstreamErrorKind::Certificate => "Upstream certificate rejected".to_string(),
+                _ => format!("Failed to forward request: {}", e),
+            };
+            return Ok(HttpResponse::BadGateway().body(message));
+        }
+    };
+
+    // Refuse responses whose headers could exhaust memory or downstream parsers
+    if response.headers().len() > config.max_response_headers {
+        warn!(
+            "Upstream {} sent {} response headers, more than the limit of {}",
+            url,
+            response.headers().len(),
+            config.max_response_headers
+        );
+        return Ok(HttpResponse::BadGateway().body("Upstream response has too many headers"));
+    }
+
+    if response.status().is_server_error() {
+        if let Some(response) = stale(&cache_key) {
+            return Ok(response);
+        }
+    }
+
+    // Decompress gzip bodies if configured, the client then gets them
+    // without Content-Encoding
+    let mut upstream_headers = response.headers().clone();
+    let decompress = decompress_upstream && is_gzip(&upstream_headers);
+    if decompress {
+        upstream_headers.remove(reqwest::header::CONTENT_ENCODING);
+        upstream_headers.remove(reqwest::header::CONTENT_LENGTH);
+    } else if decompress_upstream {
+        // Other encodings are passed on untouched, with their header, for
+        // the client to decode
+        if let Some(encoding) = upstream_headers
+            .get(reqwest::header::CONTENT_ENCODING)
+            .and_then(|encoding| encoding.to_str().ok())
+            .filter(|encoding| !encoding.trim().eq_ignore_ascii_case("identity"))
+        {
+            info!(
+                "Can't decode Content-Encoding {} from {}, passing it through",
+                encoding, url
+            );
+        }
+    }
+
+    let status =
+        StatusCode::from_u16(response.status().as_u16()).unwrap_or(StatusCode::BAD_GATEWAY);
+    let (mut builder, content_type) =
+        build_response(req, &config, &cors, &metrics, status, &upstream_headers);
+    let capture_limit = exchange.as_ref().map_or(0, |exchange| exchange.max_size());
+    let mut captured =
+        exchange.map(|exchange| exchange.response(status.as_u16(), &upstream_headers));
+
+    // 204 and 304 responses never have a body, so they get neither a
+    // Content-Length nor chunked framing, whatever the request method
+    if !body_allowed(status) {
+        if let Some(captured) = captured.take() {
+            captured(web::Bytes::new(), false);
+        }
+        return Ok(builder.body(body::None::new()));
+    }
+
+    // Keep hosts to the kinds of content they are expected to serve
+    if !config.content_type_allowed(url.host_str(), &content_type) {
+        warn!(
+            "Upstream {} responded with disallowed content type {}",
+            url, content_type
+        );
+        if let Some(captured) = captured.take() {
+            captured(web::Bytes::new(), true);
+        }
+        return Ok(HttpResponse::BadGateway().body("Upstream content type not allowed"));
+    }
+
+    // HEAD responses have no body but keep the upstream Content-Length
+    if incoming.method == actix_web::http::Method::HEAD {
+        if let Some(captured) = captured.take() {
+            captured(web::Bytes::new(), false);
+        }
+        let length = upstream_headers
+            .get(reqwest::header::CONTENT_LENGTH)
+            .and_then(|length| length.to_str().ok())
+            .and_then(|length| length.parse().ok());
+        return Ok(match length {
+            Some(length) => builder.body(SizedStream::new(
+                length,
+                futures_util::stream::empty::<Result<web::Bytes, std::io::Error>>(),
+            )),
+            None => builder.body(body::None::new()),
+        });
+    }
+
+    // HTML error pages are of no use to clients that expect JSON
+    if config.replace_upstream_error_bodies
+        && (status.is_client_error() || status.is_server_error())
+        && status.as_u16() >= config.replace_error_min_status
+        && has_media_type(&content_type, "text/html")
+    {
+        info!("Replacing HTML error page of {} from {}", status, url);
+        if let Some(captured) = captured.take() {
+            captured(web::Bytes::new(), true);
+        }
+        let message = format!("Upstream responded with {}", status);
+        let mut response = match &config.error_body_template {
+            Some(template) => {
+                builder.insert_header(("Content-Type", template.content_type()));
+                builder.body(template.render(status, &message))
+            }
+            None => {
+                builder.insert_header(("Content-Type", "application/json"));
+                builder.json(serde_json::json!({
+                    "error": message,
+                    "status": status.as_u16(),
+                }))
+            }
+        };
+        // The encoding and validators of the page don't apply to the error
+        for header in [
+            actix_web::http::header::CONTENT_ENCODING,
+            actix_web::http::header::ETAG,
+            actix_web::http::header::LAST_MODIFIED,
+        ] {
+            response.headers_mut().remove(header);
+        }
+        return Ok(response);
+    }
+
+    // Server-Sent Events are long-lived and may stay quiet between events,
+    // so they are exempt from the idle timeout. Each event is written as
+    // soon as it arrives, and intermediaries are asked not to buffer them.
+    let idle_timeout = if is_event_stream(&content_type) {
+        builder.insert_header(("X-Accel-Buffering", "no"));
+        None
+    } else {
+        config.stream_idle_timeout
+    };
+
+    // Documents are rewritten relative to the URL they were fetched from,
+    // which differs from the requested one after redirects
+    #[cfg(feature = "rewrite")]
+    let rewritten = if !config.rewrite_html_urls
+        || upstream_headers.contains_key(reqwest::header::CONTENT_ENCODING)
+    {
+        None
+    } else if has_media_type(&content_type, "text/html") {
+        Some((DocumentKind::Html, response.url().clone()))
+    } else if has_media_type(&content_type, "text/css") {
+        Some((DocumentKind::Css, response.url().clone()))
+    } else {
+        None
+    };
+
+    // Clients that can't handle an empty JSON body can get a placeholder
+    let placeholder = config
+        .empty_body_placeholder
+        .as_ref()
+        .filter(|_| is_json(&content_type) && body_allowed(status))
+        .map(|placeholder| web::Bytes::from(placeholder.clone()));
+
+    // Keep a copy of cacheable responses once the whole body has arrived
+    let store: Option<Box<dyn FnOnce(web::Bytes)>> = cache_key
+        .filter(|_| status == StatusCode::OK)
+        .and_then(|key| {
+            let ttl = cache::cache_ttl(&upstream_headers, config.cache_default_ttl)?;
+            let headers = upstream_headers.clone();
+            let cache = cache.clone();
+            Some(Box::new(move |body| {
+                cache.insert(key, status.as_u16(), headers, body, ttl);
+                drop(flight);
+            }) as Box<dyn FnOnce(web::Bytes)>)
+        });
+
+    let mut decoded = DecompressStream::new(
+        IdleTimeoutStream::new(Box::pin(response.bytes_stream()), idle_timeout),
+        decompress.then_some(config.max_decompressed_size),
+    );
+
+    // Read the start of a decompressed body before responding, so that
+    // bodies that exceed the size limit right away are answered with 502.
+    // Bodies that exceed it later are aborted.
+    let mut first = None;
+    if decompress {
+        match decoded.next().await {
+            Some(Err(e)) => {
+                warn!("Failed to decompress response from {}: {}", url, e);
+                return Ok(HttpResponse::BadGateway()
+                    .body(format!("Failed to decompress upstream response: {}", e)));
+            }
+            chunk => first = chunk,
+        }
+    }
+
+    #[cfg(feature = "rewrite")]
+    let body: rewrite::BodyStream = Box::pin(futures_util::stream::iter(first).chain(decoded));
+    #[cfg(not(feature = "rewrite"))]
+    let body = futures_util::stream::iter(first).chain(decoded);
+
+    // Rewrite the URLs of HTML and CSS documents to go through the proxy,
+    // which needs the whole document
+    #[cfg(feature = "rewrite")]
+    let body = match rewritten {
+        Some((kind, base)) => {
+            rewrite::rewrite_body(body, kind, &base, config.rewrite_max_body_size).await
+        }
+        None => body,
+    };
+
+    // Stream the response body, counting its size as it passes through.
+    // The upstream framing is never copied: the body ends where reqwest's
+    // stream ends, which includes HTTP/1.0 bodies delimited by the upstream
+    // closing the connection, and actix frames it with chunked encoding
+    // unless it is buffered below.
+    let body = CountingStream::new(
+        TeeStream::new(
+            CachingStream::new(
+                PlaceholderStream::new(body, placeholder),
+                config.cache_max_body_size,
+                store,
+            ),
+            capture_limit,
+            captured,
+        ),
+        metrics.response_body_bytes.clone(),
+    );
+
+    // Small responses of known length are sent in one piece, with a
+    // Content-Length instead of chunked encoding
+    let length = upstream_headers
+        .get(reqwest::header::CONTENT_LENGTH)
+        .and_then(|length| length.to_str().ok())
+        .and_then(|length| length.parse::<usize>().ok());
+    if length.is_some_and(|length| length < config.stream_threshold) {
+        let mut body = Box::pin(body);
+        let mut buffered = web::BytesMut::new();
+        while let Some(chunk) = body.next().await {
+            match chunk {
+                Ok(chunk) => buffered.extend_from_slice(&chunk),
+                Err(e) => {
+                    warn!("Failed to read response from {}: {}", url, e);
+                    return Ok(HttpResponse::BadGateway()
+                        .body(format!("Failed to read upstream response: {}", e)));
+                }
+            }
+        }
+        return Ok(builder.body(buffered.freeze()));
+    }
+
+    Ok(builder.streaming(body))
+}
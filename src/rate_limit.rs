@@ -0,0 +1,172 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::clock::{Clock, SystemClock};
+use crate::config_file::RateLimit;
+
+/// A token bucket that refills continuously at a fixed rate.
+struct TokenBucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    fn new(limit: &RateLimit, now: Instant) -> Self {
+        TokenBucket {
+            tokens: limit.capacity(),
+            updated: now,
+        }
+    }
+
+    /// Takes a token if one is available, otherwise returns how long it
+    /// takes until the next token becomes available.
+    fn try_acquire(&mut self, limit: &RateLimit, now: Instant) -> Result<(), Duration> {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * limit.requests_per_second).min(limit.capacity());
+        self.updated = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64(
+                (1.0 - self.tokens) / limit.requests_per_second,
+            ))
+        }
+    }
+}
+
+/// Rate limits outgoing requests with one token bucket per upstream host
+/// and one per limited path pattern.
+pub struct RateLimiter {
+    hosts: Mutex<HashMap<String, TokenBucket>>,
+    paths: Mutex<HashMap<String, TokenBucket>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl Default for RateLimiter {
+    fn default() -> Self {
+        Self::new(Arc::new(SystemClock))
+    }
+}
+
+impl RateLimiter {
+    pub fn new(clock: Arc<dyn Clock>) -> Self {
+        RateLimiter {
+            hosts: Mutex::new(HashMap::new()),
+            paths: Mutex::new(HashMap::new()),
+            clock,
+        }
+    }
+
+    /// Takes a request from the budget of `host`. On failure the time until
+    /// the host has budget again is returned.
+    pub fn try_acquire_host(&self, host: &str, limit: &RateLimit) -> Result<(), Duration> {
+        self.try_acquire(&self.hosts, host.to_ascii_lowercase(), limit)
+    }
+
+    /// Takes a request from the budget shared by all paths that match
+    /// `pattern`, on any host.
+    pub fn try_acquire_path(&self, pattern: &str, limit: &RateLimit) -> Result<(), Duration> {
+        self.try_acquire(&self.paths, pattern.to_string(), limit)
+    }
+
+    fn try_acquire(
+        &self,
+        buckets: &Mutex<HashMap<String, TokenBucket>>,
+        key: String,
+        limit: &RateLimit,
+    ) -> Result<(), Duration> {
+        let now = self.clock.now();
+        let mut buckets = buckets.lock().unwrap();
+        buckets
+            .entry(key)
+            .or_insert_with(|| TokenBucket::new(limit, now))
+            .try_acquire(limit, now)
+    }
+}
+
+/// Counts the requests the proxy is working on across all workers, in
+/// total and per client address.
+#[derive(Default)]
+pub struct ConcurrencyLimiter {
+    in_flight: AtomicUsize,
+    /// Requests in flight per client, clients without any are removed.
+    per_client: Mutex<HashMap<IpAddr, usize>>,
+}
+
+/// A slot of the concurrency limit, released when dropped.
+pub struct ConcurrencyPermit<'a> {
+    limiter: &'a ConcurrencyLimiter,
+}
+
+impl ConcurrencyLimiter {
+    /// Takes a slot if fewer than `max` requests are in flight, `0` meaning
+    /// no limit.
+    pub fn try_acquire(&self, max: usize) -> Option<ConcurrencyPermit<'_>> {
+        let acquired = self
+            .in_flight
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |in_flight| {
+                (max == 0 || in_flight < max).then(|| in_flight + 1)
+            })
+            .is_ok();
+        acquired.then(|| ConcurrencyPermit { limiter: self })
+    }
+
+    /// Takes a slot of `client` if it has fewer than `max` requests in
+    /// flight, `0` meaning no limit.
+    pub fn try_acquire_client(&self, client: IpAddr, max: usize) -> Option<ClientPermit<'_>> {
+        if max == 0 {
+            return Some(ClientPermit {
+                limiter: self,
+                client: None,
+            });
+        }
+
+        let mut per_client = self.per_client.lock().unwrap();
+        let in_flight = per_client.entry(client).or_insert(0);
+        if *in_flight >= max {
+            return None;
+        }
+        *in_flight += 1;
+        Some(ClientPermit {
+            limiter: self,
+            client: Some(client),
+        })
+    }
+
+    /// Number of clients that currently have requests in flight.
+    pub fn active_clients(&self) -> usize {
+        self.per_client.lock().unwrap().len()
+    }
+}
+
+/// A slot of a client's concurrency limit, released when dropped.
+pub struct ClientPermit<'a> {
+    limiter: &'a ConcurrencyLimiter,
+    /// The client holding the slot, none without a limit.
+    client: Option<IpAddr>,
+}
+
+impl Drop for ClientPermit<'_> {
+    fn drop(&mut self) {
+        if let Some(client) = self.client {
+            let mut per_client = self.limiter.per_client.lock().unwrap();
+            if let Some(in_flight) = per_client.get_mut(&client) {
+                *in_flight -= 1;
+                if *in_flight == 0 {
+                    per_client.remove(&client);
+                }
+            }
+        }
+    }
+}
+
+impl Drop for ConcurrencyPermit<'_> {
+    fn drop(&mut self) {
+        self.limiter.in_flight.fetch_sub(1, Ordering::SeqCst);
+    }
+}
@@ -0,0 +1,78 @@
+use std::any::Any;
+use std::panic::{self, AssertUnwindSafe};
+
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::error::InternalError;
+use actix_web::http::StatusCode;
+use actix_web::middleware::Next;
+use actix_web::{web, Error, HttpResponse};
+use futures_util::FutureExt;
+use log::{error, log_enabled, Level};
+
+use crate::config::Config;
+use crate::cors;
+use crate::metrics::Metrics;
+
+/// Logs panics with their location through the logger, falling back to
+/// the default output of panics when logging is disabled.
+pub fn install_panic_hook() {
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        if !log_enabled!(Level::Error) {
+            return default_hook(info);
+        }
+        let location = info
+            .location()
+            .map(|location| location.to_string())
+            .unwrap_or_else(|| "an unknown location".to_string());
+        error!("Panicked at {}: {}", location, message(info.payload()));
+    }));
+}
+
+fn message(payload: &(dyn Any + Send)) -> &str {
+    payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("Box<dyn Any>")
+}
+
+/// Answers requests whose handler panicked with a `500` carrying the
+/// CORS headers, instead of dropping the connection.
+///
+/// Panics while streaming a response body can't be answered anymore,
+/// and still drop the connection.
+pub async fn catch_panics(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    // The request can't be kept around while it is being routed, so the
+    // response to a panic is prepared up front
+    let path = req.path().to_string();
+    let metrics = req.app_data::<web::Data<Metrics>>().cloned();
+    let mut response = HttpResponse::InternalServerError();
+    let mut template = None;
+    if let Some(config) = req.app_data::<web::Data<Config>>() {
+        cors::add_cors_headers(&mut response, req.request(), &config.cors_for(None));
+        template = config.error_body_template.clone();
+    }
+
+    let panic = match AssertUnwindSafe(next.call(req)).catch_unwind().await {
+        Ok(response) => return response,
+        Err(panic) => panic,
+    };
+    let message = message(panic.as_ref());
+    error!("Request to {} panicked: {}", path, message);
+    if let Some(metrics) = metrics {
+        metrics.panics.inc();
+    }
+    let body = match &template {
+        Some(template) => {
+            response.insert_header(("Content-Type", template.content_type()));
+            template.render(StatusCode::INTERNAL_SERVER_ERROR, "Internal server error")
+        }
+        None => "Internal server error".to_string(),
+    };
+    Err(InternalError::from_response(message.to_string(), response.body(body)).into())
+}
@@ -0,0 +1,210 @@
+use std::cell::RefCell;
+use std::error::Error;
+use std::pin::Pin;
+
+use actix_web::web::{Bytes, BytesMut};
+use futures_util::{stream, Stream, StreamExt};
+use log::warn;
+use lol_html::errors::RewritingError;
+use lol_html::html_content::ContentType;
+use lol_html::{element, text, HtmlRewriter, Settings};
+use reqwest::Url;
+
+/// A response body as passed to the client.
+pub type BodyStream = Pin<Box<dyn Stream<Item = Result<Bytes, Box<dyn Error>>>>>;
+
+/// Kind of document whose URLs are rewritten.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DocumentKind {
+    Html,
+    Css,
+}
+
+/// Reads a whole document of up to `limit` bytes and rewrites its URLs
+/// relative to `base`, the URL it was fetched from.
+///
+/// Larger documents are passed on unchanged, starting with the part that
+/// was read before the limit was reached.
+pub async fn rewrite_body(
+    mut body: BodyStream,
+    kind: DocumentKind,
+    base: &Url,
+    limit: usize,
+) -> BodyStream {
+    let mut buffer = BytesMut::new();
+    while let Some(chunk) = body.next().await {
+        match chunk {
+            Ok(chunk) if buffer.len() + chunk.len() <= limit => buffer.extend_from_slice(&chunk),
+            chunk => {
+                if chunk.is_ok() {
+                    warn!(
+                        "Response from {} is larger than {} bytes, not rewriting its URLs",
+                        base, limit
+                    );
+                }
+                return Box::pin(stream::iter([Ok(buffer.freeze()), chunk]).chain(body));
+            }
+        }
+    }
+
+    let buffer = buffer.freeze();
+    let rewritten = match kind {
+        DocumentKind::Html => rewrite_html(&buffer, base).map_err(|e| e.to_string()),
+        DocumentKind::Css => std::str::from_utf8(&buffer)
+            .map(|css| rewrite_css(css, base).into_bytes())
+            .map_err(|e| e.to_string()),
+    };
+    let body = match rewritten {
+        Ok(rewritten) => Bytes::from(rewritten),
+        Err(e) => {
+            warn!("Failed to rewrite URLs of {}: {}", base, e);
+            buffer
+        }
+    };
+    Box::pin(stream::once(async { Ok(body) }))
+}
+
+/// Maps a URL found in a document at `base` to its path on the proxy.
+/// Fragments and URLs that aren't HTTP, like `data:` or `mailto:`, are
+/// left alone.
+pub fn proxied_url(base: &Url, value: &str) -> Option<String> {
+    let value = value.trim();
+    if value.is_empty() || value.starts_with('#') {
+        return None;
+    }
+
+    let url = base.join(value).ok()?;
+    matches!(url.scheme(), "http" | "https").then(|| format!("/{}", url))
+}
+
+/// Rewrites the URLs in link, resource and form attributes, inline styles
+/// and `<style>` elements of an HTML document. A `<base>` element changes
+/// the URL that the following URLs are resolved against.
+pub fn rewrite_html(html: &[u8], base: &Url) -> Result<Vec<u8>, RewritingError> {
+    let base = RefCell::new(base.clone());
+    let mut style = String::new();
+    let mut output = Vec::with_capacity(html.len());
+
+    let mut rewriter = HtmlRewriter::new(
+        Settings::new()
+            .append_element_content_handler(element!(
+                "[href], [src], [action], [formaction], [poster], [srcset], [style]",
+                |el| {
+                    for name in ["href", "src", "action", "formaction", "poster"] {
+                        let value = el.get_attribute(name);
+                        if let Some(url) =
+                            value.and_then(|value| proxied_url(&base.borrow(), &value))
+                        {
+                            el.set_attribute(name, &url)?;
+                        }
+                    }
+                    if let Some(srcset) = el.get_attribute("srcset") {
+                        el.set_attribute("srcset", &rewrite_srcset(&srcset, &base.borrow()))?;
+                    }
+                    if let Some(css) = el.get_attribute("style") {
+                        el.set_attribute("style", &rewrite_css(&css, &base.borrow()))?;
+                    }
+
+                    if el.tag_name() == "base" {
+                        let href = el.get_attribute("href");
+                        // The attribute was rewritten above, so the
+                        // proxied form is resolved again
+                        if let Some(url) = href
+                            .as_deref()
+                            .and_then(|href| href.strip_prefix('/'))
+                            .and_then(|href| Url::parse(href).ok())
+                        {
+                            *base.borrow_mut() = url;
+                        }
+                    }
+                    Ok(())
+                }
+            ))
+            .append_element_content_handler(text!("style", |chunk| {
+                // Style sheets may arrive in several chunks, which are
+                // collected and written out at the end
+                style.push_str(chunk.as_str());
+                if chunk.last_in_text_node() {
+                    chunk.replace(&rewrite_css(&style, &base.borrow()), ContentType::Html);
+                    style.clear();
+                } else {
+                    chunk.remove();
+                }
+                Ok(())
+            })),
+        |chunk: &[u8]| output.extend_from_slice(chunk),
+    );
+    rewriter.write(html)?;
+    rewriter.end()?;
+    Ok(output)
+}
+
+/// Rewrites the image candidates of a `srcset` attribute, each being a URL
+/// optionally followed by a descriptor.
+fn rewrite_srcset(srcset: &str, base: &Url) -> String {
+    srcset
+        .split(',')
+        .map(|candidate| {
+            let candidate = candidate.trim();
+            let (url, descriptor) = candidate
+                .split_once(char::is_whitespace)
+                .unwrap_or((candidate, ""));
+            match (proxied_url(base, url), descriptor) {
+                (Some(url), "") => url,
+                (Some(url), descriptor) => format!("{} {}", url, descriptor.trim()),
+                (None, _) => candidate.to_string(),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Rewrites the URLs in `url()` values and `@import` rules of a style sheet.
+pub fn rewrite_css(css: &str, base: &Url) -> String {
+    // Lowercasing ASCII keeps the byte offsets of the original
+    let lower = css.to_ascii_lowercase();
+    let mut output = String::with_capacity(css.len());
+    let mut copied = 0;
+    let mut position = 0;
+
+    while let Some((start, keyword)) = ["url(", "@import"]
+        .iter()
+        .filter_map(|keyword| {
+            lower[position..]
+                .find(keyword)
+                .map(|offset| (position + offset, *keyword))
+        })
+        .min()
+    {
+        let value_start = start + keyword.len();
+        let rest = &css[value_start..];
+        let skipped = rest.len() - rest.trim_start().len();
+        let rest = &rest[skipped..];
+
+        // The URL is either quoted, or unquoted up to the closing
+        // parenthesis of `url()`. `@import url()` is handled as `url()`.
+        let value = match rest.chars().next() {
+            Some(quote @ ('"' | '\'')) => rest[1..]
+                .find(quote)
+                .map(|end| (value_start + skipped + 1, &rest[1..1 + end])),
+            _ if keyword == "url(" => rest
+                .find(')')
+                .map(|end| (value_start + skipped, rest[..end].trim_end())),
+            _ => None,
+        };
+
+        position = value_start;
+        if let Some((value_start, value)) = value {
+            let value_end = value_start + value.len();
+            if let Some(url) = proxied_url(base, value) {
+                output.push_str(&css[copied..value_start]);
+                output.push_str(&url);
+                copied = value_end;
+            }
+            position = value_end;
+        }
+    }
+
+    output.push_str(&css[copied..]);
+    output
+}
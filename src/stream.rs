@@ -0,0 +1,414 @@
+use std::error::Error;
+use std::future::Future;
+use std::io::{self, Write};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use actix_web::error::PayloadError;
+use actix_web::rt::time::{sleep, Instant, Sleep};
+use actix_web::web::{Bytes, BytesMut};
+use flate2::write::GzDecoder;
+use futures_util::Stream;
+use log::warn;
+use prometheus::Histogram;
+
+/// Wraps a body stream and records the number of bytes that passed
+/// through it once the stream is finished or dropped.
+pub struct CountingStream<S> {
+    inner: S,
+    bytes: u64,
+    histogram: Histogram,
+}
+
+impl<S> CountingStream<S> {
+    pub fn new(inner: S, histogram: Histogram) -> Self {
+        CountingStream {
+            inner,
+            bytes: 0,
+            histogram,
+        }
+    }
+}
+
+impl<S, E> Stream for CountingStream<S>
+where
+    S: Stream<Item = Result<Bytes, E>> + Unpin,
+{
+    type Item = Result<Bytes, E>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let poll = Pin::new(&mut self.inner).poll_next(cx);
+        if let Poll::Ready(Some(Ok(chunk))) = &poll {
+            self.bytes += chunk.len() as u64;
+        }
+        poll
+    }
+}
+
+impl<S> Drop for CountingStream<S> {
+    fn drop(&mut self) {
+        self.histogram.observe(self.bytes as f64);
+    }
+}
+
+/// Wraps a request payload and fails with `PayloadError::Overflow` as soon
+/// as more than `limit` bytes arrived, whatever length the client declared.
+pub struct BodyLimitStream<S> {
+    inner: S,
+    received: usize,
+    limit: usize,
+}
+
+impl<S> BodyLimitStream<S> {
+    pub fn new(inner: S, limit: usize) -> Self {
+        BodyLimitStream {
+            inner,
+            received: 0,
+            limit,
+        }
+    }
+}
+
+impl<S> Stream for BodyLimitStream<S>
+where
+    S: Stream<Item = Result<Bytes, PayloadError>> + Unpin,
+{
+    type Item = Result<Bytes, PayloadError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if self.received > self.limit {
+            return Poll::Ready(None);
+        }
+        let poll = Pin::new(&mut self.inner).poll_next(cx);
+        if let Poll::Ready(Some(Ok(chunk))) = &poll {
+            self.received += chunk.len();
+            if self.received > self.limit {
+                return Poll::Ready(Some(Err(PayloadError::Overflow)));
+            }
+        }
+        poll
+    }
+}
+
+/// Wraps a body stream and aborts it with an error if no chunk arrives
+/// within the idle timeout. Without a timeout the stream is passed through.
+pub struct IdleTimeoutStream<S> {
+    inner: S,
+    timeout: Option<Duration>,
+    sleep: Pin<Box<Sleep>>,
+    timed_out: bool,
+}
+
+impl<S> IdleTimeoutStream<S> {
+    pub fn new(inner: S, timeout: Option<Duration>) -> Self {
+        IdleTimeoutStream {
+            inner,
+            timeout,
+            sleep: Box::pin(sleep(timeout.unwrap_or_default())),
+            timed_out: false,
+        }
+    }
+}
+
+impl<S, E> Stream for IdleTimeoutStream<S>
+where
+    S: Stream<Item = Result<Bytes, E>> + Unpin,
+    E: Into<Box<dyn Error>>,
+{
+    type Item = Result<Bytes, Box<dyn Error>>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if self.timed_out {
+            return Poll::Ready(None);
+        }
+
+        if let Poll::Ready(item) = Pin::new(&mut self.inner).poll_next(cx) {
+            if let Some(timeout) = self.timeout {
+                self.sleep.as_mut().reset(Instant::now() + timeout);
+            }
+            return Poll::Ready(item.map(|chunk| chunk.map_err(Into::into)));
+        }
+
+        let timeout = match self.timeout {
+            Some(timeout) => timeout,
+            None => return Poll::Pending,
+        };
+        match self.sleep.as_mut().poll(cx) {
+            Poll::Ready(()) => {
+                warn!("Upstream stalled for {:?}, aborting response", timeout);
+                self.timed_out = true;
+                Poll::Ready(Some(Err(Box::new(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "upstream response stalled",
+                )))))
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+/// Wraps a body stream and sends a placeholder instead if the stream ends
+/// without any data. Without a placeholder the stream is passed through.
+pub struct PlaceholderStream<S> {
+    inner: S,
+    placeholder: Option<Bytes>,
+}
+
+impl<S> PlaceholderStream<S> {
+    pub fn new(inner: S, placeholder: Option<Bytes>) -> Self {
+        PlaceholderStream { inner, placeholder }
+    }
+}
+
+impl<S, E> Stream for PlaceholderStream<S>
+where
+    S: Stream<Item = Result<Bytes, E>> + Unpin,
+{
+    type Item = Result<Bytes, E>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let poll = Pin::new(&mut self.inner).poll_next(cx);
+        match &poll {
+            Poll::Ready(Some(Ok(chunk))) if chunk.is_empty() => {}
+            Poll::Ready(Some(_)) => self.placeholder = None,
+            Poll::Ready(None) => {
+                if let Some(placeholder) = self.placeholder.take() {
+                    return Poll::Ready(Some(Ok(placeholder)));
+                }
+            }
+            Poll::Pending => {}
+        }
+        poll
+    }
+}
+
+/// Wraps a body stream and hands the complete body to a callback once the
+/// stream ends, unless it failed or grew beyond `limit` bytes. Without a
+/// callback the stream is passed through.
+pub struct CachingStream<S> {
+    inner: S,
+    buffer: BytesMut,
+    limit: usize,
+    on_complete: Option<Box<dyn FnOnce(Bytes)>>,
+}
+
+impl<S> CachingStream<S> {
+    pub fn new(inner: S, limit: usize, on_complete: Option<Box<dyn FnOnce(Bytes)>>) -> Self {
+        CachingStream {
+            inner,
+            buffer: BytesMut::new(),
+            limit,
+            on_complete,
+        }
+    }
+}
+
+impl<S, E> Stream for CachingStream<S>
+where
+    S: Stream<Item = Result<Bytes, E>> + Unpin,
+{
+    type Item = Result<Bytes, E>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let poll = Pin::new(&mut self.inner).poll_next(cx);
+        if self.on_complete.is_none() {
+            return poll;
+        }
+
+        match &poll {
+            Poll::Ready(Some(Ok(chunk))) => {
+                if self.buffer.len() + chunk.len() > self.limit {
+                    self.on_complete = None;
+                    self.buffer = BytesMut::new();
+                } else {
+                    self.buffer.extend_from_slice(chunk);
+                }
+            }
+            Poll::Ready(Some(Err(_))) => self.on_complete = None,
+            Poll::Ready(None) => {
+                if let Some(on_complete) = self.on_complete.take() {
+                    on_complete(std::mem::take(&mut self.buffer).freeze());
+                }
+            }
+            Poll::Pending => {}
+        }
+        poll
+    }
+}
+
+/// Called with the captured part of a body and whether the body was longer
+/// or failed.
+pub type TeeCallback = Box<dyn FnOnce(Bytes, bool)>;
+
+/// Wraps a body stream and hands a copy of its first `limit` bytes to a
+/// callback once the stream ends, fails or is dropped by a disconnecting
+/// client. Without a callback the stream is passed through.
+pub struct TeeStream<S> {
+    inner: S,
+    buffer: BytesMut,
+    limit: usize,
+    incomplete: bool,
+    on_end: Option<TeeCallback>,
+}
+
+impl<S> TeeStream<S> {
+    pub fn new(inner: S, limit: usize, on_end: Option<TeeCallback>) -> Self {
+        TeeStream {
+            inner,
+            buffer: BytesMut::new(),
+            limit,
+            incomplete: false,
+            on_end,
+        }
+    }
+
+    fn finish(&mut self) {
+        if let Some(on_end) = self.on_end.take() {
+            on_end(std::mem::take(&mut self.buffer).freeze(), self.incomplete);
+        }
+    }
+}
+
+impl<S, E> Stream for TeeStream<S>
+where
+    S: Stream<Item = Result<Bytes, E>> + Unpin,
+{
+    type Item = Result<Bytes, E>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let poll = Pin::new(&mut self.inner).poll_next(cx);
+        if self.on_end.is_none() {
+            return poll;
+        }
+
+        match &poll {
+            Poll::Ready(Some(Ok(chunk))) => {
+                let room = self.limit.saturating_sub(self.buffer.len());
+                if chunk.len() > room {
+                    self.incomplete = true;
+                }
+                self.buffer
+                    .extend_from_slice(&chunk[..chunk.len().min(room)]);
+            }
+            Poll::Ready(Some(Err(_))) => {
+                self.incomplete = true;
+                self.finish();
+            }
+            Poll::Ready(None) => self.finish(),
+            Poll::Pending => {}
+        }
+        poll
+    }
+}
+
+impl<S> Drop for TeeStream<S> {
+    fn drop(&mut self) {
+        if self.on_end.is_some() {
+            self.incomplete = true;
+            self.finish();
+        }
+    }
+}
+
+/// Collects decompressed output, failing once more than `limit` bytes were
+/// written in total.
+struct LimitedBuffer {
+    buffer: Vec<u8>,
+    written: u64,
+    limit: u64,
+}
+
+impl Write for LimitedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.written += buf.len() as u64;
+        if self.written > self.limit {
+            return Err(io::Error::new(
+                io::ErrorKind::FileTooLarge,
+                format!("decompressed body exceeds {} bytes", self.limit),
+            ));
+        }
+        self.buffer.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Wraps a gzip encoded body stream and decompresses it, failing once the
+/// decompressed body exceeds the size limit. Decompressed data is produced
+/// piece by piece, so a small body that expands to gigabytes never takes
+/// more memory than the limit. Without a limit the stream is passed through.
+pub struct DecompressStream<S> {
+    inner: S,
+    decoder: Option<GzDecoder<LimitedBuffer>>,
+    done: bool,
+}
+
+impl<S> DecompressStream<S> {
+    pub fn new(inner: S, limit: Option<u64>) -> Self {
+        DecompressStream {
+            inner,
+            decoder: limit.map(|limit| {
+                GzDecoder::new(LimitedBuffer {
+                    buffer: Vec::new(),
+                    written: 0,
+                    limit,
+                })
+            }),
+            done: false,
+        }
+    }
+}
+
+impl<S, E> Stream for DecompressStream<S>
+where
+    S: Stream<Item = Result<Bytes, E>> + Unpin,
+    E: Into<Box<dyn Error>>,
+{
+    type Item = Result<Bytes, Box<dyn Error>>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        let decoder = match &mut this.decoder {
+            Some(decoder) => decoder,
+            None => {
+                return Pin::new(&mut this.inner)
+                    .poll_next(cx)
+                    .map(|item| item.map(|chunk| chunk.map_err(Into::into)))
+            }
+        };
+
+        loop {
+            if this.done {
+                return Poll::Ready(None);
+            }
+
+            let result = match Pin::new(&mut this.inner).poll_next(cx) {
+                Poll::Ready(Some(Ok(chunk))) => decoder.write_all(&chunk),
+                Poll::Ready(Some(Err(e))) => {
+                    this.done = true;
+                    return Poll::Ready(Some(Err(e.into())));
+                }
+                Poll::Ready(None) => {
+                    this.done = true;
+                    decoder.try_finish()
+                }
+                Poll::Pending => return Poll::Pending,
+            };
+
+            if let Err(e) = result {
+                warn!("Aborting decompressed response: {}", e);
+                this.done = true;
+                return Poll::Ready(Some(Err(Box::new(e))));
+            }
+
+            let output = std::mem::take(&mut decoder.get_mut().buffer);
+            if !output.is_empty() {
+                return Poll::Ready(Some(Ok(Bytes::from(output))));
+            }
+        }
+    }
+}
@@ -0,0 +1,102 @@
+use std::error::Error;
+use std::io;
+
+use rustls::AlertDescription;
+
+/// Broad cause of a failed upstream request.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UpstreamErrorKind {
+    /// The TLS handshake failed, for example because the upstream doesn't
+    /// speak TLS or broke the protocol.
+    Tls,
+    /// The certificate of the upstream is invalid, for example expired, for
+    /// another name or not signed by a trusted authority.
+    Certificate,
+    /// The upstream doesn't support a TLS version or cipher suite the
+    /// proxy accepts, see `MIN_TLS_VERSION`.
+    TlsPolicy,
+    /// No response arrived in time.
+    Timeout,
+    /// The connection could not be established.
+    Connect,
+    /// Any other failure while sending the request or reading the response.
+    Other,
+}
+
+impl UpstreamErrorKind {
+    /// Whether a request that failed this way may be sent again over plain
+    /// HTTP with `FALLBACK_TO_HTTP_ON_TLS_ERROR`. Invalid certificates, as
+    /// presented by someone intercepting the connection, and handshakes
+    /// refused for `MIN_TLS_VERSION` never fall back.
+    pub fn allows_http_fallback(self) -> bool {
+        self == UpstreamErrorKind::Tls
+    }
+}
+
+/// Classifies a reqwest error by walking its chain of sources.
+///
+/// TLS failures are reported as connect errors by reqwest, so they are
+/// recognized by the rustls error among the sources.
+pub fn classify(error: &reqwest::Error) -> UpstreamErrorKind {
+    let mut source: Option<&(dyn Error + 'static)> = error.source();
+    while let Some(cause) = source {
+        // The TLS stream reports errors wrapped in `io::Error`s, whose
+        // `source` skips them
+        let mut inner = cause;
+        loop {
+            if let Some(tls) = inner.downcast_ref::<rustls::Error>() {
+                return classify_tls(tls);
+            }
+            let Some(io) = inner.downcast_ref::<io::Error>() else {
+                break;
+            };
+            // The upstream closed the connection during the handshake
+            if error.is_connect() && io.kind() == io::ErrorKind::UnexpectedEof {
+                return UpstreamErrorKind::Tls;
+            }
+            match io.get_ref() {
+                Some(wrapped) => inner = wrapped,
+                None => break,
+            }
+        }
+        source = cause.source();
+    }
+
+    if error.is_timeout() {
+        UpstreamErrorKind::Timeout
+    } else if error.is_connect() {
+        UpstreamErrorKind::Connect
+    } else {
+        UpstreamErrorKind::Other
+    }
+}
+
+/// The full error chain, for logging.
+pub fn describe(error: &dyn Error) -> String {
+    let mut description = error.to_string();
+    let mut source = error.source();
+    while let Some(cause) = source {
+        description.push_str(": ");
+        description.push_str(&cause.to_string());
+        source = cause.source();
+    }
+    description
+}
+
+/// Tells handshakes that failed because no TLS version or cipher suite is
+/// acceptable to both sides, or because of the certificate of the upstream,
+/// from other TLS failures.
+fn classify_tls(error: &rustls::Error) -> UpstreamErrorKind {
+    match error {
+        rustls::Error::InvalidCertificate(_)
+        | rustls::Error::NoCertificatesPresented
+        | rustls::Error::UnsupportedNameType => UpstreamErrorKind::Certificate,
+        rustls::Error::PeerIncompatible(_)
+        | rustls::Error::AlertReceived(
+            AlertDescription::ProtocolVersion
+            | AlertDescription::InsufficientSecurity
+            | AlertDescription::HandshakeFailure,
+        ) => UpstreamErrorKind::TlsPolicy,
+        _ => UpstreamErrorKind::Tls,
+    }
+}
@@ -0,0 +1,183 @@
+mod common;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use actix_web::http::StatusCode;
+use actix_web::test::TestRequest;
+use wiremock::matchers::method;
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+use common::{proxy_with, target};
+use rcp::access_log::{AccessLog, AccessLogFormat};
+use rcp::config::Config;
+use rcp::AppState;
+
+/// A fresh log file path in the temporary directory.
+fn log_path(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("rcp-access-log-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir.join("access.log")
+}
+
+/// Reads a log file once it has `lines` lines, as it is written in the
+/// background.
+async fn read_lines(path: &PathBuf, lines: usize) -> Vec<String> {
+    for _ in 0..100 {
+        let contents = fs::read_to_string(path).unwrap_or_default();
+        if contents.lines().count() >= lines {
+            return contents.lines().map(str::to_string).collect();
+        }
+        actix_web::rt::time::sleep(Duration::from_millis(20)).await;
+    }
+    panic!("{} does not have {} lines", path.display(), lines);
+}
+
+async fn upstream() -> MockServer {
+    let upstream = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("ok"))
+        .mount(&upstream)
+        .await;
+    upstream
+}
+
+#[actix_web::test]
+async fn writes_access_log_lines_to_file() {
+    let upstream = upstream().await;
+    let path = log_path("plain");
+    let state = AppState::new(Config {
+        access_log_file: Some(path.to_str().unwrap().to_string()),
+        ..Config::default()
+    });
+
+    proxy_with(&state, TestRequest::get().uri(&target(&upstream, "/data"))).await;
+
+    let lines = read_lines(&path, 1).await;
+    let expected = format!("\"GET {} HTTP/1.1\" 200 ", target(&upstream, "/data"));
+    assert!(lines[0].contains(&expected), "{}", lines[0]);
+}
+
+#[actix_web::test]
+async fn writes_json_access_log_lines() {
+    let upstream = upstream().await;
+    let path = log_path("json");
+    let state = AppState::new(Config {
+        access_log_file: Some(path.to_str().unwrap().to_string()),
+        access_log_format: AccessLogFormat::Json,
+        ..Config::default()
+    });
+
+    proxy_with(&state, TestRequest::get().uri(&target(&upstream, "/data"))).await;
+
+    let lines = read_lines(&path, 1).await;
+    let line: serde_json::Value = serde_json::from_str(&lines[0]).unwrap();
+    assert_eq!(line["method"], "GET");
+    assert_eq!(line["path"], target(&upstream, "/data"));
+    assert_eq!(line["status"], 200);
+}
+
+#[actix_web::test]
+async fn rotates_access_log_at_size_limit() {
+    let upstream = upstream().await;
+    let path = log_path("rotate");
+    let state = AppState::new(Config {
+        access_log_file: Some(path.to_str().unwrap().to_string()),
+        access_log_max_size: 150,
+        access_log_max_files: 2,
+        ..Config::default()
+    });
+
+    // Each line is about 100 bytes, so every request starts a new file
+    for _ in 0..4 {
+        proxy_with(&state, TestRequest::get().uri(&target(&upstream, "/data"))).await;
+    }
+
+    let rotated = |index: usize| PathBuf::from(format!("{}.{}", path.display(), index));
+    read_lines(&rotated(2), 1).await;
+    assert_eq!(read_lines(&path, 1).await.len(), 1);
+    assert_eq!(read_lines(&rotated(1), 1).await.len(), 1);
+    assert_eq!(read_lines(&rotated(2), 1).await.len(), 1);
+    assert!(!rotated(3).exists());
+}
+
+/// An access log to `path` that samples with a seeded RNG.
+fn sampled_log(path: &Path, sample_rate: f64) -> AccessLog {
+    let config = Config {
+        access_log_file: Some(path.to_str().unwrap().to_string()),
+        log_sample_rate: sample_rate,
+        log_slow_request_threshold: Duration::from_millis(500),
+        ..Config::default()
+    };
+    AccessLog::with_rng(&config, fastrand::Rng::with_seed(7))
+}
+
+/// Records a fast request for `path` with `status`.
+fn record(log: &AccessLog, path: &str, status: StatusCode) {
+    let req = TestRequest::get().uri(path).to_http_request();
+    log.record(&req, status, Duration::from_millis(5));
+}
+
+/// Records an error for `/last`, which is always logged, and returns the
+/// lines before it once it has been written.
+async fn lines_before_last(log: &AccessLog, path: &Path) -> Vec<String> {
+    record(log, "/last", StatusCode::INTERNAL_SERVER_ERROR);
+    for _ in 0..100 {
+        let contents = fs::read_to_string(path).unwrap_or_default();
+        if contents.contains("/last") {
+            let mut lines: Vec<String> = contents.lines().map(str::to_string).collect();
+            lines.pop();
+            return lines;
+        }
+        actix_web::rt::time::sleep(Duration::from_millis(20)).await;
+    }
+    panic!("{} does not have the last line", path.display());
+}
+
+#[actix_web::test]
+async fn samples_successful_requests() {
+    let path = log_path("sampled");
+    let log = sampled_log(&path, 0.2);
+
+    for index in 0..1000 {
+        record(&log, &format!("/ok/{}", index), StatusCode::OK);
+    }
+
+    let lines = lines_before_last(&log, &path).await;
+    assert!((150..=250).contains(&lines.len()), "{} lines", lines.len());
+}
+
+#[actix_web::test]
+async fn always_logs_errors_and_slow_requests() {
+    let path = log_path("sampled-errors");
+    let log = sampled_log(&path, 0.0);
+
+    for index in 0..10 {
+        record(&log, &format!("/ok/{}", index), StatusCode::OK);
+        record(&log, &format!("/missing/{}", index), StatusCode::NOT_FOUND);
+        record(&log, &format!("/failed/{}", index), StatusCode::BAD_GATEWAY);
+        let req = TestRequest::get()
+            .uri(&format!("/slow/{}", index))
+            .to_http_request();
+        log.record(&req, StatusCode::OK, Duration::from_millis(500));
+    }
+
+    let lines = lines_before_last(&log, &path).await;
+    assert_eq!(lines.len(), 30);
+    assert!(lines.iter().all(|line| !line.contains("/ok/")));
+    for kind in ["/missing/", "/failed/", "/slow/"] {
+        assert_eq!(lines.iter().filter(|line| line.contains(kind)).count(), 10);
+    }
+}
+
+#[test]
+fn logs_every_request_with_a_non_finite_sample_rate() {
+    for rate in ["nan", "inf", "-inf"] {
+        std::env::set_var("LOG_SAMPLE_RATE", rate);
+        let config = Config::from_env().unwrap();
+        std::env::remove_var("LOG_SAMPLE_RATE");
+        assert_eq!(config.log_sample_rate, 1.0, "{}", rate);
+    }
+}
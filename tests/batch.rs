@@ -0,0 +1,174 @@
+mod common;
+
+use actix_web::http::StatusCode;
+use actix_web::test::TestRequest;
+use serde_json::{json, Value};
+use wiremock::matchers::{header, method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+use common::{proxy, received};
+use rcp::config::Config;
+
+fn batching() -> Config {
+    Config {
+        max_batch_size: 3,
+        ..Config::default()
+    }
+}
+
+#[actix_web::test]
+async fn answers_each_request_of_a_batch() {
+    let upstream = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/users"))
+        .and(header("accept", "application/json"))
+        .respond_with(ResponseTemplate::new(200).set_body_raw(r#"["ada"]"#, "application/json"))
+        .mount(&upstream)
+        .await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(404).set_body_string("missing"))
+        .mount(&upstream)
+        .await;
+
+    let response = proxy(
+        batching(),
+        TestRequest::post().uri("/batch").set_json(json!([
+            {
+                "method": "GET",
+                "url": format!("{}/users", upstream.uri()),
+                "headers": {"Accept": "application/json"},
+            },
+            {"url": format!("{}/missing", upstream.uri())},
+            // Intranet hosts without a domain are refused like in a proxy request
+            {"method": "GET", "url": "http://intranet/secrets"},
+        ])),
+    )
+    .await;
+
+    assert_eq!(response.status, StatusCode::OK);
+    let results: Value = serde_json::from_slice(&response.body).unwrap();
+    assert_eq!(results[0]["status"], 200);
+    assert_eq!(results[0]["body"], r#"["ada"]"#);
+    assert_eq!(results[0]["headers"]["content-type"], "application/json");
+    assert_eq!(results[1]["status"], 404);
+    assert_eq!(results[1]["body"], "missing");
+    assert_eq!(results[2]["status"], 400);
+    assert_eq!(results[2]["body"], "Invalid domain name");
+    assert_eq!(received(&upstream).await.len(), 2);
+}
+
+#[actix_web::test]
+async fn rejects_batches_over_the_limit() {
+    let upstream = MockServer::start().await;
+    let entry = json!({"url": format!("{}/", upstream.uri())});
+
+    let response = proxy(
+        batching(),
+        TestRequest::post()
+            .uri("/batch")
+            .set_json(json!([entry, entry, entry, entry])),
+    )
+    .await;
+
+    assert_eq!(response.status, StatusCode::PAYLOAD_TOO_LARGE);
+    assert!(received(&upstream).await.is_empty());
+}
+
+#[actix_web::test]
+async fn refuses_methods_that_are_not_allowed() {
+    let upstream = MockServer::start().await;
+
+    let response = proxy(
+        batching(),
+        TestRequest::post().uri("/batch").set_json(json!([
+            {"method": "PATCH", "url": format!("{}/", upstream.uri())},
+        ])),
+    )
+    .await;
+
+    let results: Value = serde_json::from_slice(&response.body).unwrap();
+    assert_eq!(results[0]["status"], 405);
+    assert!(received(&upstream).await.is_empty());
+}
+
+#[actix_web::test]
+async fn refuses_method_overrides_that_are_not_allowed() {
+    let upstream = MockServer::start().await;
+
+    let response = proxy(
+        batching(),
+        TestRequest::post().uri("/batch").set_json(json!([
+            {
+                "method": "POST",
+                "url": format!("{}/", upstream.uri()),
+                "headers": {"X-HTTP-Method-Override": "PATCH"},
+            },
+        ])),
+    )
+    .await;
+
+    let results: Value = serde_json::from_slice(&response.body).unwrap();
+    assert_eq!(results[0]["status"], 405);
+    assert!(received(&upstream).await.is_empty());
+}
+
+#[actix_web::test]
+async fn encodes_binary_bodies_as_base64() {
+    let upstream = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/image"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_raw(vec![0x89, 0x50, 0x4e, 0x47, 0xff], "image/png"),
+        )
+        .mount(&upstream)
+        .await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("text"))
+        .mount(&upstream)
+        .await;
+
+    let response = proxy(
+        batching(),
+        TestRequest::post().uri("/batch").set_json(json!([
+            {"url": format!("{}/image", upstream.uri())},
+            {"url": format!("{}/text", upstream.uri())},
+        ])),
+    )
+    .await;
+
+    let results: Value = serde_json::from_slice(&response.body).unwrap();
+    assert_eq!(results[0]["body"], "iVBOR/8=");
+    assert_eq!(results[0]["encoding"], "base64");
+    assert_eq!(results[1]["body"], "text");
+    assert_eq!(results[1].get("encoding"), None);
+}
+
+#[actix_web::test]
+async fn limits_response_bodies_independently_of_request_bodies() {
+    let upstream = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("a body of 20 bytes.."))
+        .mount(&upstream)
+        .await;
+    let config = Config {
+        max_batch_response_size: 10,
+        max_body_size: 1024,
+        ..batching()
+    };
+
+    let response = proxy(
+        config,
+        TestRequest::post().uri("/batch").set_json(json!([
+            {"url": format!("{}/", upstream.uri())},
+        ])),
+    )
+    .await;
+
+    let results: Value = serde_json::from_slice(&response.body).unwrap();
+    assert_eq!(results[0]["status"], 502);
+    assert_eq!(
+        results[0]["body"],
+        "Upstream response too large for a batch"
+    );
+}
@@ -0,0 +1,403 @@
+mod common;
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use actix_web::http::StatusCode;
+use actix_web::test::TestRequest;
+use wiremock::matchers::method;
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+use common::{proxy_with, received, target, ProxyResponse};
+use rcp::clock::ManualClock;
+use rcp::config::Config;
+use rcp::AppState;
+
+async fn upstream() -> MockServer {
+    let upstream = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .insert_header("Cache-Control", "public, max-age=60")
+                .set_body_string("cached body"),
+        )
+        .mount(&upstream)
+        .await;
+    upstream
+}
+
+fn cached_state() -> AppState {
+    AppState::new(Config {
+        cache_max_entries: 10,
+        admin_token: Some("secret".to_string()),
+        ..Config::default()
+    })
+}
+
+async fn admin(state: &AppState, req: TestRequest) -> ProxyResponse {
+    proxy_with(state, req.insert_header(("Authorization", "Bearer secret"))).await
+}
+
+async fn cached_keys(state: &AppState) -> Vec<String> {
+    let response = admin(state, TestRequest::get().uri("/admin/cache")).await;
+    assert_eq!(response.status, StatusCode::OK);
+    let entries: Vec<serde_json::Value> = serde_json::from_slice(&response.body).unwrap();
+    entries
+        .iter()
+        .map(|entry| entry["key"].as_str().unwrap().to_string())
+        .collect()
+}
+
+#[actix_web::test]
+async fn serves_repeated_requests_from_cache() {
+    let upstream = upstream().await;
+    let state = cached_state();
+
+    let first = proxy_with(&state, TestRequest::get().uri(&target(&upstream, "/a"))).await;
+    let second = proxy_with(&state, TestRequest::get().uri(&target(&upstream, "/a"))).await;
+
+    assert_eq!(first.text(), "cached body");
+    assert_eq!(second.status, StatusCode::OK);
+    assert_eq!(second.text(), "cached body");
+    assert_eq!(second.header("Age"), Some("0"));
+    assert_eq!(second.header("X-Cache"), Some("HIT"));
+    assert_eq!(second.header("Access-Control-Allow-Origin"), Some("*"));
+    assert_eq!(received(&upstream).await.len(), 1);
+}
+
+#[actix_web::test]
+async fn expires_entries_after_max_age() {
+    let upstream = upstream().await;
+    let clock = Arc::new(ManualClock::new());
+    let state = AppState::with_clock(
+        Config {
+            cache_max_entries: 10,
+            ..Config::default()
+        },
+        clock.clone(),
+    );
+    proxy_with(&state, TestRequest::get().uri(&target(&upstream, "/a"))).await;
+
+    clock.advance(Duration::from_secs(59));
+    let response = proxy_with(&state, TestRequest::get().uri(&target(&upstream, "/a"))).await;
+    assert_eq!(response.header("X-Cache"), Some("HIT"));
+    assert_eq!(response.header("Age"), Some("59"));
+
+    clock.advance(Duration::from_secs(1));
+    let response = proxy_with(&state, TestRequest::get().uri(&target(&upstream, "/a"))).await;
+    assert_eq!(response.header("X-Cache"), None);
+    assert_eq!(received(&upstream).await.len(), 2);
+}
+
+async fn aged_upstream() -> MockServer {
+    let upstream = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .insert_header("Cache-Control", "public, max-age=60")
+                .insert_header("Age", "20"),
+        )
+        .mount(&upstream)
+        .await;
+    upstream
+}
+
+#[actix_web::test]
+async fn adds_time_in_cache_to_upstream_age() {
+    let upstream = aged_upstream().await;
+    let clock = Arc::new(ManualClock::new());
+    let state = AppState::with_clock(
+        Config {
+            cache_max_entries: 10,
+            ..Config::default()
+        },
+        clock.clone(),
+    );
+
+    let response = proxy_with(&state, TestRequest::get().uri(&target(&upstream, "/a"))).await;
+    assert_eq!(response.header("X-Cache"), None);
+    assert_eq!(response.header("Age"), Some("20"));
+
+    let response = proxy_with(&state, TestRequest::get().uri(&target(&upstream, "/a"))).await;
+    assert_eq!(response.header("X-Cache"), Some("HIT"));
+    assert_eq!(response.header("Age"), Some("20"));
+
+    clock.advance(Duration::from_secs(15));
+    let response = proxy_with(&state, TestRequest::get().uri(&target(&upstream, "/a"))).await;
+    assert_eq!(response.header("Age"), Some("35"));
+
+    clock.advance(Duration::from_secs(24));
+    let response = proxy_with(&state, TestRequest::get().uri(&target(&upstream, "/a"))).await;
+    assert_eq!(response.header("Age"), Some("59"));
+    assert_eq!(received(&upstream).await.len(), 1);
+}
+
+#[actix_web::test]
+async fn counts_upstream_age_against_max_age() {
+    let upstream = aged_upstream().await;
+    let clock = Arc::new(ManualClock::new());
+    let state = AppState::with_clock(
+        Config {
+            cache_max_entries: 10,
+            ..Config::default()
+        },
+        clock.clone(),
+    );
+    proxy_with(&state, TestRequest::get().uri(&target(&upstream, "/a"))).await;
+
+    clock.advance(Duration::from_secs(40));
+    let response = proxy_with(&state, TestRequest::get().uri(&target(&upstream, "/a"))).await;
+
+    assert_eq!(response.header("X-Cache"), None);
+    assert_eq!(response.header("Age"), Some("20"));
+    assert_eq!(received(&upstream).await.len(), 2);
+}
+
+#[actix_web::test]
+async fn lists_cached_entries() {
+    let upstream = upstream().await;
+    let state = cached_state();
+    proxy_with(&state, TestRequest::get().uri(&target(&upstream, "/a"))).await;
+
+    let response = admin(&state, TestRequest::get().uri("/admin/cache")).await;
+
+    let entries: Vec<serde_json::Value> = serde_json::from_slice(&response.body).unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0]["key"], format!("{}/a", upstream.uri()));
+    assert_eq!(entries[0]["size"], "cached body".len());
+    let ttl = entries[0]["ttl_seconds"].as_u64().unwrap();
+    assert!(ttl > 0 && ttl <= 60);
+}
+
+#[actix_web::test]
+async fn purges_single_entry() {
+    let upstream = upstream().await;
+    let state = cached_state();
+    proxy_with(&state, TestRequest::get().uri(&target(&upstream, "/a"))).await;
+    proxy_with(&state, TestRequest::get().uri(&target(&upstream, "/b"))).await;
+
+    let url: String =
+        form_urlencoded::byte_serialize(format!("{}/a", upstream.uri()).as_bytes()).collect();
+    let response = admin(
+        &state,
+        TestRequest::delete().uri(&format!("/admin/cache?url={}", url)),
+    )
+    .await;
+
+    assert_eq!(response.text(), r#"{"purged":1}"#);
+    assert_eq!(cached_keys(&state).await, [format!("{}/b", upstream.uri())]);
+
+    proxy_with(&state, TestRequest::get().uri(&target(&upstream, "/a"))).await;
+    assert_eq!(received(&upstream).await.len(), 3);
+}
+
+#[actix_web::test]
+async fn purges_all_entries() {
+    let upstream = upstream().await;
+    let state = cached_state();
+    proxy_with(&state, TestRequest::get().uri(&target(&upstream, "/a"))).await;
+    proxy_with(&state, TestRequest::get().uri(&target(&upstream, "/b"))).await;
+
+    let response = admin(&state, TestRequest::delete().uri("/admin/cache")).await;
+
+    assert_eq!(response.text(), r#"{"purged":2}"#);
+    assert!(cached_keys(&state).await.is_empty());
+}
+
+#[actix_web::test]
+async fn requires_admin_token_for_cache_endpoints() {
+    let state = cached_state();
+
+    let response = proxy_with(&state, TestRequest::get().uri("/admin/cache")).await;
+    assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+
+    let response = proxy_with(&state, TestRequest::delete().uri("/admin/cache")).await;
+    assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+}
+
+/// Caches a response that is stale after a second, then fails with `status`.
+async fn failing_upstream(status: u16) -> MockServer {
+    let upstream = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .insert_header("Cache-Control", "max-age=1")
+                .set_body_string("old body"),
+        )
+        .up_to_n_times(1)
+        .mount(&upstream)
+        .await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(status).set_body_string("upstream error"))
+        .mount(&upstream)
+        .await;
+    upstream
+}
+
+fn stale_state(stale_if_error: u64) -> (AppState, Arc<ManualClock>) {
+    let clock = Arc::new(ManualClock::new());
+    let state = AppState::with_clock(
+        Config {
+            cache_max_entries: 10,
+            cache_stale_if_error: Duration::from_secs(stale_if_error),
+            ..Config::default()
+        },
+        clock.clone(),
+    );
+    (state, clock)
+}
+
+#[actix_web::test]
+async fn serves_stale_response_on_upstream_error() {
+    let upstream = failing_upstream(503).await;
+    let (state, clock) = stale_state(60);
+    proxy_with(&state, TestRequest::get().uri(&target(&upstream, "/a"))).await;
+    clock.advance(Duration::from_secs(2));
+
+    let response = proxy_with(&state, TestRequest::get().uri(&target(&upstream, "/a"))).await;
+
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.text(), "old body");
+    assert_eq!(response.header("X-Cache"), Some("STALE"));
+    assert_eq!(received(&upstream).await.len(), 2);
+}
+
+#[actix_web::test]
+async fn serves_stale_response_when_upstream_is_unreachable() {
+    let upstream = failing_upstream(500).await;
+    let url = target(&upstream, "/a");
+    let (state, clock) = stale_state(60);
+    proxy_with(&state, TestRequest::get().uri(&url)).await;
+    drop(upstream);
+    clock.advance(Duration::from_secs(2));
+
+    let response = proxy_with(&state, TestRequest::get().uri(&url)).await;
+
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.text(), "old body");
+    assert_eq!(response.header("X-Cache"), Some("STALE"));
+}
+
+#[actix_web::test]
+async fn passes_upstream_error_through_without_stale_window() {
+    let upstream = failing_upstream(503).await;
+    let (state, clock) = stale_state(0);
+    proxy_with(&state, TestRequest::get().uri(&target(&upstream, "/a"))).await;
+    clock.advance(Duration::from_secs(2));
+
+    let response = proxy_with(&state, TestRequest::get().uri(&target(&upstream, "/a"))).await;
+
+    assert_eq!(response.status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(response.text(), "upstream error");
+}
+
+fn cache_requests(state: &AppState, result: &str) -> u64 {
+    state
+        .metrics
+        .cache_requests
+        .with_label_values(&[result])
+        .get()
+}
+
+#[actix_web::test]
+async fn counts_hits_misses_and_bypasses() {
+    let upstream = upstream().await;
+    let state = cached_state();
+
+    proxy_with(&state, TestRequest::get().uri(&target(&upstream, "/a"))).await;
+    assert_eq!(cache_requests(&state, "miss"), 1);
+    assert_eq!(cache_requests(&state, "hit"), 0);
+
+    proxy_with(&state, TestRequest::get().uri(&target(&upstream, "/a"))).await;
+    assert_eq!(cache_requests(&state, "miss"), 1);
+    assert_eq!(cache_requests(&state, "hit"), 1);
+
+    proxy_with(
+        &state,
+        TestRequest::get()
+            .uri(&target(&upstream, "/a"))
+            .insert_header(("Cookie", "session=1")),
+    )
+    .await;
+    assert_eq!(cache_requests(&state, "bypass"), 1);
+    assert_eq!(cache_requests(&state, "hit"), 1);
+}
+
+#[actix_web::test]
+async fn coalesces_concurrent_misses() {
+    let upstream = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .insert_header("Cache-Control", "max-age=60")
+                .set_body_string("cached body")
+                .set_delay(Duration::from_millis(300)),
+        )
+        .mount(&upstream)
+        .await;
+    let state = AppState::new(Config {
+        cache_max_entries: 10,
+        cache_coalesce_requests: true,
+        ..Config::default()
+    });
+
+    let (first, second) = futures_util::future::join(
+        proxy_with(&state, TestRequest::get().uri(&target(&upstream, "/a"))),
+        proxy_with(&state, TestRequest::get().uri(&target(&upstream, "/a"))),
+    )
+    .await;
+
+    assert_eq!(first.text(), "cached body");
+    assert_eq!(second.text(), "cached body");
+    assert_eq!(second.header("X-Cache"), Some("HIT"));
+    assert_eq!(received(&upstream).await.len(), 1);
+    assert_eq!(state.metrics.cache_coalesced.get(), 1);
+    assert_eq!(cache_requests(&state, "miss"), 1);
+}
+
+#[actix_web::test]
+async fn does_not_cache_bodies_over_the_entry_limit() {
+    let upstream = upstream().await;
+    let state = AppState::new(Config {
+        cache_max_entries: 10,
+        cache_max_body_size: "cached body".len() - 1,
+        admin_token: Some("secret".to_string()),
+        ..Config::default()
+    });
+
+    proxy_with(&state, TestRequest::get().uri(&target(&upstream, "/a"))).await;
+    proxy_with(&state, TestRequest::get().uri(&target(&upstream, "/a"))).await;
+
+    assert!(cached_keys(&state).await.is_empty());
+    assert_eq!(received(&upstream).await.len(), 2);
+}
+
+#[actix_web::test]
+async fn bounds_total_size_of_cached_bodies() {
+    let upstream = upstream().await;
+    let state = AppState::new(Config {
+        cache_max_entries: 10,
+        cache_max_bytes: 2 * "cached body".len() + 1,
+        admin_token: Some("secret".to_string()),
+        ..Config::default()
+    });
+
+    for path in ["/a", "/b", "/c", "/d"] {
+        proxy_with(&state, TestRequest::get().uri(&target(&upstream, path))).await;
+    }
+
+    let keys = cached_keys(&state).await;
+    assert_eq!(keys.len(), 2);
+    assert!(keys.contains(&format!("{}/d", upstream.uri())));
+}
+
+#[test]
+fn limits_single_bodies_to_the_total_size() {
+    std::env::set_var("CACHE_MAX_BYTES", "4096");
+    std::env::set_var("CACHE_MAX_BODY_BYTES", "8192");
+    let config = Config::from_env().unwrap();
+    std::env::remove_var("CACHE_MAX_BYTES");
+    std::env::remove_var("CACHE_MAX_BODY_BYTES");
+
+    assert_eq!(config.cache_max_body_size, 4096);
+}
@@ -0,0 +1,118 @@
+mod common;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use actix_web::http::StatusCode;
+use actix_web::test::TestRequest;
+use wiremock::matchers::method;
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+use common::{proxy, target};
+use rcp::config::Config;
+
+/// A fresh, empty capture directory in the temporary directory.
+fn capture_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("rcp-capture-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    dir
+}
+
+fn capturing(dir: &Path) -> Config {
+    Config {
+        debug_capture_dir: Some(dir.to_str().unwrap().to_string()),
+        debug_capture_pattern: "/api/*".to_string(),
+        debug_capture_max_size: 16,
+        ..Config::default()
+    }
+}
+
+/// The contents of the capture files whose names end with `suffix`.
+fn captures(dir: &Path, suffix: &str) -> Vec<String> {
+    let mut files: Vec<_> = fs::read_dir(dir)
+        .map(|entries| entries.map(|entry| entry.unwrap().path()).collect())
+        .unwrap_or_default();
+    files.retain(|path: &PathBuf| path.to_str().unwrap().ends_with(suffix));
+    files.sort();
+    files
+        .iter()
+        .map(|path| fs::read_to_string(path).unwrap())
+        .collect()
+}
+
+/// The contents of the capture files whose names end with `suffix`, once
+/// there are `count` of them, as they are written in the background.
+async fn wait_for_captures(dir: &Path, suffix: &str, count: usize) -> Vec<String> {
+    for _ in 0..100 {
+        let captures = captures(dir, suffix);
+        if captures.len() >= count {
+            return captures;
+        }
+        actix_web::rt::time::sleep(Duration::from_millis(20)).await;
+    }
+    panic!("{} has no {} captures", dir.display(), count);
+}
+
+async fn upstream() -> MockServer {
+    let upstream = MockServer::start().await;
+    Mock::given(method("POST"))
+        .respond_with(
+            ResponseTemplate::new(201)
+                .insert_header("Set-Cookie", "session=secret")
+                .set_body_raw("a response body longer than the limit", "text/plain"),
+        )
+        .mount(&upstream)
+        .await;
+    upstream
+}
+
+#[actix_web::test]
+async fn captures_matching_exchanges() {
+    let upstream = upstream().await;
+    let dir = capture_dir("matching");
+
+    let response = proxy(
+        capturing(&dir),
+        TestRequest::post()
+            .uri(&target(&upstream, "/api/items"))
+            .insert_header(("Authorization", "Bearer secret"))
+            .insert_header(("X-Custom", "value"))
+            .set_payload("request body"),
+    )
+    .await;
+
+    // The client gets the whole response as usual
+    assert_eq!(response.status, StatusCode::CREATED);
+    assert_eq!(response.text(), "a response body longer than the limit");
+
+    let requests = wait_for_captures(&dir, "-request.txt", 1).await;
+    assert_eq!(requests.len(), 1);
+    assert!(requests[0].starts_with(&format!("POST {}/api/items\n", upstream.uri())));
+    assert!(requests[0].contains("x-custom: value\n"));
+    assert!(requests[0].contains("authorization: [redacted]\n"));
+    assert!(!requests[0].contains("secret"));
+    assert!(requests[0].ends_with("\n\nrequest body"));
+
+    let responses = wait_for_captures(&dir, "-response.txt", 1).await;
+    assert_eq!(responses.len(), 1);
+    assert!(responses[0].starts_with("201\n"));
+    assert!(responses[0].contains("set-cookie: [redacted]\n"));
+    assert!(responses[0].contains("# body incomplete or cut off after 16 bytes\n"));
+    assert!(responses[0].ends_with("\n\na response body "));
+}
+
+#[actix_web::test]
+async fn skips_other_paths() {
+    let upstream = upstream().await;
+    let dir = capture_dir("other");
+
+    let response = proxy(
+        capturing(&dir),
+        TestRequest::post().uri(&target(&upstream, "/health")),
+    )
+    .await;
+
+    assert_eq!(response.status, StatusCode::CREATED);
+    assert!(captures(&dir, ".txt").is_empty());
+}
@@ -0,0 +1,38 @@
+use rcp::client::{UpstreamClient, UpstreamHttpVersion};
+use rcp::config::Config;
+use rcp::metrics::Metrics;
+
+#[test]
+fn uses_http3_only_when_requested() {
+    let client = UpstreamClient::new(&Config::default(), &Metrics::new());
+
+    assert!(!client.http3_enabled());
+}
+
+#[cfg(feature = "h3")]
+#[actix_web::test]
+async fn enables_http3_with_feature_and_setting() {
+    let client = UpstreamClient::new(
+        &Config {
+            upstream_http_version: UpstreamHttpVersion::Http3,
+            ..Config::default()
+        },
+        &Metrics::new(),
+    );
+
+    assert!(client.http3_enabled());
+}
+
+#[cfg(not(feature = "h3"))]
+#[test]
+fn ignores_http3_setting_without_feature() {
+    let client = UpstreamClient::new(
+        &Config {
+            upstream_http_version: UpstreamHttpVersion::Http3,
+            ..Config::default()
+        },
+        &Metrics::new(),
+    );
+
+    assert!(!client.http3_enabled());
+}
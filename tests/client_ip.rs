@@ -0,0 +1,267 @@
+mod common;
+
+use std::net::SocketAddr;
+
+use actix_web::http::StatusCode;
+use actix_web::test::TestRequest;
+use wiremock::matchers::method;
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+use common::{header_values, proxy, received, target};
+use rcp::client_ip::parse_cidr;
+use rcp::config::Config;
+
+async fn upstream() -> MockServer {
+    let upstream = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&upstream)
+        .await;
+    upstream
+}
+
+fn allowlist_config(cidrs: &[&str], trust_forwarded_for: bool) -> Config {
+    Config {
+        client_ip_allowlist: cidrs.iter().map(|cidr| parse_cidr(cidr).unwrap()).collect(),
+        trust_forwarded_for,
+        ..Config::default()
+    }
+}
+
+fn peer(address: &str) -> SocketAddr {
+    address.parse().unwrap()
+}
+
+#[actix_web::test]
+async fn allows_client_in_ipv4_range() {
+    let upstream = upstream().await;
+
+    let response = proxy(
+        allowlist_config(&["10.0.0.0/8"], false),
+        TestRequest::get()
+            .uri(&target(&upstream, "/"))
+            .peer_addr(peer("10.1.2.3:40000")),
+    )
+    .await;
+
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(received(&upstream).await.len(), 1);
+}
+
+#[actix_web::test]
+async fn allows_client_in_ipv6_range() {
+    let upstream = upstream().await;
+
+    let response = proxy(
+        allowlist_config(&["10.0.0.0/8", "2001:db8::/32"], false),
+        TestRequest::get()
+            .uri(&target(&upstream, "/"))
+            .peer_addr(peer("[2001:db8::1]:40000")),
+    )
+    .await;
+
+    assert_eq!(response.status, StatusCode::OK);
+}
+
+#[actix_web::test]
+async fn rejects_client_outside_ranges() {
+    let upstream = upstream().await;
+
+    let response = proxy(
+        allowlist_config(&["10.0.0.0/8", "2001:db8::/32"], false),
+        TestRequest::get()
+            .uri(&target(&upstream, "/"))
+            .peer_addr(peer("192.168.1.1:40000")),
+    )
+    .await;
+
+    assert_eq!(response.status, StatusCode::FORBIDDEN);
+    assert!(received(&upstream).await.is_empty());
+}
+
+#[actix_web::test]
+async fn ignores_forwarded_for_unless_trusted() {
+    let upstream = upstream().await;
+
+    let response = proxy(
+        allowlist_config(&["10.0.0.0/8"], false),
+        TestRequest::get()
+            .uri(&target(&upstream, "/"))
+            .peer_addr(peer("192.168.1.1:40000"))
+            .insert_header(("X-Forwarded-For", "10.1.2.3")),
+    )
+    .await;
+
+    assert_eq!(response.status, StatusCode::FORBIDDEN);
+}
+
+#[actix_web::test]
+async fn uses_trusted_forwarded_for() {
+    let upstream = upstream().await;
+
+    let response = proxy(
+        allowlist_config(&["10.0.0.0/8"], true),
+        TestRequest::get()
+            .uri(&target(&upstream, "/"))
+            .peer_addr(peer("192.168.1.1:40000"))
+            .insert_header(("X-Forwarded-For", "10.1.2.3, 192.168.1.1")),
+    )
+    .await;
+
+    assert_eq!(response.status, StatusCode::OK);
+}
+
+fn forwarded_proto(upstream_request: &wiremock::Request) -> Vec<String> {
+    header_values(upstream_request, "X-Forwarded-Proto")
+}
+
+#[actix_web::test]
+async fn forwards_connection_scheme_by_default() {
+    let upstream = upstream().await;
+
+    proxy(
+        Config::default(),
+        TestRequest::get()
+            .uri(&target(&upstream, "/"))
+            .insert_header(("X-Forwarded-Proto", "https")),
+    )
+    .await;
+
+    let requests = received(&upstream).await;
+    assert_eq!(forwarded_proto(&requests[0]), ["http"]);
+}
+
+#[actix_web::test]
+async fn forwards_trusted_forwarded_proto() {
+    let upstream = upstream().await;
+    let config = Config {
+        trust_forwarded_proto: true,
+        ..Config::default()
+    };
+
+    proxy(
+        config,
+        TestRequest::get()
+            .uri(&target(&upstream, "/"))
+            .insert_header(("X-Forwarded-Proto", "HTTPS, http")),
+    )
+    .await;
+
+    let requests = received(&upstream).await;
+    assert_eq!(forwarded_proto(&requests[0]), ["https"]);
+}
+
+#[actix_web::test]
+async fn ignores_invalid_trusted_forwarded_proto() {
+    let upstream = upstream().await;
+    let config = Config {
+        trust_forwarded_proto: true,
+        ..Config::default()
+    };
+
+    proxy(
+        config,
+        TestRequest::get()
+            .uri(&target(&upstream, "/"))
+            .insert_header(("X-Forwarded-Proto", "gopher")),
+    )
+    .await;
+
+    let requests = received(&upstream).await;
+    assert_eq!(forwarded_proto(&requests[0]), ["http"]);
+}
+
+fn real_ip_config(trust_forwarded_for: bool) -> Config {
+    Config {
+        send_x_real_ip: true,
+        trust_forwarded_for,
+        ..Config::default()
+    }
+}
+
+#[actix_web::test]
+async fn sends_connection_address_as_real_ip() {
+    let upstream = upstream().await;
+
+    proxy(
+        real_ip_config(false),
+        TestRequest::get()
+            .uri(&target(&upstream, "/"))
+            .peer_addr(peer("203.0.113.7:4000"))
+            .insert_header(("X-Forwarded-For", "198.51.100.1"))
+            .insert_header(("X-Real-IP", "192.0.2.99")),
+    )
+    .await;
+
+    let requests = received(&upstream).await;
+    assert_eq!(header_values(&requests[0], "X-Real-IP"), ["203.0.113.7"]);
+}
+
+#[actix_web::test]
+async fn sends_trusted_forwarded_address_as_real_ip() {
+    let upstream = upstream().await;
+
+    proxy(
+        real_ip_config(true),
+        TestRequest::get()
+            .uri(&target(&upstream, "/"))
+            .peer_addr(peer("10.0.0.2:4000"))
+            .insert_header(("X-Forwarded-For", "198.51.100.1, 10.0.0.1")),
+    )
+    .await;
+
+    let requests = received(&upstream).await;
+    assert_eq!(header_values(&requests[0], "X-Real-IP"), ["198.51.100.1"]);
+    // X-Forwarded-For is still passed on
+    assert_eq!(
+        header_values(&requests[0], "X-Forwarded-For"),
+        ["198.51.100.1, 10.0.0.1"]
+    );
+}
+
+fn trusted_proxy_config() -> Config {
+    Config {
+        send_x_real_ip: true,
+        trusted_proxies: vec![parse_cidr("10.0.0.0/24").unwrap()],
+        ..Config::default()
+    }
+}
+
+#[actix_web::test]
+async fn uses_forwarded_headers_from_trusted_proxies() {
+    let upstream = upstream().await;
+
+    proxy(
+        trusted_proxy_config(),
+        TestRequest::get()
+            .uri(&target(&upstream, "/"))
+            .peer_addr(peer("10.0.0.2:4000"))
+            // The client may prepend anything, the trusted proxies append
+            .insert_header(("X-Forwarded-For", "192.0.2.99, 198.51.100.1, 10.0.0.1"))
+            .insert_header(("X-Forwarded-Proto", "https")),
+    )
+    .await;
+
+    let requests = received(&upstream).await;
+    assert_eq!(header_values(&requests[0], "X-Real-IP"), ["198.51.100.1"]);
+    assert_eq!(forwarded_proto(&requests[0]), ["https"]);
+}
+
+#[actix_web::test]
+async fn ignores_forwarded_headers_from_other_peers() {
+    let upstream = upstream().await;
+
+    proxy(
+        trusted_proxy_config(),
+        TestRequest::get()
+            .uri(&target(&upstream, "/"))
+            .peer_addr(peer("203.0.113.7:4000"))
+            .insert_header(("X-Forwarded-For", "198.51.100.1"))
+            .insert_header(("X-Forwarded-Proto", "https")),
+    )
+    .await;
+
+    let requests = received(&upstream).await;
+    assert_eq!(header_values(&requests[0], "X-Real-IP"), ["203.0.113.7"]);
+    assert_eq!(forwarded_proto(&requests[0]), ["http"]);
+}
@@ -0,0 +1,115 @@
+#![allow(dead_code)]
+
+use actix_web::http::StatusCode;
+use actix_web::test::{self, TestRequest};
+use actix_web::{web, App, HttpServer};
+use wiremock::MockServer;
+
+use rcp::config::Config;
+use rcp::connection;
+use rcp::AppState;
+
+/// The proxied response as seen by the client.
+pub struct ProxyResponse {
+    pub status: StatusCode,
+    pub headers: actix_web::http::header::HeaderMap,
+    pub body: web::Bytes,
+}
+
+impl ProxyResponse {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name).and_then(|value| value.to_str().ok())
+    }
+
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+}
+
+/// Sends a request through a fresh proxy app with the given configuration.
+pub async fn proxy(config: Config, req: TestRequest) -> ProxyResponse {
+    proxy_with(&AppState::new(config), req).await
+}
+
+/// Sends a request through an in-process proxy app using existing state,
+/// so that limits and metrics carry over between requests.
+pub async fn proxy_with(state: &AppState, req: TestRequest) -> ProxyResponse {
+    let state = state.clone();
+    let app = test::init_service(App::new().configure(move |cfg| state.configure(cfg))).await;
+    let response = test::call_service(&app, req.to_request()).await;
+    let status = response.status();
+    let headers = response.headers().clone();
+    let body = test::read_body(response).await;
+    ProxyResponse {
+        status,
+        headers,
+        body,
+    }
+}
+
+/// Serves the proxy on a random local port and returns its base URL, for
+/// tests that need real connections and HTTP framing.
+pub fn serve(state: &AppState) -> String {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let on_connect = connection::on_connect(&state.config);
+    let state = state.clone();
+    let server = HttpServer::new(move || {
+        let state = state.clone();
+        App::new().configure(move |cfg| state.configure(cfg))
+    })
+    .on_connect(on_connect)
+    .workers(1)
+    .disable_signals()
+    .listen(listener)
+    .unwrap()
+    .run();
+    actix_web::rt::spawn(server);
+    format!("http://{}", address)
+}
+
+/// The proxy path that forwards to `path` on the mock upstream.
+pub fn target(upstream: &MockServer, path: &str) -> String {
+    format!("/{}{}", upstream.uri(), path)
+}
+
+/// The requests the mock upstream has received so far.
+pub async fn received(upstream: &MockServer) -> Vec<wiremock::Request> {
+    upstream.received_requests().await.unwrap_or_default()
+}
+
+/// All values of a header on a request received by the mock upstream.
+pub fn header_values(request: &wiremock::Request, name: &str) -> Vec<String> {
+    request
+        .headers
+        .get_all(name)
+        .iter()
+        .map(|value| value.to_str().unwrap().to_string())
+        .collect()
+}
+
+/// The address of an upstream that serves TLS with a self-signed
+/// certificate for `localhost`, which clients don't trust. It accepts one
+/// connection.
+pub fn untrusted_tls_upstream() -> String {
+    use rustls::pki_types::pem::PemObject;
+    use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+
+    let certificate =
+        CertificateDer::from_pem_slice(include_bytes!("../fixtures/self-signed.crt")).unwrap();
+    let key = PrivateKeyDer::from_pem_slice(include_bytes!("../fixtures/self-signed.key")).unwrap();
+    let config = rustls::ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(vec![certificate], key)
+        .unwrap();
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut connection = rustls::ServerConnection::new(std::sync::Arc::new(config)).unwrap();
+        // Fails once the client refuses the certificate
+        while connection.complete_io(&mut stream).is_ok() && connection.is_handshaking() {}
+    });
+    address.to_string()
+}
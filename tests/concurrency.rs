@@ -0,0 +1,100 @@
+mod common;
+
+use std::time::Duration;
+
+use actix_web::http::StatusCode;
+use actix_web::test::TestRequest;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+use common::{proxy_with, target};
+use rcp::config::Config;
+use rcp::AppState;
+
+async fn slow_upstream() -> MockServer {
+    let upstream = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/slow"))
+        .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_millis(300)))
+        .mount(&upstream)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/fast"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&upstream)
+        .await;
+    upstream
+}
+
+#[actix_web::test]
+async fn sheds_load_with_jittered_retry_after() {
+    let upstream = slow_upstream().await;
+    let state = AppState::new(Config {
+        max_concurrent_requests: 1,
+        overload_retry_after: 2,
+        retry_after_jitter: 3,
+        ..Config::default()
+    });
+
+    let slow = proxy_with(&state, TestRequest::get().uri(&target(&upstream, "/slow")));
+    let shed = async {
+        actix_web::rt::time::sleep(Duration::from_millis(100)).await;
+        proxy_with(&state, TestRequest::get().uri(&target(&upstream, "/fast"))).await
+    };
+    let (slow, shed) = futures_util::join!(slow, shed);
+
+    assert_eq!(slow.status, StatusCode::OK);
+    assert_eq!(shed.status, StatusCode::SERVICE_UNAVAILABLE);
+    let retry_after: u64 = shed.header("Retry-After").unwrap().parse().unwrap();
+    assert!((2..=5).contains(&retry_after), "{}", retry_after);
+
+    // The slot is released once the slow request is done
+    let response = proxy_with(&state, TestRequest::get().uri(&target(&upstream, "/fast"))).await;
+    assert_eq!(response.status, StatusCode::OK);
+}
+
+#[actix_web::test]
+async fn limits_each_client_separately() {
+    let upstream = slow_upstream().await;
+    let state = AppState::new(Config {
+        max_concurrent_requests: 10,
+        max_concurrent_per_client: 1,
+        ..Config::default()
+    });
+    let greedy = "10.0.0.1:1234".parse().unwrap();
+    let other = "10.0.0.2:1234".parse().unwrap();
+
+    let slow = proxy_with(
+        &state,
+        TestRequest::get()
+            .uri(&target(&upstream, "/slow"))
+            .peer_addr(greedy),
+    );
+    let rest = async {
+        actix_web::rt::time::sleep(Duration::from_millis(100)).await;
+        let limited = proxy_with(
+            &state,
+            TestRequest::get()
+                .uri(&target(&upstream, "/fast"))
+                .peer_addr(greedy),
+        )
+        .await;
+        let unaffected = proxy_with(
+            &state,
+            TestRequest::get()
+                .uri(&target(&upstream, "/fast"))
+                .peer_addr(other),
+        )
+        .await;
+        (limited, unaffected)
+    };
+    let (slow, (limited, unaffected)) = futures_util::join!(slow, rest);
+
+    assert_eq!(slow.status, StatusCode::OK);
+    assert_eq!(limited.status, StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(limited.header("Retry-After"), Some("1"));
+    assert_eq!(unaffected.status, StatusCode::OK);
+
+    // Clients are forgotten once their requests are done
+    assert_eq!(state.concurrency.active_clients(), 0);
+}
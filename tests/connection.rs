@@ -0,0 +1,118 @@
+mod common;
+
+use std::io::{ErrorKind, Read, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+use common::serve;
+use rcp::config::Config;
+use rcp::AppState;
+
+/// Sends `count` requests one after another on a single connection.
+/// Returns the responses and whether the proxy closed the connection
+/// afterwards.
+async fn requests_on_one_connection(state: &AppState, count: usize) -> (Vec<String>, bool) {
+    let address = serve(state).trim_start_matches("http://").to_string();
+    actix_web::rt::task::spawn_blocking(move || {
+        let mut stream = TcpStream::connect(address).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_millis(500)))
+            .unwrap();
+
+        let mut responses = Vec::new();
+        for _ in 0..count {
+            stream
+                .write_all(b"GET /robots.txt HTTP/1.1\r\nHost: localhost\r\n\r\n")
+                .unwrap();
+            let mut response = Vec::new();
+            let mut buffer = [0; 1024];
+            while !response.ends_with(b"Disallow: /\n") {
+                let read = stream.read(&mut buffer).unwrap();
+                assert!(read > 0, "connection closed early");
+                response.extend_from_slice(&buffer[..read]);
+            }
+            responses.push(String::from_utf8_lossy(&response).to_ascii_lowercase());
+        }
+
+        let closed = match stream.read(&mut [0; 1]) {
+            Ok(0) => true,
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => false,
+            result => panic!("unexpected read result {:?}", result),
+        };
+        (responses, closed)
+    })
+    .await
+    .unwrap()
+}
+
+#[actix_web::test]
+async fn closes_connection_after_request_limit() {
+    let state = AppState::new(Config {
+        max_requests_per_connection: 2,
+        ..Config::default()
+    });
+
+    let (responses, closed) = requests_on_one_connection(&state, 2).await;
+
+    assert!(!responses[0].contains("connection: close"));
+    assert!(responses[1].contains("connection: close"));
+    assert!(closed);
+}
+
+#[actix_web::test]
+async fn keeps_connection_open_without_limit() {
+    let state = AppState::new(Config::default());
+
+    let (responses, closed) = requests_on_one_connection(&state, 3).await;
+
+    assert!(responses
+        .iter()
+        .all(|response| !response.contains("connection: close")));
+    assert!(!closed);
+}
+
+/// Sends a request on `stream` and returns what the proxy answered until
+/// it closed the connection or went quiet.
+fn robots_txt(stream: &mut TcpStream) -> String {
+    stream
+        .set_read_timeout(Some(Duration::from_millis(500)))
+        .unwrap();
+    let _ = stream.write_all(b"GET /robots.txt HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    let mut response = Vec::new();
+    let mut buffer = [0; 1024];
+    while !response.ends_with(b"Disallow: /\n") {
+        match stream.read(&mut buffer) {
+            Ok(0) | Err(_) => break,
+            Ok(read) => response.extend_from_slice(&buffer[..read]),
+        }
+    }
+    String::from_utf8_lossy(&response).into_owned()
+}
+
+#[actix_web::test]
+async fn refuses_connections_over_the_limit_per_ip() {
+    let state = AppState::new(Config {
+        max_connections_per_ip: 2,
+        ..Config::default()
+    });
+    let address = serve(&state).trim_start_matches("http://").to_string();
+
+    actix_web::rt::task::spawn_blocking(move || {
+        let mut first = TcpStream::connect(&address).unwrap();
+        let mut second = TcpStream::connect(&address).unwrap();
+        assert!(robots_txt(&mut first).starts_with("HTTP/1.1 200"));
+        assert!(robots_txt(&mut second).starts_with("HTTP/1.1 200"));
+
+        let mut third = TcpStream::connect(&address).unwrap();
+        assert_eq!(robots_txt(&mut third), "");
+
+        // Closed connections make room for new ones
+        drop(first);
+        std::thread::sleep(Duration::from_millis(200));
+        let mut fourth = TcpStream::connect(&address).unwrap();
+        assert!(robots_txt(&mut fourth).starts_with("HTTP/1.1 200"));
+        assert!(robots_txt(&mut second).starts_with("HTTP/1.1 200"));
+    })
+    .await
+    .unwrap();
+}
@@ -0,0 +1,101 @@
+mod common;
+
+use actix_web::http::StatusCode;
+use actix_web::test::TestRequest;
+use wiremock::matchers::path;
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+use common::{proxy, target};
+use rcp::config::Config;
+use rcp::config_file::ConfigFile;
+
+async fn upstream() -> MockServer {
+    let upstream = MockServer::start().await;
+    Mock::given(path("/page"))
+        .respond_with(ResponseTemplate::new(200).set_body_raw("<p>hi</p>", "text/html"))
+        .mount(&upstream)
+        .await;
+    Mock::given(path("/data"))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_raw("{}", "application/json; charset=utf-8"),
+        )
+        .mount(&upstream)
+        .await;
+    upstream
+}
+
+fn allowing(host_rule: &str) -> Config {
+    Config {
+        allowed_content_types: vec!["application/json".to_string(), "text/*".to_string()],
+        file: ConfigFile::parse(host_rule).unwrap(),
+        ..Config::default()
+    }
+}
+
+#[actix_web::test]
+async fn applies_global_content_types() {
+    let upstream = upstream().await;
+    let config = || Config {
+        allowed_content_types: vec!["application/json".to_string()],
+        ..Config::default()
+    };
+
+    let response = proxy(
+        config(),
+        TestRequest::get().uri(&target(&upstream, "/data")),
+    )
+    .await;
+    assert_eq!(response.status, StatusCode::OK);
+
+    let response = proxy(
+        config(),
+        TestRequest::get().uri(&target(&upstream, "/page")),
+    )
+    .await;
+    assert_eq!(response.status, StatusCode::BAD_GATEWAY);
+    assert_eq!(response.text(), "Upstream content type not allowed");
+}
+
+#[actix_web::test]
+async fn host_rule_rejects_globally_allowed_type() {
+    let upstream = upstream().await;
+    let config = || {
+        allowing(
+            r#"
+            [hosts."127.0.0.1"]
+            allowed_content_types = ["application/json"]
+            "#,
+        )
+    };
+
+    let response = proxy(
+        config(),
+        TestRequest::get().uri(&target(&upstream, "/page")),
+    )
+    .await;
+    assert_eq!(response.status, StatusCode::BAD_GATEWAY);
+
+    let response = proxy(
+        config(),
+        TestRequest::get().uri(&target(&upstream, "/data")),
+    )
+    .await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.text(), "{}");
+}
+
+#[actix_web::test]
+async fn falls_back_to_global_rule_for_other_hosts() {
+    let upstream = upstream().await;
+    let config = allowing(
+        r#"
+        [hosts."api.example.com"]
+        allowed_content_types = ["application/json"]
+        "#,
+    );
+
+    let response = proxy(config, TestRequest::get().uri(&target(&upstream, "/page"))).await;
+
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.text(), "<p>hi</p>");
+}
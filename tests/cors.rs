@@ -0,0 +1,471 @@
+mod common;
+
+use actix_web::http::StatusCode;
+use actix_web::test::TestRequest;
+use wiremock::matchers::method;
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+use common::{proxy, received, target};
+use rcp::config::{Config, OptionsMode};
+use rcp::config_file::ConfigFile;
+use rcp::cors::{CorsConfig, OriginPattern};
+
+async fn upstream() -> MockServer {
+    let upstream = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(200).insert_header("X-Total-Count", "42"))
+        .mount(&upstream)
+        .await;
+    upstream
+}
+
+#[actix_web::test]
+async fn uses_default_max_age_and_expose_headers() {
+    let upstream = upstream().await;
+
+    let response = proxy(
+        Config::default(),
+        TestRequest::get().uri(&target(&upstream, "/")),
+    )
+    .await;
+
+    assert_eq!(response.header("Access-Control-Max-Age"), Some("3600"));
+    let exposed = response.header("Access-Control-Expose-Headers").unwrap();
+    assert!(exposed.contains("Content-Length"));
+    assert!(exposed.contains("ETag"));
+}
+
+#[actix_web::test]
+async fn uses_configured_max_age_and_expose_headers() {
+    let upstream = upstream().await;
+    let config = Config {
+        cors: CorsConfig {
+            max_age: 600,
+            expose_headers: vec!["X-Total-Count".to_string(), "X-Request-Id".to_string()],
+            ..CorsConfig::default()
+        },
+        ..Config::default()
+    };
+
+    let response = proxy(config, TestRequest::get().uri(&target(&upstream, "/"))).await;
+
+    assert_eq!(response.header("Access-Control-Max-Age"), Some("600"));
+    assert_eq!(
+        response.header("Access-Control-Expose-Headers"),
+        Some("X-Total-Count, X-Request-Id")
+    );
+    assert_eq!(response.header("X-Total-Count"), Some("42"));
+}
+
+#[actix_web::test]
+async fn preflight_uses_configured_max_age() {
+    let config = Config {
+        cors: CorsConfig {
+            max_age: 60,
+            ..CorsConfig::default()
+        },
+        ..Config::default()
+    };
+
+    let response = proxy(
+        config,
+        TestRequest::default()
+            .method(actix_web::http::Method::OPTIONS)
+            .uri("/https://api.example.com/"),
+    )
+    .await;
+
+    assert_eq!(response.header("Access-Control-Max-Age"), Some("60"));
+}
+
+#[actix_web::test]
+async fn omits_expose_headers_when_empty() {
+    let upstream = upstream().await;
+    let config = Config {
+        cors: CorsConfig {
+            expose_headers: Vec::new(),
+            ..CorsConfig::default()
+        },
+        ..Config::default()
+    };
+
+    let response = proxy(config, TestRequest::get().uri(&target(&upstream, "/"))).await;
+
+    assert_eq!(response.header("Access-Control-Expose-Headers"), None);
+}
+
+fn preflight(url: &str, origin: &str) -> TestRequest {
+    TestRequest::default()
+        .method(actix_web::http::Method::OPTIONS)
+        .uri(url)
+        .insert_header(("Origin", origin))
+}
+
+fn per_host_config() -> Config {
+    Config {
+        file: ConfigFile::parse(
+            r#"
+            [hosts."private.example.com".cors]
+            allowed_origins = ["https://app.example.com"]
+            allowed_methods = ["GET"]
+            allow_credentials = true
+            "#,
+        )
+        .unwrap(),
+        ..Config::default()
+    }
+}
+
+#[actix_web::test]
+async fn hosts_use_their_own_cors_policy() {
+    let public = proxy(
+        per_host_config(),
+        preflight("/https://public.example.com/", "https://other.example.net"),
+    )
+    .await;
+    assert_eq!(public.header("Access-Control-Allow-Origin"), Some("*"));
+    assert_eq!(public.header("Access-Control-Allow-Credentials"), None);
+
+    let private = proxy(
+        per_host_config(),
+        preflight("/https://private.example.com/", "https://other.example.net"),
+    )
+    .await;
+    assert_eq!(private.header("Access-Control-Allow-Origin"), None);
+
+    let private = proxy(
+        per_host_config(),
+        preflight("/https://private.example.com/", "https://app.example.com"),
+    )
+    .await;
+    assert_eq!(
+        private.header("Access-Control-Allow-Origin"),
+        Some("https://app.example.com")
+    );
+    assert_eq!(
+        private.header("Access-Control-Allow-Credentials"),
+        Some("true")
+    );
+    assert_eq!(private.header("Access-Control-Allow-Methods"), Some("GET"));
+}
+
+#[actix_web::test]
+async fn applies_host_policy_to_proxied_responses() {
+    let upstream = upstream().await;
+    let config = Config {
+        file: ConfigFile::parse(
+            r#"
+            [hosts."127.0.0.1".cors]
+            allowed_origins = ["https://app.example.com"]
+            "#,
+        )
+        .unwrap(),
+        ..Config::default()
+    };
+
+    let response = proxy(
+        config,
+        TestRequest::get()
+            .uri(&target(&upstream, "/"))
+            .insert_header(("Origin", "https://evil.example.net")),
+    )
+    .await;
+
+    assert_eq!(response.header("Access-Control-Allow-Origin"), None);
+    assert_eq!(response.header("Vary"), Some("Origin"));
+}
+
+#[actix_web::test]
+async fn rejects_invalid_origin_patterns_in_config_file() {
+    let result = ConfigFile::parse(
+        r#"
+        [hosts."api.example.com".cors]
+        allowed_origins = ["https://*.*.example.com"]
+        "#,
+    );
+
+    assert!(result.is_err());
+}
+
+#[actix_web::test]
+async fn adds_timing_allow_origin_when_configured() {
+    let upstream = upstream().await;
+    let config = Config {
+        cors: CorsConfig {
+            timing_allow_origin: Some("https://app.example.com".to_string()),
+            ..CorsConfig::default()
+        },
+        ..Config::default()
+    };
+
+    let response = proxy(config, TestRequest::get().uri(&target(&upstream, "/"))).await;
+    assert_eq!(
+        response.header("Timing-Allow-Origin"),
+        Some("https://app.example.com")
+    );
+
+    let response = proxy(
+        Config::default(),
+        TestRequest::get().uri(&target(&upstream, "/")),
+    )
+    .await;
+    assert_eq!(response.header("Timing-Allow-Origin"), None);
+}
+
+#[actix_web::test]
+async fn allows_private_network_access_when_configured() {
+    let config = || Config {
+        cors: CorsConfig {
+            allow_private_network: true,
+            ..CorsConfig::default()
+        },
+        ..Config::default()
+    };
+    let private_network = || {
+        preflight("/https://api.example.com/", "https://app.example.com")
+            .insert_header(("Access-Control-Request-Method", "GET"))
+            .insert_header(("Access-Control-Request-Private-Network", "true"))
+    };
+
+    let response = proxy(config(), private_network()).await;
+    assert_eq!(response.status, actix_web::http::StatusCode::NO_CONTENT);
+    assert_eq!(
+        response.header("Access-Control-Allow-Private-Network"),
+        Some("true")
+    );
+    assert_eq!(response.header("Access-Control-Allow-Origin"), Some("*"));
+
+    // Only preflights that ask for it are answered
+    let response = proxy(
+        config(),
+        preflight("/https://api.example.com/", "https://app.example.com"),
+    )
+    .await;
+    assert_eq!(
+        response.header("Access-Control-Allow-Private-Network"),
+        None
+    );
+
+    let response = proxy(Config::default(), private_network()).await;
+    assert_eq!(
+        response.header("Access-Control-Allow-Private-Network"),
+        None
+    );
+}
+
+fn requiring_origin() -> Config {
+    Config {
+        require_origin: true,
+        ..Config::default()
+    }
+}
+
+#[actix_web::test]
+async fn rejects_requests_without_origin_when_required() {
+    let upstream = upstream().await;
+
+    let response = proxy(
+        requiring_origin(),
+        TestRequest::get().uri(&target(&upstream, "/")),
+    )
+    .await;
+    assert_eq!(response.status, actix_web::http::StatusCode::FORBIDDEN);
+
+    let response = proxy(
+        requiring_origin(),
+        TestRequest::get()
+            .uri(&target(&upstream, "/"))
+            .insert_header(("Origin", "https://app.example.com")),
+    )
+    .await;
+    assert_eq!(response.status, actix_web::http::StatusCode::OK);
+    assert_eq!(response.header("X-Total-Count"), Some("42"));
+}
+
+#[actix_web::test]
+async fn exempts_preflights_from_required_origin() {
+    let response = proxy(
+        requiring_origin(),
+        TestRequest::default()
+            .method(actix_web::http::Method::OPTIONS)
+            .uri("/https://api.example.com/"),
+    )
+    .await;
+
+    assert_eq!(response.status, actix_web::http::StatusCode::NO_CONTENT);
+}
+
+fn wildcard() -> OriginPattern {
+    OriginPattern::parse("https://*.example.com").unwrap()
+}
+
+#[actix_web::test]
+async fn wildcard_origins_match_subdomains() {
+    assert!(wildcard().matches("https://app.example.com"));
+    assert!(wildcard().matches("https://a.b.example.com"));
+    assert!(!wildcard().matches("https://app.example.com.evil.net"));
+    assert!(!wildcard().matches("https://appexample.com"));
+
+    let response = proxy(
+        Config {
+            cors: CorsConfig {
+                allowed_origins: vec![wildcard()],
+                ..CorsConfig::default()
+            },
+            ..Config::default()
+        },
+        preflight("/https://api.example.com/", "https://app.example.com"),
+    )
+    .await;
+    assert_eq!(
+        response.header("Access-Control-Allow-Origin"),
+        Some("https://app.example.com")
+    );
+}
+
+#[test]
+fn wildcard_origins_do_not_match_the_apex_domain() {
+    assert!(!wildcard().matches("https://example.com"));
+    assert!(!wildcard().matches("https://.example.com"));
+}
+
+#[test]
+fn wildcard_origins_require_the_same_scheme_and_port() {
+    assert!(!wildcard().matches("http://app.example.com"));
+    assert!(!wildcard().matches("https://app.example.com:8443"));
+
+    let with_port = OriginPattern::parse("https://*.example.com:8443").unwrap();
+    assert!(with_port.matches("https://app.example.com:8443"));
+    assert!(!with_port.matches("https://app.example.com"));
+}
+
+async fn options_upstream() -> MockServer {
+    let upstream = MockServer::start().await;
+    Mock::given(method("OPTIONS"))
+        .respond_with(ResponseTemplate::new(200).insert_header("Allow", "GET, OPTIONS"))
+        .mount(&upstream)
+        .await;
+    upstream
+}
+
+#[actix_web::test]
+async fn answers_preflights_locally_in_preflight_mode() {
+    let upstream = options_upstream().await;
+
+    let response = proxy(
+        Config::default(),
+        preflight(&target(&upstream, "/"), "https://app.example.com"),
+    )
+    .await;
+
+    assert_eq!(response.status, StatusCode::NO_CONTENT);
+    assert_eq!(response.header("Access-Control-Allow-Origin"), Some("*"));
+    assert!(received(&upstream).await.is_empty());
+}
+
+#[actix_web::test]
+async fn forwards_preflights_in_passthrough_mode() {
+    let upstream = options_upstream().await;
+    let config = Config {
+        options_mode: OptionsMode::Passthrough,
+        ..Config::default()
+    };
+
+    let response = proxy(
+        config,
+        preflight(&target(&upstream, "/"), "https://app.example.com"),
+    )
+    .await;
+
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.header("Allow"), Some("GET, OPTIONS"));
+    assert_eq!(response.header("Access-Control-Allow-Origin"), Some("*"));
+    let requests = received(&upstream).await;
+    assert_eq!(requests.len(), 1);
+    assert_eq!(requests[0].method.as_str(), "OPTIONS");
+}
+
+#[test]
+fn rejects_credentials_without_an_origin_allowlist() {
+    for cors in [
+        "allow_credentials = true",
+        "allow_credentials = true\nallowed_origins = []",
+        "allow_credentials = true\nallowed_origins = [\"*\"]",
+    ] {
+        let result = ConfigFile::parse(&format!("[hosts.\"api.example.com\".cors]\n{}", cors));
+        assert!(result.is_err(), "{}", cors);
+    }
+}
+
+#[actix_web::test]
+async fn grants_credentials_only_to_listed_origins() {
+    let credentialed = |allowed_origins| Config {
+        cors: CorsConfig {
+            allowed_origins,
+            allow_credentials: true,
+            ..CorsConfig::default()
+        },
+        ..Config::default()
+    };
+
+    let response = proxy(
+        credentialed(Vec::new()),
+        preflight("/https://api.example.com/", "https://evil.example.net"),
+    )
+    .await;
+    assert_eq!(response.header("Access-Control-Allow-Credentials"), None);
+    assert_eq!(response.header("Access-Control-Allow-Origin"), Some("*"));
+
+    let response = proxy(
+        credentialed(vec![wildcard()]),
+        preflight("/https://api.example.com/", "https://evil.example.net"),
+    )
+    .await;
+    assert_eq!(response.header("Access-Control-Allow-Credentials"), None);
+    assert_eq!(response.header("Access-Control-Allow-Origin"), None);
+
+    let response = proxy(
+        credentialed(vec![wildcard()]),
+        preflight("/https://api.example.com/", "https://app.example.com"),
+    )
+    .await;
+    assert_eq!(
+        response.header("Access-Control-Allow-Credentials"),
+        Some("true")
+    );
+    assert_eq!(
+        response.header("Access-Control-Allow-Origin"),
+        Some("https://app.example.com")
+    );
+}
+
+#[actix_web::test]
+async fn announces_only_the_allowed_methods() {
+    std::env::set_var("ALLOWED_METHODS", "GET,HEAD,OPTIONS");
+    let config = Config::from_env().unwrap();
+    std::env::remove_var("ALLOWED_METHODS");
+
+    let response = proxy(
+        config,
+        preflight("/https://example.com/", "https://app.example.com"),
+    )
+    .await;
+
+    assert_eq!(
+        response.header("Access-Control-Allow-Methods"),
+        Some("GET, HEAD, OPTIONS")
+    );
+}
+
+#[actix_web::test]
+async fn allows_the_request_headers_read_by_the_proxy() {
+    let response = proxy(
+        Config::default(),
+        preflight("/https://example.com/", "https://app.example.com"),
+    )
+    .await;
+
+    let allowed = response.header("Access-Control-Allow-Headers").unwrap();
+    assert!(allowed.contains("X-HTTP-Method-Override"));
+    assert!(allowed.contains("X-Rcp-Strip-Response-Headers"));
+}
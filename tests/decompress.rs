@@ -0,0 +1,157 @@
+mod common;
+
+use std::io::Write;
+
+use actix_web::http::StatusCode;
+use actix_web::test::TestRequest;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use wiremock::matchers::{header, method};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+use common::{header_values, proxy, received, target};
+use rcp::config::Config;
+
+fn gzip(data: &[u8]) -> Vec<u8> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::best());
+    encoder.write_all(data).unwrap();
+    encoder.finish().unwrap()
+}
+
+async fn gzip_upstream(body: Vec<u8>) -> MockServer {
+    let upstream = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(header("Accept-Encoding", "gzip"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .insert_header("Content-Encoding", "gzip")
+                .set_body_raw(body, "text/plain"),
+        )
+        .mount(&upstream)
+        .await;
+    upstream
+}
+
+fn decompressing(max_size: u64) -> Config {
+    Config {
+        decompress_upstream: true,
+        max_decompressed_size: max_size,
+        ..Config::default()
+    }
+}
+
+#[actix_web::test]
+async fn decompresses_gzip_responses() {
+    let upstream = gzip_upstream(gzip(b"hello world")).await;
+
+    let response = proxy(
+        decompressing(1024),
+        TestRequest::get()
+            .uri(&target(&upstream, "/"))
+            .insert_header(("Accept-Encoding", "br")),
+    )
+    .await;
+
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.text(), "hello world");
+    assert_eq!(response.header("Content-Encoding"), None);
+}
+
+#[actix_web::test]
+async fn rejects_gzip_bombs() {
+    // 64 MiB of zeros compress to about 64 KiB
+    let bomb = gzip(&vec![0u8; 64 * 1024 * 1024]);
+    assert!(bomb.len() < 100 * 1024);
+    let upstream = gzip_upstream(bomb).await;
+
+    let response = proxy(
+        decompressing(1024 * 1024),
+        TestRequest::get().uri(&target(&upstream, "/")),
+    )
+    .await;
+
+    assert_eq!(response.status, StatusCode::BAD_GATEWAY);
+    assert!(response.text().contains("exceeds 1048576 bytes"));
+}
+
+#[actix_web::test]
+async fn passes_gzip_through_by_default() {
+    let body = gzip(b"hello world");
+    let upstream = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .insert_header("Content-Encoding", "gzip")
+                .set_body_raw(body.clone(), "text/plain"),
+        )
+        .mount(&upstream)
+        .await;
+
+    let response = proxy(
+        Config::default(),
+        TestRequest::get().uri(&target(&upstream, "/")),
+    )
+    .await;
+
+    assert_eq!(response.header("Content-Encoding"), Some("gzip"));
+    assert_eq!(response.body, body);
+}
+
+#[actix_web::test]
+async fn passes_unsupported_encodings_through() {
+    let body = b"\x00\x01proprietary\xff".to_vec();
+    let upstream = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .insert_header("Content-Encoding", "x-proprietary")
+                .set_body_raw(body.clone(), "application/octet-stream"),
+        )
+        .mount(&upstream)
+        .await;
+
+    let response = proxy(
+        decompressing(1024),
+        TestRequest::get().uri(&target(&upstream, "/")),
+    )
+    .await;
+
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.header("Content-Encoding"), Some("x-proprietary"));
+    assert_eq!(response.body, body);
+}
+
+#[actix_web::test]
+async fn forwards_client_accept_encoding_verbatim_when_preserved() {
+    let body = gzip(b"hello world");
+    let upstream = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .insert_header("Content-Encoding", "gzip")
+                .set_body_raw(body.clone(), "text/plain"),
+        )
+        .mount(&upstream)
+        .await;
+    let config = Config {
+        preserve_client_accept_encoding: true,
+        ..decompressing(1024)
+    };
+
+    let response = proxy(
+        config,
+        TestRequest::get()
+            .uri(&target(&upstream, "/"))
+            .insert_header(("Accept-Encoding", "gzip, deflate, br, zstd")),
+    )
+    .await;
+
+    let requests = received(&upstream).await;
+    assert_eq!(
+        header_values(&requests[0], "accept-encoding"),
+        ["gzip, deflate, br, zstd"]
+    );
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.header("Content-Encoding"), Some("gzip"));
+    assert_eq!(response.body, body);
+}
@@ -0,0 +1,82 @@
+mod common;
+
+use actix_web::http::StatusCode;
+use actix_web::test::TestRequest;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+use common::{proxy, target};
+use rcp::config::Config;
+
+async fn upstream() -> MockServer {
+    let upstream = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/json"))
+        .respond_with(ResponseTemplate::new(200).insert_header("Content-Type", "application/json"))
+        .mount(&upstream)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/text"))
+        .respond_with(ResponseTemplate::new(200).insert_header("Content-Type", "text/plain"))
+        .mount(&upstream)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/filled"))
+        .respond_with(ResponseTemplate::new(200).set_body_raw("[1]", "application/json"))
+        .mount(&upstream)
+        .await;
+    upstream
+}
+
+fn with_placeholder(placeholder: &str) -> Config {
+    Config {
+        empty_body_placeholder: Some(placeholder.to_string()),
+        ..Config::default()
+    }
+}
+
+#[actix_web::test]
+async fn substitutes_empty_json_body() {
+    let upstream = upstream().await;
+
+    let response = proxy(
+        with_placeholder("{}"),
+        TestRequest::get().uri(&target(&upstream, "/json")),
+    )
+    .await;
+
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.text(), "{}");
+}
+
+#[actix_web::test]
+async fn keeps_non_empty_and_non_json_bodies() {
+    let upstream = upstream().await;
+
+    let response = proxy(
+        with_placeholder("null"),
+        TestRequest::get().uri(&target(&upstream, "/text")),
+    )
+    .await;
+    assert_eq!(response.text(), "");
+
+    let response = proxy(
+        with_placeholder("null"),
+        TestRequest::get().uri(&target(&upstream, "/filled")),
+    )
+    .await;
+    assert_eq!(response.text(), "[1]");
+}
+
+#[actix_web::test]
+async fn keeps_empty_json_body_by_default() {
+    let upstream = upstream().await;
+
+    let response = proxy(
+        Config::default(),
+        TestRequest::get().uri(&target(&upstream, "/json")),
+    )
+    .await;
+
+    assert_eq!(response.text(), "");
+}
@@ -0,0 +1,184 @@
+mod common;
+
+use actix_web::http::StatusCode;
+use actix_web::test::TestRequest;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+use common::{proxy, target};
+use rcp::config::Config;
+use rcp::error_body::ErrorBodyTemplate;
+
+const PAGE: &str = "<html><body><h1>Not Found</h1></body></html>";
+
+async fn upstream() -> MockServer {
+    let upstream = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/missing"))
+        .respond_with(ResponseTemplate::new(404).set_body_raw(PAGE, "text/html; charset=utf-8"))
+        .mount(&upstream)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/broken"))
+        .respond_with(ResponseTemplate::new(500).set_body_raw(PAGE, "text/html"))
+        .mount(&upstream)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/json"))
+        .respond_with(ResponseTemplate::new(404).set_body_raw(r#"{"id":1}"#, "application/json"))
+        .mount(&upstream)
+        .await;
+    upstream
+}
+
+fn replacing() -> Config {
+    Config {
+        replace_upstream_error_bodies: true,
+        ..Config::default()
+    }
+}
+
+#[actix_web::test]
+async fn passes_error_pages_through_by_default() {
+    let upstream = upstream().await;
+
+    let response = proxy(
+        Config::default(),
+        TestRequest::get().uri(&target(&upstream, "/missing")),
+    )
+    .await;
+
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+    assert_eq!(response.text(), PAGE);
+}
+
+#[actix_web::test]
+async fn replaces_html_error_pages() {
+    let upstream = upstream().await;
+
+    let response = proxy(
+        replacing(),
+        TestRequest::get().uri(&target(&upstream, "/missing")),
+    )
+    .await;
+
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+    assert_eq!(response.header("Content-Type"), Some("application/json"));
+    let body: serde_json::Value = serde_json::from_slice(&response.body).unwrap();
+    assert_eq!(body["status"], 404);
+    assert_eq!(body["error"], "Upstream responded with 404 Not Found");
+}
+
+#[actix_web::test]
+async fn keeps_json_error_bodies() {
+    let upstream = upstream().await;
+
+    let response = proxy(
+        replacing(),
+        TestRequest::get().uri(&target(&upstream, "/json")),
+    )
+    .await;
+
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+    assert_eq!(response.text(), r#"{"id":1}"#);
+}
+
+#[actix_web::test]
+async fn replaces_only_from_configured_status() {
+    let upstream = upstream().await;
+    let config = Config {
+        replace_error_min_status: 500,
+        ..replacing()
+    };
+
+    let response = proxy(
+        config,
+        TestRequest::get().uri(&target(&upstream, "/missing")),
+    )
+    .await;
+    assert_eq!(response.text(), PAGE);
+
+    let config = Config {
+        replace_error_min_status: 500,
+        ..replacing()
+    };
+    let response = proxy(
+        config,
+        TestRequest::get().uri(&target(&upstream, "/broken")),
+    )
+    .await;
+    assert_eq!(response.status, StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(response.header("Content-Type"), Some("application/json"));
+}
+
+const TEMPLATE: &str = r#"{"ok":false,"message":"{message}","status":{status},"code":"{code}"}"#;
+
+fn templated() -> Config {
+    Config {
+        error_body_template: Some(ErrorBodyTemplate::new(TEMPLATE, "application/json").unwrap()),
+        ..Config::default()
+    }
+}
+
+#[actix_web::test]
+async fn renders_proxy_errors_with_template() {
+    let response = proxy(templated(), TestRequest::get().uri("/https://intranet/")).await;
+
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+    assert_eq!(response.header("Content-Type"), Some("application/json"));
+    let body: serde_json::Value = serde_json::from_slice(&response.body).unwrap();
+    assert_eq!(
+        body,
+        serde_json::json!({
+            "ok": false,
+            "message": "Invalid domain name",
+            "status": 400,
+            "code": "bad_request",
+        })
+    );
+}
+
+#[actix_web::test]
+async fn renders_gateway_errors_with_template() {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    drop(listener);
+
+    let response = proxy(
+        templated(),
+        TestRequest::get().uri(&format!("/http://{}/", address)),
+    )
+    .await;
+
+    assert_eq!(response.status, StatusCode::BAD_GATEWAY);
+    let body: serde_json::Value = serde_json::from_slice(&response.body).unwrap();
+    assert_eq!(body["ok"], false);
+    assert_eq!(body["status"], 502);
+    assert_eq!(body["code"], "bad_gateway");
+    assert!(body["message"]
+        .as_str()
+        .unwrap()
+        .starts_with("Failed to forward request"));
+}
+
+#[actix_web::test]
+async fn passes_upstream_errors_through_template() {
+    let upstream = upstream().await;
+
+    let response = proxy(
+        templated(),
+        TestRequest::get().uri(&target(&upstream, "/json")),
+    )
+    .await;
+
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+    assert_eq!(response.text(), r#"{"id":1}"#);
+}
+
+#[test]
+fn validates_error_body_templates() {
+    assert!(ErrorBodyTemplate::new(TEMPLATE, "application/json").is_ok());
+    assert!(ErrorBodyTemplate::new("{status}: {message}", "text/plain").is_ok());
+    assert!(ErrorBodyTemplate::new(r#"{"error":"{reason}"}"#, "application/json").is_err());
+    assert!(ErrorBodyTemplate::new(r#"{"status":{status}"#, "application/json").is_err());
+}
@@ -0,0 +1,103 @@
+mod common;
+
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+use actix_web::http::StatusCode;
+use actix_web::test::TestRequest;
+use wiremock::matchers::method;
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+use common::{header_values, proxy, received, serve, target};
+use rcp::config::{AmbiguousFraming, Config};
+use rcp::AppState;
+
+async fn upstream() -> MockServer {
+    let upstream = MockServer::start().await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&upstream)
+        .await;
+    upstream
+}
+
+fn ambiguous(upstream: &MockServer) -> TestRequest {
+    TestRequest::post()
+        .uri(&target(upstream, "/"))
+        .insert_header(("Content-Length", "4"))
+        .insert_header(("Transfer-Encoding", "chunked"))
+        .set_payload("data")
+}
+
+#[actix_web::test]
+async fn rejects_requests_with_content_length_and_transfer_encoding() {
+    let upstream = upstream().await;
+
+    let response = proxy(Config::default(), ambiguous(&upstream)).await;
+
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+    assert_eq!(
+        response.text(),
+        "Both Content-Length and Transfer-Encoding are set"
+    );
+    assert_eq!(response.header("Access-Control-Allow-Origin"), Some("*"));
+    assert!(received(&upstream).await.is_empty());
+}
+
+#[actix_web::test]
+async fn forwards_a_single_content_length_when_normalizing() {
+    let upstream = upstream().await;
+    let config = Config {
+        ambiguous_framing: AmbiguousFraming::Normalize,
+        ..Config::default()
+    };
+
+    let response = proxy(config, ambiguous(&upstream)).await;
+
+    assert_eq!(response.status, StatusCode::OK);
+    let requests = received(&upstream).await;
+    assert_eq!(requests[0].body, b"data");
+    assert_eq!(header_values(&requests[0], "content-length"), ["4"]);
+    assert!(header_values(&requests[0], "transfer-encoding").is_empty());
+}
+
+#[actix_web::test]
+async fn rejects_smuggling_attempts_on_a_real_connection() {
+    let upstream = upstream().await;
+    let address = serve(&AppState::new(Config::default()))
+        .trim_start_matches("http://")
+        .to_string();
+    let path = target(&upstream, "/");
+
+    let response = actix_web::rt::task::spawn_blocking(move || {
+        let mut stream = TcpStream::connect(address).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        write!(
+            stream,
+            "POST {path} HTTP/1.1\r\nHost: localhost\r\nContent-Length: 4\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n\r\nPOST {path} HTTP/1.1\r\nHost: localhost\r\nContent-Length: 0\r\n\r\n",
+        )
+        .unwrap();
+        let mut response = String::new();
+        let _ = stream.read_to_string(&mut response);
+        response
+    })
+    .await
+    .unwrap();
+
+    assert!(
+        response.starts_with("HTTP/1.1 400 Bad Request"),
+        "{}",
+        response
+    );
+    // The request hidden in the body is answered without being forwarded
+    assert_eq!(
+        response.matches("HTTP/1.1 400 Bad Request").count(),
+        response.matches("HTTP/1.1 ").count(),
+        "{}",
+        response
+    );
+    assert!(received(&upstream).await.is_empty());
+}
@@ -0,0 +1,83 @@
+mod common;
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc;
+use std::thread;
+
+use common::serve;
+use rcp::config::Config;
+use rcp::AppState;
+
+/// An upstream that sends the head of the request it receives over
+/// `heads`, and answers with a custom header.
+fn upstream(heads: mpsc::Sender<String>) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut head = String::new();
+        while reader.read_line(&mut head).unwrap() > 0 && !head.ends_with("\r\n\r\n") {}
+        heads.send(head).unwrap();
+
+        let mut stream = stream;
+        let _ = stream
+            .write_all(b"HTTP/1.1 200 OK\r\nx-upstream-header: 1\r\ncontent-length: 2\r\n\r\nok");
+    });
+    format!("http://{}", address)
+}
+
+/// Sends a request with a lowercase header through a served proxy and
+/// returns the heads of the upstream request and of the response.
+async fn heads(config: Config) -> (String, String) {
+    let (sender, receiver) = mpsc::channel();
+    let upstream = upstream(sender);
+    let proxy = serve(&AppState::new(config));
+    let address = proxy.trim_start_matches("http://").to_string();
+    let response = actix_web::rt::task::spawn_blocking(move || {
+        let mut stream = TcpStream::connect(address).unwrap();
+        write!(
+            stream,
+            "GET /{}/resource HTTP/1.1\r\nHost: localhost\r\nx-api-key: secret\r\nConnection: close\r\n\r\n",
+            upstream
+        )
+        .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    })
+    .await
+    .unwrap();
+    let (response_head, _) = response.split_once("\r\n\r\n").unwrap();
+    (receiver.recv().unwrap(), response_head.to_string())
+}
+
+#[actix_web::test]
+async fn sends_title_case_header_names_when_enabled() {
+    let config = Config {
+        title_case_headers: true,
+        ..Config::default()
+    };
+
+    let (request, response) = heads(config).await;
+
+    assert!(request.contains("\r\nX-Api-Key: secret\r\n"), "{}", request);
+    assert!(
+        response.contains("\r\nX-Upstream-Header: 1"),
+        "{}",
+        response
+    );
+}
+
+#[actix_web::test]
+async fn sends_lowercase_header_names_by_default() {
+    let (request, response) = heads(Config::default()).await;
+
+    assert!(request.contains("\r\nx-api-key: secret\r\n"), "{}", request);
+    assert!(
+        response.contains("\r\nx-upstream-header: 1"),
+        "{}",
+        response
+    );
+}
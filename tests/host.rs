@@ -0,0 +1,89 @@
+mod common;
+
+use actix_web::http::StatusCode;
+use actix_web::test::TestRequest;
+use wiremock::matchers::method;
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+use common::{header_values, proxy, received, target};
+use rcp::config::Config;
+
+async fn upstream() -> MockServer {
+    let upstream = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&upstream)
+        .await;
+    upstream
+}
+
+fn enforcing(hosts: &[&str]) -> Config {
+    Config {
+        enforce_host: hosts.iter().map(|host| host.to_string()).collect(),
+        ..Config::default()
+    }
+}
+
+#[actix_web::test]
+async fn derives_upstream_host_from_target_url() {
+    let upstream = upstream().await;
+
+    proxy(
+        Config::default(),
+        TestRequest::get()
+            .uri(&target(&upstream, "/"))
+            .insert_header(("Host", "evil.example.com")),
+    )
+    .await;
+
+    let requests = received(&upstream).await;
+    let expected = upstream.uri().trim_start_matches("http://").to_string();
+    assert_eq!(header_values(&requests[0], "Host"), [expected]);
+}
+
+#[actix_web::test]
+async fn accepts_expected_hosts() {
+    let upstream = upstream().await;
+
+    let response = proxy(
+        enforcing(&["proxy.example.com", "localhost:8080"]),
+        TestRequest::get()
+            .uri(&target(&upstream, "/"))
+            .insert_header(("Host", "Proxy.Example.com:443")),
+    )
+    .await;
+    assert_eq!(response.status, StatusCode::OK);
+
+    let response = proxy(
+        enforcing(&["proxy.example.com", "localhost:8080"]),
+        TestRequest::get()
+            .uri(&target(&upstream, "/"))
+            .insert_header(("Host", "localhost:8080")),
+    )
+    .await;
+    assert_eq!(response.status, StatusCode::OK);
+}
+
+#[actix_web::test]
+async fn rejects_unexpected_hosts_when_enforced() {
+    let upstream = upstream().await;
+
+    let response = proxy(
+        enforcing(&["proxy.example.com", "localhost:8080"]),
+        TestRequest::get()
+            .uri(&target(&upstream, "/"))
+            .insert_header(("Host", "evil.example.com")),
+    )
+    .await;
+    assert_eq!(response.status, StatusCode::MISDIRECTED_REQUEST);
+
+    let response = proxy(
+        enforcing(&["proxy.example.com", "localhost:8080"]),
+        TestRequest::get()
+            .uri(&target(&upstream, "/"))
+            .insert_header(("Host", "localhost:9090")),
+    )
+    .await;
+    assert_eq!(response.status, StatusCode::MISDIRECTED_REQUEST);
+    assert!(received(&upstream).await.is_empty());
+}
@@ -0,0 +1,106 @@
+mod common;
+
+use actix_web::http::{Method, StatusCode};
+use actix_web::test::TestRequest;
+use wiremock::matchers::any;
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+use common::{proxy, received, target};
+use rcp::config::Config;
+
+async fn upstream() -> MockServer {
+    let upstream = MockServer::start().await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&upstream)
+        .await;
+    upstream
+}
+
+fn read_only() -> Config {
+    Config {
+        allowed_methods: vec![Method::GET, Method::HEAD],
+        ..Config::default()
+    }
+}
+
+#[actix_web::test]
+async fn read_only_proxy_rejects_post() {
+    let upstream = upstream().await;
+
+    let response = proxy(
+        read_only(),
+        TestRequest::post().uri(&target(&upstream, "/items")),
+    )
+    .await;
+
+    assert_eq!(response.status, StatusCode::METHOD_NOT_ALLOWED);
+    assert_eq!(response.header("Allow"), Some("GET, HEAD"));
+    assert!(received(&upstream).await.is_empty());
+}
+
+#[actix_web::test]
+async fn read_only_proxy_rejects_method_override() {
+    let upstream = upstream().await;
+
+    let response = proxy(
+        read_only(),
+        TestRequest::post()
+            .uri(&target(&upstream, "/items"))
+            .insert_header(("X-HTTP-Method-Override", "PUT")),
+    )
+    .await;
+
+    assert_eq!(response.status, StatusCode::METHOD_NOT_ALLOWED);
+    assert_eq!(response.header("Allow"), Some("GET, HEAD"));
+    assert!(received(&upstream).await.is_empty());
+}
+
+#[actix_web::test]
+async fn read_only_proxy_forwards_get() {
+    let upstream = upstream().await;
+
+    let response = proxy(
+        read_only(),
+        TestRequest::get().uri(&target(&upstream, "/items")),
+    )
+    .await;
+
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(received(&upstream).await.len(), 1);
+}
+
+#[actix_web::test]
+async fn lists_default_methods_in_allow_header() {
+    let upstream = upstream().await;
+
+    let response = proxy(
+        Config::default(),
+        TestRequest::patch().uri(&target(&upstream, "/items")),
+    )
+    .await;
+
+    assert_eq!(response.status, StatusCode::METHOD_NOT_ALLOWED);
+    assert_eq!(
+        response.header("Allow"),
+        Some("GET, HEAD, POST, PUT, DELETE, OPTIONS")
+    );
+}
+
+#[actix_web::test]
+async fn proxies_additionally_allowed_methods() {
+    let upstream = upstream().await;
+    let config = Config {
+        allowed_methods: vec![Method::GET, Method::PATCH],
+        ..Config::default()
+    };
+
+    let response = proxy(
+        config,
+        TestRequest::patch().uri(&target(&upstream, "/items")),
+    )
+    .await;
+
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(received(&upstream).await[0].method.as_str(), "PATCH");
+}
@@ -0,0 +1,48 @@
+mod common;
+
+use actix_web::http::StatusCode;
+use actix_web::test::TestRequest;
+use wiremock::matchers::method;
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+use common::{proxy_with, target};
+use rcp::config::Config;
+use rcp::AppState;
+
+/// The line of a metric with the given name and labels.
+fn metric_line<'a>(metrics: &'a str, prefix: &str) -> &'a str {
+    metrics
+        .lines()
+        .find(|line| line.starts_with(prefix))
+        .unwrap_or_else(|| panic!("no {} in\n{}", prefix, metrics))
+}
+
+#[actix_web::test]
+async fn records_body_size_and_content_type_of_responses() {
+    let upstream = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .insert_header("Content-Type", "text/html; charset=utf-8")
+                .set_body_string("a".repeat(1000)),
+        )
+        .mount(&upstream)
+        .await;
+    let state = AppState::new(Config::default());
+
+    let response = proxy_with(&state, TestRequest::get().uri(&target(&upstream, "/"))).await;
+    assert_eq!(response.body.len(), 1000);
+
+    let response = proxy_with(&state, TestRequest::get().uri("/metrics")).await;
+    assert_eq!(response.status, StatusCode::OK);
+    let metrics = response.text();
+    // 1000 bytes fall into the bucket up to 1024, none into the one below
+    assert!(metric_line(&metrics, "rcp_response_body_bytes_bucket{le=\"256\"}").ends_with(" 0"));
+    assert!(metric_line(&metrics, "rcp_response_body_bytes_bucket{le=\"1024\"}").ends_with(" 1"));
+    assert!(metric_line(&metrics, "rcp_response_body_bytes_sum").ends_with(" 1000"));
+    assert!(metric_line(
+        &metrics,
+        "rcp_responses_by_content_type_total{content_type=\"text\"}"
+    )
+    .ends_with(" 1"));
+}
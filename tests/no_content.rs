@@ -0,0 +1,72 @@
+mod common;
+
+use std::io::{Read, Write};
+use std::net::TcpStream;
+
+use wiremock::matchers::method;
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+use common::{serve, target};
+use rcp::config::Config;
+use rcp::AppState;
+
+/// Sends a request through a served proxy and returns the raw response.
+async fn raw_response(request_method: &str, status: u16) -> String {
+    let upstream = MockServer::start().await;
+    Mock::given(method(request_method))
+        .respond_with(ResponseTemplate::new(status).insert_header("ETag", "\"v1\""))
+        .mount(&upstream)
+        .await;
+
+    let proxy = serve(&AppState::new(Config::default()));
+    let address = proxy.trim_start_matches("http://").to_string();
+    let path = target(&upstream, "/resource");
+    let request_method = request_method.to_string();
+    actix_web::rt::task::spawn_blocking(move || {
+        let mut stream = TcpStream::connect(address).unwrap();
+        write!(
+            stream,
+            "{} {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+            request_method, path
+        )
+        .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    })
+    .await
+    .unwrap()
+}
+
+fn assert_bodyless(response: &str, status_line: &str) {
+    assert!(response.starts_with(status_line), "{}", response);
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    let head = head.to_ascii_lowercase();
+    let has_header = |name: &str| head.lines().any(|line| line.starts_with(name));
+    assert!(!has_header("content-length:"), "{}", head);
+    assert!(!has_header("transfer-encoding:"), "{}", head);
+    assert!(has_header("etag: \"v1\""), "{}", head);
+    assert!(has_header("access-control-allow-origin: *"), "{}", head);
+    assert_eq!(body, "");
+}
+
+#[actix_web::test]
+async fn forwards_no_content_without_body() {
+    let response = raw_response("GET", 204).await;
+
+    assert_bodyless(&response, "HTTP/1.1 204 No Content\r\n");
+}
+
+#[actix_web::test]
+async fn forwards_not_modified_without_body() {
+    let response = raw_response("GET", 304).await;
+
+    assert_bodyless(&response, "HTTP/1.1 304 Not Modified\r\n");
+}
+
+#[actix_web::test]
+async fn answers_head_with_no_content_without_length() {
+    let response = raw_response("HEAD", 204).await;
+
+    assert_bodyless(&response, "HTTP/1.1 204 No Content\r\n");
+}
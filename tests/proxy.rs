@@ -0,0 +1,635 @@
+mod common;
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::pin::Pin;
+
+use actix_web::dev::Payload;
+use actix_web::error::PayloadError;
+use actix_web::http::{Method, StatusCode};
+use actix_web::test::{self, TestRequest};
+use actix_web::{web, App};
+use futures_util::Stream;
+use wiremock::matchers::{body_string, method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+use common::{header_values, proxy, received, serve, target};
+use rcp::config::Config;
+use rcp::AppState;
+
+#[actix_web::test]
+async fn forwards_get_request() {
+    let upstream = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/data"))
+        .respond_with(ResponseTemplate::new(200).set_body_raw(r#"{"ok":true}"#, "application/json"))
+        .expect(1)
+        .mount(&upstream)
+        .await;
+
+    let response = proxy(
+        Config::default(),
+        TestRequest::get()
+            .uri(&target(&upstream, "/data"))
+            .insert_header(("X-Custom", "value")),
+    )
+    .await;
+
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.text(), r#"{"ok":true}"#);
+    assert_eq!(response.header("Content-Type"), Some("application/json"));
+    assert_eq!(response.header("Access-Control-Allow-Origin"), Some("*"));
+
+    let requests = received(&upstream).await;
+    assert_eq!(header_values(&requests[0], "X-Custom"), ["value"]);
+}
+
+#[actix_web::test]
+async fn forwards_post_request_with_body() {
+    let upstream = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/items"))
+        .and(body_string("payload"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("created"))
+        .expect(1)
+        .mount(&upstream)
+        .await;
+
+    let response = proxy(
+        Config::default(),
+        TestRequest::post()
+            .uri(&target(&upstream, "/items"))
+            .insert_header(("Content-Type", "text/plain"))
+            .set_payload("payload"),
+    )
+    .await;
+
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.text(), "created");
+
+    let requests = received(&upstream).await;
+    assert_eq!(header_values(&requests[0], "Content-Type"), ["text/plain"]);
+}
+
+#[actix_web::test]
+async fn preserves_upstream_status_and_headers() {
+    let upstream = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(
+            ResponseTemplate::new(404)
+                .insert_header("X-Total-Count", "0")
+                .insert_header(
+                    "Access-Control-Allow-Origin",
+                    "https://upstream.example.com",
+                )
+                .set_body_string("missing"),
+        )
+        .mount(&upstream)
+        .await;
+
+    let response = proxy(
+        Config::default(),
+        TestRequest::get().uri(&target(&upstream, "/missing")),
+    )
+    .await;
+
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+    assert_eq!(response.text(), "missing");
+    assert_eq!(response.header("X-Total-Count"), Some("0"));
+    assert_eq!(response.header("Access-Control-Allow-Origin"), Some("*"));
+}
+
+#[actix_web::test]
+async fn strips_response_headers_named_by_the_request() {
+    let upstream = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .insert_header("X-Powered-By", "Express")
+                .insert_header("Server", "upstream")
+                .insert_header("X-Total-Count", "3"),
+        )
+        .mount(&upstream)
+        .await;
+
+    let response = proxy(
+        Config::default(),
+        TestRequest::get()
+            .uri(&target(&upstream, "/"))
+            .insert_header(("X-Rcp-Strip-Response-Headers", "x-powered-by, Server")),
+    )
+    .await;
+
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.header("X-Powered-By"), None);
+    assert_eq!(response.header("Server"), None);
+    assert_eq!(response.header("X-Total-Count"), Some("3"));
+    let requests = received(&upstream).await;
+    assert!(header_values(&requests[0], "x-rcp-strip-response-headers").is_empty());
+}
+
+#[actix_web::test]
+async fn head_returns_upstream_headers_without_body() {
+    let upstream = MockServer::start().await;
+    Mock::given(method("HEAD"))
+        .and(path("/file"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .insert_header("Accept-Ranges", "bytes")
+                .set_body_raw(vec![0u8; 1234], "application/octet-stream"),
+        )
+        .expect(1)
+        .mount(&upstream)
+        .await;
+
+    let proxy = serve(&AppState::new(Config::default()));
+    let response = reqwest::Client::new()
+        .head(format!("{}{}", proxy, target(&upstream, "/file")))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+    assert_eq!(response.headers()["Accept-Ranges"], "bytes");
+    assert_eq!(response.headers()["Content-Length"], "1234");
+    assert_eq!(
+        response.headers()["Content-Type"],
+        "application/octet-stream"
+    );
+    assert!(response.bytes().await.unwrap().is_empty());
+}
+
+fn with_headers(count: usize) -> ResponseTemplate {
+    (0..count).fold(ResponseTemplate::new(200), |response, index| {
+        response.append_header(format!("X-Header-{}", index).as_str(), "value")
+    })
+}
+
+#[actix_web::test]
+async fn keeps_order_of_repeated_headers() {
+    let upstream = MockServer::start().await;
+    Mock::given(path("/login"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .append_header("Set-Cookie", "session=1; Path=/")
+                .append_header("Link", "</a.css>; rel=preload")
+                .append_header("Set-Cookie", "session=2; Path=/app")
+                .append_header("Set-Cookie", "session=1; Path=/")
+                .append_header("Link", "</b.js>; rel=preload"),
+        )
+        .mount(&upstream)
+        .await;
+    let proxy = serve(&AppState::new(Config::default()));
+
+    let response = reqwest::get(format!("{}/{}/login", proxy, upstream.uri()))
+        .await
+        .unwrap();
+
+    let values = |name| {
+        response
+            .headers()
+            .get_all(name)
+            .iter()
+            .map(|value| value.to_str().unwrap().to_string())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(
+        values("set-cookie"),
+        [
+            "session=1; Path=/",
+            "session=2; Path=/app",
+            "session=1; Path=/"
+        ]
+    );
+    assert_eq!(
+        values("link"),
+        ["</a.css>; rel=preload", "</b.js>; rel=preload"]
+    );
+}
+
+#[actix_web::test]
+async fn forwards_repeated_request_headers_in_order() {
+    let upstream = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&upstream)
+        .await;
+
+    proxy(
+        Config::default(),
+        TestRequest::get()
+            .uri(&target(&upstream, "/"))
+            .append_header(("X-Custom", "first"))
+            .append_header(("X-Custom", "second, third"))
+            .append_header(("X-Custom", "fourth")),
+    )
+    .await;
+
+    let requests = received(&upstream).await;
+    assert_eq!(
+        header_values(&requests[0], "x-custom"),
+        ["first", "second, third", "fourth"]
+    );
+}
+
+#[actix_web::test]
+async fn rejects_responses_over_header_limit() {
+    let upstream = MockServer::start().await;
+    Mock::given(path("/many"))
+        .respond_with(with_headers(20))
+        .mount(&upstream)
+        .await;
+    Mock::given(path("/few"))
+        .respond_with(with_headers(5))
+        .mount(&upstream)
+        .await;
+    let config = || Config {
+        max_response_headers: 10,
+        ..Config::default()
+    };
+
+    let response = proxy(
+        config(),
+        TestRequest::get().uri(&target(&upstream, "/many")),
+    )
+    .await;
+    assert_eq!(response.status, StatusCode::BAD_GATEWAY);
+    assert_eq!(response.header("X-Header-0"), None);
+
+    let response = proxy(config(), TestRequest::get().uri(&target(&upstream, "/few"))).await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.header("X-Header-4"), Some("value"));
+}
+
+#[actix_web::test]
+async fn rejects_excessive_headers_by_default() {
+    let upstream = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(with_headers(500))
+        .mount(&upstream)
+        .await;
+
+    let response = proxy(
+        Config::default(),
+        TestRequest::get().uri(&target(&upstream, "/")),
+    )
+    .await;
+
+    assert_eq!(response.status, StatusCode::BAD_GATEWAY);
+}
+
+#[actix_web::test]
+async fn answers_expect_continue_and_forwards_body() {
+    let upstream = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(body_string("uploaded"))
+        .respond_with(ResponseTemplate::new(201))
+        .expect(1)
+        .mount(&upstream)
+        .await;
+
+    let proxy = serve(&AppState::new(Config::default()));
+    let address = proxy.trim_start_matches("http://").to_string();
+    let path = target(&upstream, "/upload");
+    let (interim, response) = actix_web::rt::task::spawn_blocking(move || {
+        let mut stream = TcpStream::connect(address).unwrap();
+        write!(
+            stream,
+            "POST {} HTTP/1.1\r\nHost: localhost\r\nContent-Length: 8\r\nExpect: 100-continue\r\nConnection: close\r\n\r\n",
+            path
+        )
+        .unwrap();
+
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut interim = String::new();
+        reader.read_line(&mut interim).unwrap();
+        let mut blank = String::new();
+        reader.read_line(&mut blank).unwrap();
+
+        stream.write_all(b"uploaded").unwrap();
+        let mut response = String::new();
+        reader.read_to_string(&mut response).unwrap();
+        (interim, response)
+    })
+    .await
+    .unwrap();
+
+    assert_eq!(interim, "HTTP/1.1 100 Continue\r\n");
+    assert!(response.starts_with("HTTP/1.1 201 Created\r\n"));
+
+    let requests = received(&upstream).await;
+    assert!(header_values(&requests[0], "Expect").is_empty());
+}
+
+#[actix_web::test]
+async fn forwards_post_with_method_override() {
+    let upstream = MockServer::start().await;
+    Mock::given(method("DELETE"))
+        .and(path("/items/1"))
+        .respond_with(ResponseTemplate::new(204))
+        .expect(1)
+        .mount(&upstream)
+        .await;
+
+    let response = proxy(
+        Config::default(),
+        TestRequest::post()
+            .uri(&target(&upstream, "/items/1"))
+            .insert_header(("X-HTTP-Method-Override", "delete")),
+    )
+    .await;
+
+    assert_eq!(response.status, StatusCode::NO_CONTENT);
+    let requests = received(&upstream).await;
+    assert!(header_values(&requests[0], "X-HTTP-Method-Override").is_empty());
+}
+
+#[actix_web::test]
+async fn rejects_invalid_method_override() {
+    let upstream = MockServer::start().await;
+
+    let response = proxy(
+        Config::default(),
+        TestRequest::post()
+            .uri(&target(&upstream, "/items/1"))
+            .insert_header(("X-HTTP-Method-Override", "CONNECT")),
+    )
+    .await;
+
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+    assert!(received(&upstream).await.is_empty());
+}
+
+#[actix_web::test]
+async fn rejects_method_override_outside_allowed_methods() {
+    let upstream = MockServer::start().await;
+
+    let response = proxy(
+        Config {
+            allowed_methods: vec![Method::GET, Method::POST],
+            ..Config::default()
+        },
+        TestRequest::post()
+            .uri(&target(&upstream, "/items/1"))
+            .insert_header(("X-HTTP-Method-Override", "DELETE")),
+    )
+    .await;
+
+    assert_eq!(response.status, StatusCode::METHOD_NOT_ALLOWED);
+    assert_eq!(response.header("Allow"), Some("GET, POST"));
+    assert!(received(&upstream).await.is_empty());
+}
+
+#[actix_web::test]
+async fn rejects_truncated_request_body() {
+    let upstream = MockServer::start().await;
+    let state = AppState::new(Config::default());
+    let app = {
+        let state = state.clone();
+        test::init_service(App::new().configure(move |cfg| state.configure(cfg))).await
+    };
+
+    // The client goes away after sending part of the body
+    let body = futures_util::stream::iter([
+        Ok(web::Bytes::from_static(b"truncated")),
+        Err(PayloadError::Incomplete(None)),
+    ]);
+    let (req, _) = TestRequest::post()
+        .uri(&target(&upstream, "/upload"))
+        .insert_header(("Content-Length", "100"))
+        .to_request()
+        .replace_payload(Payload::from(
+            Box::pin(body) as Pin<Box<dyn Stream<Item = _>>>
+        ));
+    let response = test::call_service(&app, req).await;
+
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let body = test::read_body(response).await;
+    assert!(String::from_utf8_lossy(&body).starts_with("Failed to read request body"));
+    assert!(received(&upstream).await.is_empty());
+    assert_eq!(state.metrics.request_body_errors.get(), 1);
+}
+
+#[actix_web::test]
+async fn rejects_chunked_body_over_limit() {
+    let upstream = MockServer::start().await;
+    let state = AppState::new(Config {
+        max_body_size: 10,
+        ..Config::default()
+    });
+    let app = {
+        let state = state.clone();
+        test::init_service(App::new().configure(move |cfg| state.configure(cfg))).await
+    };
+
+    // No Content-Length, the limit is only noticed while the body arrives
+    let body = futures_util::stream::iter([
+        Ok(web::Bytes::from_static(b"first ")),
+        Ok(web::Bytes::from_static(b"second ")),
+        Ok(web::Bytes::from_static(b"third")),
+    ]);
+    let (req, _) = TestRequest::post()
+        .uri(&target(&upstream, "/upload"))
+        .insert_header(("Transfer-Encoding", "chunked"))
+        .to_request()
+        .replace_payload(Payload::from(
+            Box::pin(body) as Pin<Box<dyn Stream<Item = Result<_, PayloadError>>>>
+        ));
+    let response = test::call_service(&app, req).await;
+
+    assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    assert!(received(&upstream).await.is_empty());
+}
+
+#[actix_web::test]
+async fn rejects_declared_length_over_limit() {
+    let upstream = MockServer::start().await;
+    let config = Config {
+        max_body_size: 10,
+        ..Config::default()
+    };
+
+    let response = proxy(
+        config,
+        TestRequest::post()
+            .uri(&target(&upstream, "/upload"))
+            .set_payload("a body that is too long"),
+    )
+    .await;
+
+    assert_eq!(response.status, StatusCode::PAYLOAD_TOO_LARGE);
+    assert!(received(&upstream).await.is_empty());
+}
+
+#[actix_web::test]
+async fn buffers_responses_below_stream_threshold() {
+    let upstream = MockServer::start().await;
+    Mock::given(path("/small"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("x".repeat(100)))
+        .mount(&upstream)
+        .await;
+    Mock::given(path("/large"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("x".repeat(5000)))
+        .mount(&upstream)
+        .await;
+    let proxy = serve(&AppState::new(Config {
+        stream_threshold: 1024,
+        ..Config::default()
+    }));
+
+    let small = reqwest::get(format!("{}/{}/small", proxy, upstream.uri()))
+        .await
+        .unwrap();
+    assert_eq!(small.headers()["Content-Length"], "100");
+    assert!(!small.headers().contains_key("Transfer-Encoding"));
+    assert_eq!(small.text().await.unwrap(), "x".repeat(100));
+
+    let large = reqwest::get(format!("{}/{}/large", proxy, upstream.uri()))
+        .await
+        .unwrap();
+    assert_eq!(large.headers()["Transfer-Encoding"], "chunked");
+    assert!(!large.headers().contains_key("Content-Length"));
+    assert_eq!(large.text().await.unwrap().len(), 5000);
+}
+
+#[actix_web::test]
+async fn sends_server_timing_when_enabled() {
+    let upstream = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(200).set_delay(std::time::Duration::from_millis(50)))
+        .mount(&upstream)
+        .await;
+    let config = Config {
+        send_server_timing: true,
+        ..Config::default()
+    };
+
+    let response = proxy(config, TestRequest::get().uri(&target(&upstream, "/"))).await;
+
+    let timing = response.header("Server-Timing").unwrap();
+    let durations: Vec<(&str, f64)> = timing
+        .split(", ")
+        .map(|metric| {
+            let (name, duration) = metric.split_once(";dur=").unwrap();
+            (name, duration.parse().unwrap())
+        })
+        .collect();
+    assert_eq!(durations[0].0, "upstream");
+    assert!(durations[0].1 >= 50.0, "{}", timing);
+    assert_eq!(durations[1].0, "proxy");
+    assert!(response
+        .header("Access-Control-Expose-Headers")
+        .unwrap()
+        .ends_with(", Server-Timing"));
+}
+
+#[actix_web::test]
+async fn sends_no_server_timing_by_default() {
+    let upstream = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&upstream)
+        .await;
+
+    let response = proxy(
+        Config::default(),
+        TestRequest::get().uri(&target(&upstream, "/")),
+    )
+    .await;
+
+    assert_eq!(response.header("Server-Timing"), None);
+}
+
+#[actix_web::test]
+async fn rejects_declared_length_over_limit_before_the_body_arrives() {
+    let upstream = MockServer::start().await;
+    let proxy = serve(&AppState::new(Config {
+        max_body_size: 1024,
+        ..Config::default()
+    }));
+    let address = proxy.trim_start_matches("http://").to_string();
+    let path = target(&upstream, "/upload");
+
+    let response = actix_web::rt::task::spawn_blocking(move || {
+        let mut stream = TcpStream::connect(address).unwrap();
+        stream
+            .set_read_timeout(Some(std::time::Duration::from_secs(2)))
+            .unwrap();
+        // Only the head is sent, the declared body never follows
+        write!(
+            stream,
+            "POST {} HTTP/1.1\r\nHost: localhost\r\nContent-Length: 104857600\r\n\r\n",
+            path
+        )
+        .unwrap();
+        let mut reader = BufReader::new(stream);
+        let mut status = String::new();
+        reader.read_line(&mut status).unwrap();
+        let mut head = String::new();
+        let mut line = String::new();
+        while reader.read_line(&mut line).unwrap() > 2 {
+            head.push_str(&line.to_ascii_lowercase());
+            line.clear();
+        }
+        (status, head)
+    })
+    .await
+    .unwrap();
+
+    assert!(response.0.starts_with("HTTP/1.1 413"), "{}", response.0);
+    assert!(response.1.contains("connection: close"), "{}", response.1);
+    assert!(received(&upstream).await.is_empty());
+}
+
+/// An HTTP/1.0 upstream that sends its body in parts without a
+/// Content-Length, ending it by closing the connection.
+fn close_delimited_upstream(parts: &'static [&'static str]) -> String {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    std::thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut line = String::new();
+        while reader.read_line(&mut line).unwrap() > 2 {
+            line.clear();
+        }
+        let mut stream = stream;
+        stream
+            .write_all(b"HTTP/1.0 200 OK\r\nContent-Type: text/plain\r\n\r\n")
+            .unwrap();
+        for part in parts {
+            std::thread::sleep(std::time::Duration::from_millis(50));
+            stream.write_all(part.as_bytes()).unwrap();
+            stream.flush().unwrap();
+        }
+    });
+    format!("http://{}", address)
+}
+
+#[actix_web::test]
+async fn forwards_close_delimited_bodies_in_full() {
+    let upstream = close_delimited_upstream(&["first ", "second ", "third"]);
+
+    let response = proxy(
+        Config::default(),
+        TestRequest::get().uri(&format!("/{}/legacy", upstream)),
+    )
+    .await;
+
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.text(), "first second third");
+}
+
+#[actix_web::test]
+async fn frames_close_delimited_bodies_for_the_client() {
+    let upstream = close_delimited_upstream(&["first ", "second ", "third"]);
+    let proxy = serve(&AppState::new(Config::default()));
+
+    let response = reqwest::get(format!("{}/{}/legacy", proxy, upstream))
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+    assert_eq!(response.headers()["Transfer-Encoding"], "chunked");
+    assert_eq!(response.text().await.unwrap(), "first second third");
+}
@@ -0,0 +1,170 @@
+mod common;
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use actix_web::http::StatusCode;
+use actix_web::test::TestRequest;
+use wiremock::matchers::method;
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+use common::{proxy_with, target};
+use rcp::clock::ManualClock;
+use rcp::config::Config;
+use rcp::config_file::ConfigFile;
+use rcp::AppState;
+
+async fn upstream() -> MockServer {
+    let upstream = MockServer::start().await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&upstream)
+        .await;
+    upstream
+}
+
+fn path_limited() -> AppState {
+    AppState::new(Config {
+        file: ConfigFile::parse(
+            r#"
+            [[path_limits]]
+            pattern = "*/login"
+            rate_limit = { requests_per_second = 0.01, burst = 2 }
+            "#,
+        )
+        .unwrap(),
+        ..Config::default()
+    })
+}
+
+async fn post(state: &AppState, upstream: &MockServer, path: &str) -> StatusCode {
+    proxy_with(state, TestRequest::post().uri(&target(upstream, path)))
+        .await
+        .status
+}
+
+#[actix_web::test]
+async fn limits_requests_to_matching_paths() {
+    let upstream = upstream().await;
+    let state = path_limited();
+
+    assert_eq!(post(&state, &upstream, "/login").await, StatusCode::OK);
+    assert_eq!(post(&state, &upstream, "/v2/login").await, StatusCode::OK);
+
+    let response = proxy_with(
+        &state,
+        TestRequest::post().uri(&target(&upstream, "/login")),
+    )
+    .await;
+    assert_eq!(response.status, StatusCode::TOO_MANY_REQUESTS);
+    assert!(response.header("Retry-After").is_some());
+}
+
+#[actix_web::test]
+async fn refills_budget_over_time() {
+    let upstream = upstream().await;
+    let clock = Arc::new(ManualClock::new());
+    let state = AppState::with_clock(
+        Config {
+            file: ConfigFile::parse(
+                r#"
+                [[path_limits]]
+                pattern = "/login"
+                rate_limit = { requests_per_second = 0.5, burst = 1 }
+                "#,
+            )
+            .unwrap(),
+            ..Config::default()
+        },
+        clock.clone(),
+    );
+
+    assert_eq!(post(&state, &upstream, "/login").await, StatusCode::OK);
+    let response = proxy_with(
+        &state,
+        TestRequest::post().uri(&target(&upstream, "/login")),
+    )
+    .await;
+    assert_eq!(response.status, StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(response.header("Retry-After"), Some("2"));
+
+    clock.advance(Duration::from_secs(1));
+    assert_eq!(
+        post(&state, &upstream, "/login").await,
+        StatusCode::TOO_MANY_REQUESTS
+    );
+    clock.advance(Duration::from_secs(1));
+    assert_eq!(post(&state, &upstream, "/login").await, StatusCode::OK);
+}
+
+#[actix_web::test]
+async fn passes_requests_to_other_paths() {
+    let upstream = upstream().await;
+    let state = path_limited();
+
+    for _ in 0..5 {
+        assert_eq!(post(&state, &upstream, "/login/help").await, StatusCode::OK);
+        assert_eq!(post(&state, &upstream, "/data").await, StatusCode::OK);
+    }
+}
+
+#[actix_web::test]
+async fn rejects_relative_path_patterns() {
+    let result = ConfigFile::parse(
+        r#"
+        [[path_limits]]
+        pattern = "login"
+        rate_limit = { requests_per_second = 1.0 }
+        "#,
+    );
+
+    assert!(result.is_err());
+}
+
+#[actix_web::test]
+async fn limits_each_host_on_its_own() {
+    // Listening on all addresses, the upstream can be reached as two hosts
+    let listener = std::net::TcpListener::bind("0.0.0.0:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let upstream = MockServer::builder().listener(listener).start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&upstream)
+        .await;
+    let clock = Arc::new(ManualClock::new());
+    let state = AppState::with_clock(
+        Config {
+            file: ConfigFile::parse(
+                r#"
+                [hosts."127.0.0.1"]
+                rate_limit = { requests_per_second = 1.0, burst = 1 }
+
+                [hosts."127.0.0.2"]
+                rate_limit = { requests_per_second = 1.0, burst = 1 }
+                "#,
+            )
+            .unwrap(),
+            ..Config::default()
+        },
+        clock.clone(),
+    );
+    let get = |host: &str| {
+        let uri = format!("/http://{}:{}/", host, port);
+        let state = state.clone();
+        async move {
+            proxy_with(&state, TestRequest::get().uri(&uri))
+                .await
+                .status
+        }
+    };
+
+    assert_eq!(get("127.0.0.1").await, StatusCode::OK);
+    assert_eq!(get("127.0.0.1").await, StatusCode::SERVICE_UNAVAILABLE);
+    // The other host still has its whole budget
+    assert_eq!(get("127.0.0.2").await, StatusCode::OK);
+    assert_eq!(get("127.0.0.2").await, StatusCode::SERVICE_UNAVAILABLE);
+
+    clock.advance(Duration::from_secs(1));
+    assert_eq!(get("127.0.0.1").await, StatusCode::OK);
+    assert_eq!(get("127.0.0.2").await, StatusCode::OK);
+}
@@ -0,0 +1,129 @@
+mod common;
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use actix_web::http::StatusCode;
+use actix_web::test::TestRequest;
+use futures_util::future::join_all;
+use wiremock::matchers::method;
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+use common::{proxy, proxy_with, received};
+use rcp::clock::ManualClock;
+use rcp::config::Config;
+use rcp::AppState;
+
+async fn canary(status: u16) -> MockServer {
+    let upstream = MockServer::start().await;
+    Mock::given(method("HEAD"))
+        .respond_with(ResponseTemplate::new(status))
+        .mount(&upstream)
+        .await;
+    upstream
+}
+
+fn checking(url: String) -> Config {
+    Config {
+        canary_url: Some(url),
+        ..Config::default()
+    }
+}
+
+#[actix_web::test]
+async fn deep_check_succeeds_with_healthy_canary() {
+    let upstream = canary(200).await;
+
+    let response = proxy(
+        checking(upstream.uri()),
+        TestRequest::get().uri("/readyz?deep=1"),
+    )
+    .await;
+
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(received(&upstream).await.len(), 1);
+}
+
+#[actix_web::test]
+async fn deep_check_fails_with_unhealthy_canary() {
+    let upstream = canary(503).await;
+    let state = AppState::new(checking(upstream.uri()));
+
+    let response = proxy_with(&state, TestRequest::get().uri("/readyz?deep=1")).await;
+    assert_eq!(response.status, StatusCode::SERVICE_UNAVAILABLE);
+
+    // The shallow probe doesn't contact the canary
+    let response = proxy_with(&state, TestRequest::get().uri("/readyz")).await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(received(&upstream).await.len(), 1);
+}
+
+#[actix_web::test]
+async fn deep_check_fails_with_unreachable_canary() {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    drop(listener);
+
+    let response = proxy(
+        checking(format!("http://{}/", address)),
+        TestRequest::get().uri("/readyz?deep=1"),
+    )
+    .await;
+
+    assert_eq!(response.status, StatusCode::SERVICE_UNAVAILABLE);
+}
+
+#[actix_web::test]
+async fn reuses_canary_result_until_it_expires() {
+    let upstream = canary(200).await;
+    let clock = Arc::new(ManualClock::new());
+    let state = AppState::with_clock(
+        Config {
+            canary_cache_ttl: Duration::from_secs(10),
+            ..checking(upstream.uri())
+        },
+        clock.clone(),
+    );
+
+    for _ in 0..3 {
+        let response = proxy_with(&state, TestRequest::get().uri("/readyz?deep=1")).await;
+        assert_eq!(response.status, StatusCode::OK);
+    }
+    assert_eq!(received(&upstream).await.len(), 1);
+
+    clock.advance(Duration::from_secs(11));
+    proxy_with(&state, TestRequest::get().uri("/readyz?deep=1")).await;
+    assert_eq!(received(&upstream).await.len(), 2);
+}
+
+#[actix_web::test]
+async fn requests_the_canary_once_for_concurrent_probes() {
+    let upstream = MockServer::start().await;
+    Mock::given(method("HEAD"))
+        .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_millis(200)))
+        .mount(&upstream)
+        .await;
+    let clock = Arc::new(ManualClock::new());
+    let state = AppState::with_clock(
+        Config {
+            canary_cache_ttl: Duration::from_secs(10),
+            ..checking(upstream.uri())
+        },
+        clock.clone(),
+    );
+    let probes =
+        || join_all((0..5).map(|_| proxy_with(&state, TestRequest::get().uri("/readyz?deep=1"))));
+
+    for response in probes().await {
+        assert_eq!(response.status, StatusCode::OK);
+    }
+    assert_eq!(received(&upstream).await.len(), 1);
+
+    // Once the result expires, one probe refreshes it and the others get
+    // the last one
+    clock.advance(Duration::from_secs(11));
+    for response in probes().await {
+        assert_eq!(response.status, StatusCode::OK);
+    }
+    assert_eq!(received(&upstream).await.len(), 2);
+}
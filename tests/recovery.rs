@@ -0,0 +1,60 @@
+use actix_web::{middleware, web, App, HttpResponse, HttpServer};
+use reqwest::StatusCode;
+
+use rcp::config::Config;
+use rcp::recovery;
+use rcp::AppState;
+
+async fn panicking() -> HttpResponse {
+    panic!("deliberate panic for testing")
+}
+
+/// Serves the proxy with a `/panic` endpoint whose handler panics.
+fn serve_panicking(state: &AppState) -> String {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let state = state.clone();
+    let server = HttpServer::new(move || {
+        let state = state.clone();
+        App::new()
+            .service(
+                web::resource("/panic")
+                    .wrap(middleware::from_fn(recovery::catch_panics))
+                    .to(panicking),
+            )
+            .configure(move |cfg| state.configure(cfg))
+    })
+    .workers(1)
+    .disable_signals()
+    .listen(listener)
+    .unwrap()
+    .run();
+    actix_web::rt::spawn(server);
+    format!("http://{}", address)
+}
+
+#[actix_web::test]
+async fn answers_panics_with_internal_server_error() {
+    let state = AppState::new(Config::default());
+    let proxy = serve_panicking(&state);
+    let client = reqwest::Client::new();
+
+    let response = client
+        .get(format!("{}/panic", proxy))
+        .header("Origin", "https://app.example.com")
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(response.headers()["access-control-allow-origin"], "*");
+    assert_eq!(state.metrics.panics.get(), 1);
+
+    // The worker keeps serving requests after the panic
+    let response = client
+        .get(format!("{}/robots.txt", proxy))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
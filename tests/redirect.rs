@@ -0,0 +1,102 @@
+mod common;
+
+use actix_web::http::StatusCode;
+use actix_web::test::TestRequest;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+use common::{proxy_with, received, target};
+use rcp::config::Config;
+use rcp::AppState;
+
+async fn upstream() -> MockServer {
+    let upstream = MockServer::start().await;
+    for (from, to) in [
+        ("/old", "/new"),
+        ("/first", "/old"),
+        ("/ftp", "ftp://files.example.com/report.pdf"),
+    ] {
+        Mock::given(path(from))
+            .respond_with(ResponseTemplate::new(302).insert_header("Location", to))
+            .mount(&upstream)
+            .await;
+    }
+    Mock::given(path("/submit"))
+        .respond_with(ResponseTemplate::new(303).insert_header("Location", "/new"))
+        .mount(&upstream)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/new"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("moved"))
+        .mount(&upstream)
+        .await;
+    upstream
+}
+
+#[actix_web::test]
+async fn follows_redirects() {
+    let upstream = upstream().await;
+    let state = AppState::new(Config::default());
+
+    let response = proxy_with(&state, TestRequest::get().uri(&target(&upstream, "/first"))).await;
+
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.text(), "moved");
+    let redirects = &state.metrics.redirects;
+    assert_eq!(redirects.followed.get(), 2);
+    assert_eq!(redirects.blocked.get(), 0);
+    assert_eq!(redirects.chain_length.get_sample_count(), 1);
+    assert_eq!(redirects.chain_length.get_sample_sum(), 2.0);
+}
+
+#[actix_web::test]
+async fn passes_blocked_redirects_to_client() {
+    let upstream = upstream().await;
+    let state = AppState::new(Config::default());
+
+    let response = proxy_with(&state, TestRequest::get().uri(&target(&upstream, "/ftp"))).await;
+
+    assert_eq!(response.status, StatusCode::FOUND);
+    assert_eq!(
+        response.header("Location"),
+        Some("ftp://files.example.com/report.pdf")
+    );
+    assert_eq!(state.metrics.redirects.blocked.get(), 1);
+    assert_eq!(state.metrics.redirects.followed.get(), 0);
+}
+
+#[actix_web::test]
+async fn stops_at_redirect_limit() {
+    let upstream = upstream().await;
+    let state = AppState::new(Config {
+        max_redirects: 1,
+        ..Config::default()
+    });
+
+    let response = proxy_with(&state, TestRequest::get().uri(&target(&upstream, "/first"))).await;
+
+    assert_eq!(response.status, StatusCode::FOUND);
+    assert_eq!(response.header("Location"), Some("/new"));
+    assert_eq!(state.metrics.redirects.followed.get(), 1);
+}
+
+#[actix_web::test]
+async fn continues_with_get_after_see_other() {
+    let upstream = upstream().await;
+    let state = AppState::new(Config::default());
+
+    let response = proxy_with(
+        &state,
+        TestRequest::post()
+            .uri(&target(&upstream, "/submit"))
+            .insert_header(("Content-Type", "text/plain"))
+            .set_payload("form"),
+    )
+    .await;
+
+    assert_eq!(response.status, StatusCode::OK);
+    let requests = received(&upstream).await;
+    assert_eq!(requests[1].method.as_str(), "GET");
+    assert!(requests[1].body.is_empty());
+    assert!(!requests[1].headers.contains_key("content-type"));
+}
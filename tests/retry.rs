@@ -0,0 +1,199 @@
+mod common;
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use actix_web::http::StatusCode;
+use actix_web::test::TestRequest;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+use common::{proxy, proxy_with, received, target};
+use rcp::clock::ManualClock;
+use rcp::config::Config;
+use rcp::AppState;
+
+#[actix_web::test]
+async fn retries_temporary_failures() {
+    let upstream = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(503))
+        .up_to_n_times(2)
+        .mount(&upstream)
+        .await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("ok"))
+        .mount(&upstream)
+        .await;
+    let config = Config {
+        upstream_retries: 2,
+        ..Config::default()
+    };
+
+    let response = proxy(config, TestRequest::get().uri(&target(&upstream, "/"))).await;
+
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.text(), "ok");
+    assert_eq!(received(&upstream).await.len(), 3);
+}
+
+#[actix_web::test]
+async fn does_not_retry_post_requests() {
+    let upstream = MockServer::start().await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(503))
+        .mount(&upstream)
+        .await;
+    let config = Config {
+        upstream_retries: 2,
+        ..Config::default()
+    };
+
+    let response = proxy(config, TestRequest::post().uri(&target(&upstream, "/"))).await;
+
+    assert_eq!(response.status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(received(&upstream).await.len(), 1);
+}
+
+#[actix_web::test]
+async fn gives_up_retrying_at_the_deadline() {
+    let upstream = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(503).set_delay(Duration::from_millis(300)))
+        .mount(&upstream)
+        .await;
+    let config = Config {
+        upstream_retries: 10,
+        total_request_deadline: Some(Duration::from_secs(1)),
+        ..Config::default()
+    };
+
+    let started = Instant::now();
+    let response = proxy(config, TestRequest::get().uri(&target(&upstream, "/"))).await;
+
+    assert_eq!(response.status, StatusCode::GATEWAY_TIMEOUT);
+    assert!(response
+        .text()
+        .starts_with("Upstream didn't respond within the request deadline"));
+    assert!(started.elapsed() >= Duration::from_secs(1));
+    assert!(started.elapsed() < Duration::from_secs(2));
+    assert!(received(&upstream).await.len() < 5);
+}
+
+#[actix_web::test]
+async fn stops_retrying_once_the_budget_is_exhausted() {
+    let upstream = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(503))
+        .mount(&upstream)
+        .await;
+    let state = AppState::new(Config {
+        upstream_retries: 3,
+        retry_budget_ratio: 0.1,
+        retry_budget_min_per_second: 0.0,
+        retry_budget_burst: 2,
+        ..Config::default()
+    });
+
+    for _ in 0..3 {
+        let response = proxy_with(&state, TestRequest::get().uri(&target(&upstream, "/"))).await;
+        assert_eq!(response.status, StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    // The first request uses up the budget with two retries, the others
+    // fail fast
+    assert_eq!(received(&upstream).await.len(), 3 + 1 + 1);
+}
+
+#[actix_web::test]
+async fn earns_retries_with_successful_responses() {
+    let upstream = MockServer::start().await;
+    Mock::given(path("/ok"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&upstream)
+        .await;
+    Mock::given(path("/failing"))
+        .respond_with(ResponseTemplate::new(503))
+        .mount(&upstream)
+        .await;
+    let state = AppState::new(Config {
+        upstream_retries: 1,
+        retry_budget_ratio: 0.5,
+        retry_budget_min_per_second: 0.0,
+        retry_budget_burst: 1,
+        ..Config::default()
+    });
+    let failing = || TestRequest::get().uri(&target(&upstream, "/failing"));
+
+    proxy_with(&state, failing()).await;
+    proxy_with(&state, failing()).await;
+    assert_eq!(received(&upstream).await.len(), 2 + 1);
+
+    for _ in 0..2 {
+        proxy_with(&state, TestRequest::get().uri(&target(&upstream, "/ok"))).await;
+    }
+    proxy_with(&state, failing()).await;
+    assert_eq!(received(&upstream).await.len(), 3 + 2 + 2);
+}
+
+#[actix_web::test]
+async fn refills_the_budget_over_time() {
+    let upstream = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(503))
+        .mount(&upstream)
+        .await;
+    let clock = Arc::new(ManualClock::new());
+    let state = AppState::with_clock(
+        Config {
+            upstream_retries: 1,
+            retry_budget_ratio: 0.1,
+            retry_budget_min_per_second: 0.5,
+            retry_budget_burst: 1,
+            ..Config::default()
+        },
+        clock.clone(),
+    );
+    let attempts = || async {
+        let before = received(&upstream).await.len();
+        proxy_with(&state, TestRequest::get().uri(&target(&upstream, "/"))).await;
+        received(&upstream).await.len() - before
+    };
+
+    assert_eq!(attempts().await, 2);
+    assert_eq!(attempts().await, 1);
+    clock.advance(Duration::from_secs(1));
+    assert_eq!(attempts().await, 1);
+    clock.advance(Duration::from_secs(1));
+    assert_eq!(attempts().await, 2);
+}
+
+#[actix_web::test]
+async fn measures_the_request_deadline_with_the_clock() {
+    let upstream = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(503).set_delay(Duration::from_millis(200)))
+        .mount(&upstream)
+        .await;
+    let clock = Arc::new(ManualClock::new());
+    let state = AppState::with_clock(
+        Config {
+            upstream_retries: 10,
+            total_request_deadline: Some(Duration::from_secs(10)),
+            ..Config::default()
+        },
+        clock.clone(),
+    );
+
+    // The deadline passes while the first attempt waits for its response
+    let (response, ()) = futures_util::join!(
+        proxy_with(&state, TestRequest::get().uri(&target(&upstream, "/"))),
+        async {
+            actix_web::rt::time::sleep(Duration::from_millis(100)).await;
+            clock.advance(Duration::from_secs(11));
+        }
+    );
+
+    assert_eq!(response.status, StatusCode::GATEWAY_TIMEOUT);
+    assert_eq!(received(&upstream).await.len(), 1);
+}
@@ -0,0 +1,163 @@
+#![cfg(feature = "rewrite")]
+
+mod common;
+
+use actix_web::http::StatusCode;
+use actix_web::test::TestRequest;
+use reqwest::Url;
+use wiremock::matchers::method;
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+use common::{proxy, target};
+use rcp::config::Config;
+use rcp::rewrite::rewrite_css;
+
+const PAGE: &str = concat!(
+    r#"<a href="next.html">next</a>"#,
+    r#"<a href="/about">about</a>"#,
+    r#"<a href="https://example.com/x">other</a>"#,
+    r##"<a href="#top">top</a>"##,
+    r#"<a href="mailto:me@example.com">mail</a>"#,
+    r#"<img src="img/logo.png" srcset="a.png 1x, b.png 2x">"#,
+    r#"<img src="data:image/png;base64,AAAA">"#,
+);
+
+async fn upstream_with(body: &str, content_type: &str) -> MockServer {
+    let upstream = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(200).set_body_raw(body.to_string(), content_type))
+        .mount(&upstream)
+        .await;
+    upstream
+}
+
+fn rewriting() -> Config {
+    Config {
+        rewrite_html_urls: true,
+        ..Config::default()
+    }
+}
+
+#[actix_web::test]
+async fn rewrites_href_and_src_attributes() {
+    let upstream = upstream_with(PAGE, "text/html; charset=utf-8").await;
+    let base = upstream.uri();
+
+    let response = proxy(
+        rewriting(),
+        TestRequest::get().uri(&target(&upstream, "/docs/index.html")),
+    )
+    .await;
+
+    assert_eq!(response.status, StatusCode::OK);
+    let html = response.text();
+    assert!(html.contains(&format!(r#"href="/{}/docs/next.html""#, base)));
+    assert!(html.contains(&format!(r#"href="/{}/about""#, base)));
+    assert!(html.contains(r#"href="/https://example.com/x""#));
+    assert!(html.contains(&format!(r#"src="/{}/docs/img/logo.png""#, base)));
+    assert!(html.contains(&format!(
+        r#"srcset="/{0}/docs/a.png 1x, /{0}/docs/b.png 2x""#,
+        base
+    )));
+    // URLs that don't load anything over HTTP are kept
+    assert!(html.contains(r##"href="#top""##));
+    assert!(html.contains(r#"href="mailto:me@example.com""#));
+    assert!(html.contains(r#"src="data:image/png;base64,AAAA""#));
+}
+
+#[actix_web::test]
+async fn resolves_against_base_element() {
+    let upstream = upstream_with(
+        r#"<base href="https://cdn.example.com/assets/"><script src="app.js"></script>"#,
+        "text/html",
+    )
+    .await;
+
+    let response = proxy(rewriting(), TestRequest::get().uri(&target(&upstream, "/"))).await;
+
+    let html = response.text();
+    assert!(html.contains(r#"href="/https://cdn.example.com/assets/""#));
+    assert!(html.contains(r#"src="/https://cdn.example.com/assets/app.js""#));
+}
+
+#[actix_web::test]
+async fn rewrites_style_sheets() {
+    let upstream = upstream_with(
+        r#"body { background: url("bg.png") } @import 'theme.css';"#,
+        "text/css",
+    )
+    .await;
+    let base = upstream.uri();
+
+    let response = proxy(
+        rewriting(),
+        TestRequest::get().uri(&target(&upstream, "/css/main.css")),
+    )
+    .await;
+
+    assert_eq!(
+        response.text(),
+        format!(
+            r#"body {{ background: url("/{0}/css/bg.png") }} @import '/{0}/css/theme.css';"#,
+            base
+        )
+    );
+}
+
+#[actix_web::test]
+async fn keeps_other_content_types() {
+    let body = r#"{"html": "<a href=\"next.html\">"}"#;
+    let upstream = upstream_with(body, "application/json").await;
+
+    let response = proxy(rewriting(), TestRequest::get().uri(&target(&upstream, "/"))).await;
+
+    assert_eq!(response.text(), body);
+}
+
+#[actix_web::test]
+async fn rewrites_only_when_enabled() {
+    let upstream = upstream_with(PAGE, "text/html").await;
+
+    let response = proxy(
+        Config::default(),
+        TestRequest::get().uri(&target(&upstream, "/")),
+    )
+    .await;
+
+    assert_eq!(response.text(), PAGE);
+}
+
+#[actix_web::test]
+async fn passes_large_documents_through() {
+    let upstream = upstream_with(PAGE, "text/html").await;
+
+    let response = proxy(
+        Config {
+            rewrite_max_body_size: 16,
+            ..rewriting()
+        },
+        TestRequest::get().uri(&target(&upstream, "/")),
+    )
+    .await;
+
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.text(), PAGE);
+}
+
+#[test]
+fn rewrites_unquoted_css_urls() {
+    let base = Url::parse("https://example.com/a/b.css").unwrap();
+
+    assert_eq!(
+        rewrite_css(
+            "a { background: URL( img.png ) } @import url(x.css);",
+            &base
+        ),
+        "a { background: URL( /https://example.com/a/img.png ) } \
+         @import url(/https://example.com/a/x.css);"
+    );
+    assert_eq!(
+        rewrite_css("a { background: url(data:image/png;base64,AAAA) }", &base),
+        "a { background: url(data:image/png;base64,AAAA) }"
+    );
+}
@@ -0,0 +1,59 @@
+mod common;
+
+use actix_web::http::StatusCode;
+use actix_web::test::TestRequest;
+use wiremock::matchers::method;
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+use common::{proxy, received, target};
+use rcp::config::Config;
+
+async fn upstream() -> MockServer {
+    let upstream = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(200).set_body_raw("<html></html>", "text/html"))
+        .mount(&upstream)
+        .await;
+    upstream
+}
+
+#[actix_web::test]
+async fn serves_disallow_all_robots_txt() {
+    let response = proxy(Config::default(), TestRequest::get().uri("/robots.txt")).await;
+
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(
+        response.header("Content-Type"),
+        Some("text/plain; charset=utf-8")
+    );
+    assert_eq!(response.text(), "User-agent: *\nDisallow: /\n");
+}
+
+#[actix_web::test]
+async fn adds_noindex_header_when_enabled() {
+    let upstream = upstream().await;
+    let config = Config {
+        add_noindex: true,
+        ..Config::default()
+    };
+
+    let response = proxy(config, TestRequest::get().uri(&target(&upstream, "/page"))).await;
+
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.header("X-Robots-Tag"), Some("noindex"));
+    assert_eq!(received(&upstream).await.len(), 1);
+}
+
+#[actix_web::test]
+async fn omits_noindex_header_by_default() {
+    let upstream = upstream().await;
+
+    let response = proxy(
+        Config::default(),
+        TestRequest::get().uri(&target(&upstream, "/page")),
+    )
+    .await;
+
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.header("X-Robots-Tag"), None);
+}
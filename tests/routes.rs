@@ -0,0 +1,164 @@
+mod common;
+
+use actix_web::http::StatusCode;
+use actix_web::test::TestRequest;
+use wiremock::matchers::{method, path, query_param};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+use common::{proxy, received, target};
+use rcp::config::Config;
+use rcp::config_file::ConfigFile;
+
+fn routed(upstream: &MockServer, extra: &str) -> Config {
+    let file = format!(
+        r#"
+        {extra}
+
+        [routes]
+        "/api" = "{uri}/base/"
+        "/api/v2" = "{uri}/v2"
+        "#,
+        extra = extra,
+        uri = upstream.uri()
+    );
+    Config {
+        file: ConfigFile::parse(&file).unwrap(),
+        ..Config::default()
+    }
+}
+
+#[actix_web::test]
+async fn joins_route_base_with_remaining_path() {
+    let upstream = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/base/users/1"))
+        .and(query_param("fields", "name"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("user"))
+        .expect(1)
+        .mount(&upstream)
+        .await;
+
+    let response = proxy(
+        routed(&upstream, ""),
+        TestRequest::get().uri("/api/users/1?fields=name"),
+    )
+    .await;
+
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.text(), "user");
+}
+
+#[actix_web::test]
+async fn prefers_longest_matching_prefix() {
+    let upstream = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/v2/items"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&upstream)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/base/"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&upstream)
+        .await;
+
+    let response = proxy(
+        routed(&upstream, ""),
+        TestRequest::get().uri("/api/v2/items"),
+    )
+    .await;
+    assert_eq!(response.status, StatusCode::OK);
+
+    let response = proxy(routed(&upstream, ""), TestRequest::get().uri("/api")).await;
+    assert_eq!(response.status, StatusCode::OK);
+}
+
+#[actix_web::test]
+async fn falls_back_to_full_urls_outside_routes() {
+    let upstream = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/apidocs"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("docs"))
+        .expect(1)
+        .mount(&upstream)
+        .await;
+
+    let response = proxy(
+        routed(&upstream, ""),
+        TestRequest::get().uri(&target(&upstream, "/apidocs")),
+    )
+    .await;
+
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.text(), "docs");
+}
+
+#[actix_web::test]
+async fn rejects_full_urls_when_disabled() {
+    let upstream = MockServer::start().await;
+
+    let response = proxy(
+        routed(&upstream, "full_url_targets = false"),
+        TestRequest::get().uri(&target(&upstream, "/data")),
+    )
+    .await;
+
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+    assert!(received(&upstream).await.is_empty());
+}
+
+#[actix_web::test]
+async fn rejects_invalid_route_bases() {
+    let result = ConfigFile::parse(
+        r#"
+        [routes]
+        "/files" = "ftp://files.internal/"
+        "#,
+    );
+
+    assert!(result.is_err());
+}
+
+#[actix_web::test]
+async fn rejects_dot_segments_under_routes() {
+    let upstream = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&upstream)
+        .await;
+
+    for path in [
+        "/api/../secret",
+        "/api/users/../../secret",
+        "/api/%2e%2e/secret",
+        "/api/%2E./secret",
+        "/api/..%2fsecret",
+        "/api/./users",
+    ] {
+        let response = proxy(routed(&upstream, ""), TestRequest::get().uri(path)).await;
+        assert_eq!(response.status, StatusCode::BAD_REQUEST, "{}", path);
+        assert_eq!(response.text(), "Invalid path");
+    }
+    assert!(received(&upstream).await.is_empty());
+}
+
+#[actix_web::test]
+async fn accepts_dots_within_segments_under_routes() {
+    let upstream = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/base/files/archive..tar/.hidden"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&upstream)
+        .await;
+
+    let response = proxy(
+        routed(&upstream, ""),
+        TestRequest::get().uri("/api/files/archive..tar/.hidden"),
+    )
+    .await;
+
+    assert_eq!(response.status, StatusCode::OK);
+}
@@ -0,0 +1,102 @@
+mod common;
+
+use std::io::Read;
+use std::net::TcpListener;
+use std::sync::mpsc;
+use std::thread;
+
+use actix_web::test::TestRequest;
+
+use common::proxy;
+use rcp::config::Config;
+use rcp::config_file::ConfigFile;
+
+/// An upstream that records the TLS ClientHello record it receives and
+/// then hangs up, returning its address and the record.
+fn tls_upstream() -> (String, mpsc::Receiver<Vec<u8>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut hello = Vec::new();
+        let mut buffer = [0; 4096];
+        // The record header ends with the length of the record
+        while hello.len() < 5 || hello.len() < 5 + u16::from_be_bytes([hello[3], hello[4]]) as usize
+        {
+            match stream.read(&mut buffer) {
+                Ok(0) | Err(_) => break,
+                Ok(read) => hello.extend_from_slice(&buffer[..read]),
+            }
+        }
+        let _ = sender.send(hello);
+    });
+    (address.to_string(), receiver)
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack
+        .windows(needle.len())
+        .any(|window| window == needle)
+}
+
+#[actix_web::test]
+async fn sends_sni_override_of_host() {
+    let (address, hello) = tls_upstream();
+    let config = Config {
+        file: ConfigFile::parse(
+            r#"
+            [hosts."127.0.0.1"]
+            sni_override = "origin.sni.test"
+            "#,
+        )
+        .unwrap(),
+        ..Config::default()
+    };
+
+    proxy(
+        config,
+        TestRequest::get().uri(&format!("/https://{}/", address)),
+    )
+    .await;
+
+    assert!(contains(&hello.recv().unwrap(), b"origin.sni.test"));
+}
+
+#[actix_web::test]
+async fn sends_no_sni_override_for_other_hosts() {
+    let (address, hello) = tls_upstream();
+    let config = Config {
+        file: ConfigFile::parse(
+            r#"
+            [hosts."other.example.com"]
+            sni_override = "origin.sni.test"
+            "#,
+        )
+        .unwrap(),
+        ..Config::default()
+    };
+
+    proxy(
+        config,
+        TestRequest::get().uri(&format!("/https://{}/", address)),
+    )
+    .await;
+
+    let hello = hello.recv().unwrap();
+    assert!(!hello.is_empty());
+    assert!(!contains(&hello, b"origin.sni.test"));
+}
+
+#[test]
+fn rejects_invalid_sni_override() {
+    let error = ConfigFile::parse(
+        r#"
+        [hosts."api.example.com"]
+        sni_override = "https://www.example.com/"
+        "#,
+    )
+    .unwrap_err();
+
+    assert!(error.contains("Invalid sni_override"));
+}
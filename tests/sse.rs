@@ -0,0 +1,75 @@
+mod common;
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use futures_util::StreamExt;
+
+use common::serve;
+use rcp::config::Config;
+use rcp::AppState;
+
+/// An upstream that sends `count` events, pausing `interval` between them.
+fn event_source(count: usize, interval: Duration) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut line = String::new();
+        while reader.read_line(&mut line).unwrap() > 2 {
+            line.clear();
+        }
+
+        let mut stream = stream;
+        stream
+            .write_all(
+                b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nTransfer-Encoding: chunked\r\n\r\n",
+            )
+            .unwrap();
+        for index in 0..count {
+            if index > 0 {
+                thread::sleep(interval);
+            }
+            let event = format!("data: {}\n\n", index);
+            write!(stream, "{:x}\r\n{}\r\n", event.len(), event).unwrap();
+            stream.flush().unwrap();
+        }
+        stream.write_all(b"0\r\n\r\n").unwrap();
+    });
+    format!("http://{}", address)
+}
+
+#[actix_web::test]
+async fn streams_events_as_they_arrive() {
+    let upstream = event_source(3, Duration::from_millis(300));
+    // Pauses between events are longer than the idle timeout, which does
+    // not apply to event streams.
+    let config = Config {
+        stream_idle_timeout: Some(Duration::from_millis(100)),
+        ..Config::default()
+    };
+    let proxy = serve(&AppState::new(config));
+
+    let started = Instant::now();
+    let response = reqwest::get(format!("{}/{}/events", proxy, upstream))
+        .await
+        .unwrap();
+    assert_eq!(response.headers()["Content-Type"], "text/event-stream");
+    assert_eq!(response.headers()["X-Accel-Buffering"], "no");
+
+    let mut arrivals = Vec::new();
+    let mut body = String::new();
+    let mut chunks = response.bytes_stream();
+    while let Some(chunk) = chunks.next().await {
+        body.push_str(&String::from_utf8_lossy(&chunk.unwrap()));
+        arrivals.push(started.elapsed());
+    }
+
+    assert_eq!(body, "data: 0\n\ndata: 1\n\ndata: 2\n\n");
+    assert!(arrivals.len() >= 3);
+    assert!(arrivals[0] < Duration::from_millis(300));
+    assert!(arrivals[arrivals.len() - 1] >= Duration::from_millis(600));
+}
@@ -0,0 +1,49 @@
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+use rcp::config::Config;
+use rcp::AppState;
+
+/// A URL on a local port that nothing listens on.
+fn unreachable_url() -> String {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    format!("http://{}/", address)
+}
+
+fn checking(url: String, fatal: bool) -> AppState {
+    AppState::new(Config {
+        startup_check_url: Some(url),
+        startup_check_fatal: fatal,
+        ..Config::default()
+    })
+}
+
+#[actix_web::test]
+async fn passes_startup_check_when_upstream_is_reachable() {
+    let upstream = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/health"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&upstream)
+        .await;
+
+    let state = checking(format!("{}/health", upstream.uri()), true);
+
+    assert!(state.startup_check().await.is_ok());
+}
+
+#[actix_web::test]
+async fn aborts_startup_on_failed_fatal_check() {
+    let state = checking(unreachable_url(), true);
+
+    assert!(state.startup_check().await.is_err());
+}
+
+#[actix_web::test]
+async fn only_warns_on_failed_check_by_default() {
+    let state = checking(unreachable_url(), false);
+
+    assert!(state.startup_check().await.is_ok());
+}
@@ -0,0 +1,118 @@
+mod common;
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use reqwest::StatusCode;
+
+use common::serve;
+use rcp::config::Config;
+use rcp::AppState;
+
+/// An upstream that waits `delay` before sending the response headers,
+/// then sends a chunk of the body after each of `pauses`.
+fn download(delay: Duration, pauses: Vec<Duration>) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut line = String::new();
+        while reader.read_line(&mut line).unwrap() > 2 {
+            line.clear();
+        }
+
+        thread::sleep(delay);
+        let mut stream = stream;
+        let _ = stream.write_all(
+            b"HTTP/1.1 200 OK\r\nContent-Type: application/octet-stream\r\nTransfer-Encoding: chunked\r\n\r\n",
+        );
+        for pause in pauses {
+            thread::sleep(pause);
+            // The proxy hangs up on stalled downloads
+            if stream.write_all(b"5\r\nchunk\r\n").is_err() || stream.flush().is_err() {
+                return;
+            }
+        }
+        let _ = stream.write_all(b"0\r\n\r\n");
+    });
+    format!("http://{}", address)
+}
+
+#[actix_web::test]
+async fn keeps_steady_streams_beyond_request_timeout() {
+    let upstream = download(Duration::ZERO, vec![Duration::from_millis(100); 6]);
+    let config = Config {
+        request_timeout: Some(Duration::from_millis(300)),
+        stream_idle_timeout: Some(Duration::from_millis(300)),
+        ..Config::default()
+    };
+    let proxy = serve(&AppState::new(config));
+
+    let started = Instant::now();
+    let response = reqwest::get(format!("{}/{}/file", proxy, upstream))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.text().await.unwrap(), "chunk".repeat(6));
+    assert!(started.elapsed() >= Duration::from_millis(600));
+}
+
+#[actix_web::test]
+async fn aborts_stalled_streams() {
+    let upstream = download(Duration::ZERO, vec![Duration::ZERO, Duration::from_secs(2)]);
+    let config = Config {
+        stream_idle_timeout: Some(Duration::from_millis(200)),
+        ..Config::default()
+    };
+    let proxy = serve(&AppState::new(config));
+
+    let started = Instant::now();
+    let response = reqwest::get(format!("{}/{}/file", proxy, upstream))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.text().await.is_err());
+    assert!(started.elapsed() < Duration::from_secs(2));
+}
+
+#[actix_web::test]
+async fn times_out_waiting_for_headers() {
+    let upstream = download(Duration::from_secs(2), vec![]);
+    let config = Config {
+        request_timeout: Some(Duration::from_millis(200)),
+        ..Config::default()
+    };
+    let proxy = serve(&AppState::new(config));
+
+    let started = Instant::now();
+    let response = reqwest::get(format!("{}/{}/file", proxy, upstream))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::GATEWAY_TIMEOUT);
+    assert!(started.elapsed() < Duration::from_secs(2));
+}
+
+#[actix_web::test]
+async fn times_out_waiting_for_response_headers() {
+    let upstream = download(Duration::from_secs(2), vec![]);
+    let config = Config {
+        response_header_timeout: Some(Duration::from_millis(200)),
+        ..Config::default()
+    };
+    let proxy = serve(&AppState::new(config));
+
+    let started = Instant::now();
+    let response = reqwest::get(format!("{}/{}/file", proxy, upstream))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::GATEWAY_TIMEOUT);
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .starts_with("Upstream didn't send response headers"));
+    assert!(started.elapsed() < Duration::from_secs(2));
+}
@@ -0,0 +1,150 @@
+mod common;
+
+use std::io::{Read, Write};
+use std::net::TcpListener;
+use std::sync::mpsc;
+use std::thread;
+
+use actix_web::http::StatusCode;
+use actix_web::test::TestRequest;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+use common::{proxy, received, untrusted_tls_upstream};
+use rcp::client::MinTlsVersion;
+use rcp::config::Config;
+
+/// An upstream that records the TLS ClientHello record it receives and
+/// refuses it with a `protocol_version` alert, like a server that only
+/// supports older TLS versions.
+fn outdated_tls_upstream() -> (String, mpsc::Receiver<Vec<u8>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut hello = Vec::new();
+        let mut buffer = [0; 4096];
+        while hello.len() < 5 || hello.len() < 5 + u16::from_be_bytes([hello[3], hello[4]]) as usize
+        {
+            match stream.read(&mut buffer) {
+                Ok(0) | Err(_) => break,
+                Ok(read) => hello.extend_from_slice(&buffer[..read]),
+            }
+        }
+        let _ = stream.write_all(&[0x15, 0x03, 0x03, 0x00, 0x02, 0x02, 0x46]);
+        let _ = sender.send(hello);
+    });
+    (address.to_string(), receiver)
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack
+        .windows(needle.len())
+        .any(|window| window == needle)
+}
+
+#[test]
+fn parses_min_tls_versions() {
+    assert_eq!(MinTlsVersion::parse("1.2"), Some(MinTlsVersion::Tls12));
+    assert_eq!(MinTlsVersion::parse("1.3"), Some(MinTlsVersion::Tls13));
+    assert_eq!(MinTlsVersion::parse("1.1"), None);
+}
+
+#[actix_web::test]
+async fn offers_only_tls_versions_from_the_minimum() {
+    let (address, hello) = outdated_tls_upstream();
+    let config = Config {
+        min_tls_version: Some(MinTlsVersion::Tls13),
+        ..Config::default()
+    };
+
+    let response = proxy(
+        config,
+        TestRequest::get().uri(&format!("/https://{}/", address)),
+    )
+    .await;
+
+    // The supported_versions extension lists TLS 1.3 alone
+    assert!(contains(
+        &hello.recv().unwrap(),
+        &[0x00, 0x2b, 0x00, 0x03, 0x02, 0x03, 0x04]
+    ));
+    assert_eq!(response.status, StatusCode::BAD_GATEWAY);
+    assert_eq!(response.text(), "TLS policy not met");
+}
+
+/// A plain HTTP upstream, and the `https://` URL of its `/resource`.
+async fn plain_http_upstream() -> (MockServer, String) {
+    let upstream = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/resource"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("plain"))
+        .mount(&upstream)
+        .await;
+    let url = format!("/https://{}/resource", upstream.address());
+    (upstream, url)
+}
+
+#[actix_web::test]
+async fn does_not_fall_back_to_http_for_explicit_ports() {
+    let (upstream, url) = plain_http_upstream().await;
+    let config = Config {
+        fallback_to_http_on_tls_error: true,
+        ..Config::default()
+    };
+
+    let response = proxy(config, TestRequest::get().uri(&url)).await;
+
+    assert_eq!(response.status, StatusCode::BAD_GATEWAY);
+    assert_eq!(response.text(), "Upstream TLS handshake failed");
+    assert!(received(&upstream).await.is_empty());
+}
+
+#[actix_web::test]
+async fn does_not_fall_back_to_http_by_default() {
+    let (upstream, url) = plain_http_upstream().await;
+
+    let response = proxy(Config::default(), TestRequest::get().uri(&url)).await;
+
+    assert_eq!(response.status, StatusCode::BAD_GATEWAY);
+    assert_eq!(response.text(), "Upstream TLS handshake failed");
+    assert!(received(&upstream).await.is_empty());
+}
+
+#[actix_web::test]
+async fn does_not_fall_back_to_http_when_the_tls_policy_is_not_met() {
+    let (address, _hello) = outdated_tls_upstream();
+    let config = Config {
+        min_tls_version: Some(MinTlsVersion::Tls13),
+        fallback_to_http_on_tls_error: true,
+        ..Config::default()
+    };
+
+    let response = proxy(
+        config,
+        TestRequest::get().uri(&format!("/https://{}/", address)),
+    )
+    .await;
+
+    assert_eq!(response.status, StatusCode::BAD_GATEWAY);
+    assert_eq!(response.text(), "TLS policy not met");
+}
+
+#[actix_web::test]
+async fn does_not_fall_back_to_http_for_untrusted_certificates() {
+    let address = untrusted_tls_upstream();
+    let config = Config {
+        fallback_to_http_on_tls_error: true,
+        ..Config::default()
+    };
+
+    let response = proxy(
+        config,
+        TestRequest::get().uri(&format!("/https://{}/", address)),
+    )
+    .await;
+
+    assert_eq!(response.status, StatusCode::BAD_GATEWAY);
+    assert_eq!(response.text(), "Upstream certificate rejected");
+}
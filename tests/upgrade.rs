@@ -0,0 +1,116 @@
+mod common;
+
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+use actix_web::http::StatusCode;
+use actix_web::test::TestRequest;
+use wiremock::matchers::method;
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+use common::{header_values, proxy, received, serve, target};
+use rcp::config::Config;
+use rcp::AppState;
+
+async fn upstream() -> MockServer {
+    let upstream = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&upstream)
+        .await;
+    upstream
+}
+
+#[actix_web::test]
+async fn rejects_h2c_upgrades() {
+    let upstream = upstream().await;
+
+    let response = proxy(
+        Config::default(),
+        TestRequest::get()
+            .uri(&target(&upstream, "/"))
+            .insert_header(("Connection", "Upgrade, HTTP2-Settings"))
+            .insert_header(("Upgrade", "h2c"))
+            .insert_header(("HTTP2-Settings", "AAMAAABkAARAAAAAAAIAAAAA")),
+    )
+    .await;
+
+    assert_eq!(response.status, StatusCode::NOT_IMPLEMENTED);
+    assert_eq!(
+        response.text(),
+        "Upgrading the connection to h2c is not supported"
+    );
+    assert_eq!(response.header("Access-Control-Allow-Origin"), Some("*"));
+    assert!(received(&upstream).await.is_empty());
+}
+
+#[actix_web::test]
+async fn rejects_websocket_upgrades() {
+    let upstream = upstream().await;
+
+    let response = proxy(
+        Config::default(),
+        TestRequest::get()
+            .uri(&target(&upstream, "/socket"))
+            .insert_header(("Connection", "upgrade"))
+            .insert_header(("Upgrade", "websocket"))
+            .insert_header(("Sec-WebSocket-Key", "dGhlIHNhbXBsZSBub25jZQ=="))
+            .insert_header(("Sec-WebSocket-Version", "13")),
+    )
+    .await;
+
+    assert_eq!(response.status, StatusCode::NOT_IMPLEMENTED);
+    assert!(received(&upstream).await.is_empty());
+}
+
+#[actix_web::test]
+async fn does_not_forward_upgrade_header_without_connection_upgrade() {
+    let upstream = upstream().await;
+
+    let response = proxy(
+        Config::default(),
+        TestRequest::get()
+            .uri(&target(&upstream, "/"))
+            .insert_header(("Upgrade", "h2c")),
+    )
+    .await;
+
+    assert_eq!(response.status, StatusCode::OK);
+    let requests = received(&upstream).await;
+    assert!(header_values(&requests[0], "upgrade").is_empty());
+}
+
+#[actix_web::test]
+async fn answers_upgrade_requests_on_a_real_connection() {
+    let upstream = upstream().await;
+    let address = serve(&AppState::new(Config::default()))
+        .trim_start_matches("http://")
+        .to_string();
+    let path = target(&upstream, "/");
+
+    let response = actix_web::rt::task::spawn_blocking(move || {
+        let mut stream = TcpStream::connect(address).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        write!(
+            stream,
+            "GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: Upgrade, HTTP2-Settings\r\nUpgrade: h2c\r\nHTTP2-Settings: AAMAAABkAARAAAAAAAIAAAAA\r\n\r\n",
+            path
+        )
+        .unwrap();
+        let mut response = String::new();
+        let _ = stream.read_to_string(&mut response);
+        response
+    })
+    .await
+    .unwrap();
+
+    assert!(
+        response.starts_with("HTTP/1.1 501 Not Implemented"),
+        "{}",
+        response
+    );
+    assert!(received(&upstream).await.is_empty());
+}
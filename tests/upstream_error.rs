@@ -0,0 +1,66 @@
+mod common;
+
+use actix_web::http::StatusCode;
+use actix_web::test::TestRequest;
+use wiremock::MockServer;
+
+use common::{proxy, untrusted_tls_upstream};
+use rcp::config::Config;
+use rcp::upstream_error::{classify, UpstreamErrorKind};
+
+/// An `https://` URL for a server that only speaks plain HTTP, so that the
+/// TLS handshake fails.
+async fn plain_http_server() -> (MockServer, String) {
+    let upstream = MockServer::start().await;
+    let url = upstream.uri().replace("http://", "https://");
+    (upstream, url)
+}
+
+#[actix_web::test]
+async fn classifies_handshake_failures_as_tls() {
+    let (_upstream, url) = plain_http_server().await;
+
+    let error = reqwest::get(&url).await.unwrap_err();
+
+    assert_eq!(classify(&error), UpstreamErrorKind::Tls);
+    assert!(classify(&error).allows_http_fallback());
+}
+
+#[actix_web::test]
+async fn classifies_untrusted_certificates_as_certificate() {
+    let address = untrusted_tls_upstream();
+
+    let error = reqwest::get(format!("https://{}/", address))
+        .await
+        .unwrap_err();
+
+    assert_eq!(classify(&error), UpstreamErrorKind::Certificate);
+    assert!(!classify(&error).allows_http_fallback());
+}
+
+#[actix_web::test]
+async fn classifies_refused_connections_as_connect() {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    drop(listener);
+
+    let error = reqwest::get(format!("http://{}", address))
+        .await
+        .unwrap_err();
+
+    assert_eq!(classify(&error), UpstreamErrorKind::Connect);
+}
+
+#[actix_web::test]
+async fn hides_tls_error_details_from_clients() {
+    let (_upstream, url) = plain_http_server().await;
+
+    let response = proxy(
+        Config::default(),
+        TestRequest::get().uri(&format!("/{}/", url)),
+    )
+    .await;
+
+    assert_eq!(response.status, StatusCode::BAD_GATEWAY);
+    assert_eq!(response.text(), "Upstream TLS handshake failed");
+}
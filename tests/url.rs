@@ -0,0 +1,288 @@
+mod common;
+
+use actix_web::http::StatusCode;
+use actix_web::test::TestRequest;
+use wiremock::matchers::method;
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+use common::{header_values, proxy, received, target};
+use rcp::config::Config;
+
+async fn upstream() -> MockServer {
+    let upstream = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&upstream)
+        .await;
+    upstream
+}
+
+/// A target path on the upstream whose full URL is exactly `length` bytes.
+fn path_with_length(upstream: &MockServer, length: usize) -> String {
+    let prefix = format!("{}/?q=", upstream.uri());
+    format!("/?q={}", "a".repeat(length - prefix.len()))
+}
+
+#[actix_web::test]
+async fn accepts_url_at_length_limit() {
+    let upstream = upstream().await;
+    let config = Config {
+        max_url_length: 100,
+        ..Config::default()
+    };
+
+    let path = path_with_length(&upstream, 100);
+    let response = proxy(config, TestRequest::get().uri(&target(&upstream, &path))).await;
+
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(received(&upstream).await.len(), 1);
+}
+
+#[actix_web::test]
+async fn rejects_url_over_length_limit() {
+    let upstream = upstream().await;
+    let config = Config {
+        max_url_length: 100,
+        ..Config::default()
+    };
+
+    let path = path_with_length(&upstream, 101);
+    let response = proxy(config, TestRequest::get().uri(&target(&upstream, &path))).await;
+
+    assert_eq!(response.status, StatusCode::URI_TOO_LONG);
+    assert!(received(&upstream).await.is_empty());
+}
+
+#[actix_web::test]
+async fn counts_decoded_url_length() {
+    let upstream = upstream().await;
+    let config = Config {
+        max_url_length: 100,
+        ..Config::default()
+    };
+
+    // Each %61 decodes to a single byte, so the URL is within the limit
+    let prefix = format!("{}/?q=", upstream.uri());
+    let path = format!("/?q={}", "%61".repeat(100 - prefix.len()));
+    let response = proxy(config, TestRequest::get().uri(&target(&upstream, &path))).await;
+
+    assert_eq!(response.status, StatusCode::OK);
+}
+
+#[actix_web::test]
+async fn inserts_upstream_path_prefix() {
+    let cases = [
+        ("/v2/", "/users", "/v2/users"),
+        ("v2", "/users", "/v2/users"),
+        ("/v2", "/users/", "/v2/users/"),
+        ("v2/", "", "/v2/"),
+        ("/api/v2/", "/users?page=2", "/api/v2/users"),
+        ("//v2//", "//users", "/v2/users"),
+    ];
+
+    for (prefix, path, expected) in cases {
+        let upstream = upstream().await;
+        let config = Config {
+            upstream_path_prefix: Some(prefix.to_string()),
+            ..Config::default()
+        };
+
+        let response = proxy(config, TestRequest::get().uri(&target(&upstream, path))).await;
+
+        assert_eq!(response.status, StatusCode::OK);
+        let requests = received(&upstream).await;
+        assert_eq!(requests[0].url.path(), expected, "{} + {}", prefix, path);
+    }
+}
+
+#[actix_web::test]
+async fn keeps_query_after_path_prefix() {
+    let upstream = upstream().await;
+    let config = Config {
+        upstream_path_prefix: Some("/v2".to_string()),
+        ..Config::default()
+    };
+
+    proxy(
+        config,
+        TestRequest::get().uri(&target(&upstream, "/users?page=2")),
+    )
+    .await;
+
+    let requests = received(&upstream).await;
+    assert_eq!(requests[0].url.path(), "/v2/users");
+    assert_eq!(requests[0].url.query(), Some("page=2"));
+}
+
+#[actix_web::test]
+async fn collapses_duplicate_slashes_in_path() {
+    let upstream = upstream().await;
+    let config = Config {
+        normalize_path_slashes: true,
+        ..Config::default()
+    };
+
+    proxy(
+        config,
+        TestRequest::get().uri(&target(&upstream, "//a//b///c/?next=//x//y")),
+    )
+    .await;
+
+    let requests = received(&upstream).await;
+    assert_eq!(requests[0].url.path(), "/a/b/c/");
+    assert_eq!(requests[0].url.query(), Some("next=//x//y"));
+    assert_eq!(requests[0].url.scheme(), "http");
+}
+
+#[actix_web::test]
+async fn keeps_duplicate_slashes_by_default() {
+    let upstream = upstream().await;
+
+    proxy(
+        Config::default(),
+        TestRequest::get().uri(&target(&upstream, "/a//b")),
+    )
+    .await;
+
+    let requests = received(&upstream).await;
+    assert_eq!(requests[0].url.path(), "/a//b");
+}
+
+#[actix_web::test]
+async fn rejects_urls_without_host() {
+    for url in [
+        "/https://",
+        "/http://",
+        "/https:///path",
+        "/https:///example.com/a",
+    ] {
+        let response = proxy(Config::default(), TestRequest::get().uri(url)).await;
+
+        assert_eq!(response.status, StatusCode::BAD_REQUEST, "{}", url);
+        assert_eq!(response.text(), "Missing host", "{}", url);
+    }
+}
+
+#[actix_web::test]
+async fn rejects_extra_schemes_by_default() {
+    for url in ["/ftp://files.example.com/a.txt", "/file:///etc/passwd.txt"] {
+        let response = proxy(Config::default(), TestRequest::get().uri(url)).await;
+
+        assert_eq!(response.status, StatusCode::BAD_REQUEST, "{}", url);
+    }
+}
+
+#[actix_web::test]
+async fn accepts_only_explicitly_allowed_schemes() {
+    let config = || Config {
+        allow_extra_schemes: vec!["ftp".to_string()],
+        ..Config::default()
+    };
+
+    // Allowed schemes pass validation, but only HTTP is proxied
+    let response = proxy(
+        config(),
+        TestRequest::get().uri("/ftp://files.example.com/a.txt"),
+    )
+    .await;
+    assert_eq!(response.status, StatusCode::NOT_IMPLEMENTED);
+
+    let response = proxy(config(), TestRequest::get().uri("/file:///etc/passwd.txt")).await;
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+}
+
+#[test]
+fn makes_scheme_allowlist_explicit() {
+    let config = Config {
+        allow_extra_schemes: vec!["ftp".to_string()],
+        ..Config::default()
+    };
+
+    assert!(config.scheme_allowed("http"));
+    assert!(config.scheme_allowed("https"));
+    assert!(config.scheme_allowed("FTP"));
+    assert!(!config.scheme_allowed("file"));
+    assert!(!Config::default().scheme_allowed("ftp"));
+}
+
+#[actix_web::test]
+async fn strips_listed_query_params_and_keeps_the_order_of_others() {
+    let upstream = upstream().await;
+    let config = Config {
+        strip_query_params: vec!["token".to_string(), "utm_source".to_string()],
+        ..Config::default()
+    };
+
+    let response = proxy(
+        config,
+        TestRequest::get().uri(&target(
+            &upstream,
+            "/search?z=1&token=secret&a=2&utm_source=mail&m=3&token=again",
+        )),
+    )
+    .await;
+
+    assert_eq!(response.status, StatusCode::OK);
+    let requests = received(&upstream).await;
+    assert_eq!(requests[0].url.path(), "/search");
+    assert_eq!(requests[0].url.query(), Some("z=1&a=2&m=3"));
+}
+
+#[actix_web::test]
+async fn forwards_query_unchanged_without_stripped_params() {
+    let upstream = upstream().await;
+
+    proxy(
+        Config::default(),
+        TestRequest::get().uri(&target(&upstream, "/search?b=2&token=x&a=1")),
+    )
+    .await;
+
+    let requests = received(&upstream).await;
+    assert_eq!(requests[0].url.query(), Some("b=2&token=x&a=1"));
+}
+
+/// A target path with credentials in the userinfo of the upstream URL.
+fn with_credentials(upstream: &MockServer, userinfo: &str) -> String {
+    format!("/http://{}@{}/private", userinfo, upstream.address())
+}
+
+#[actix_web::test]
+async fn rejects_credentials_in_urls_by_default() {
+    let upstream = upstream().await;
+
+    let response = proxy(
+        Config::default(),
+        TestRequest::get().uri(&with_credentials(&upstream, "user:secret")),
+    )
+    .await;
+
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+    assert_eq!(response.text(), "Credentials in URLs are not allowed");
+    assert!(received(&upstream).await.is_empty());
+}
+
+#[actix_web::test]
+async fn sends_credentials_in_urls_as_basic_auth_when_allowed() {
+    let upstream = upstream().await;
+    let config = Config {
+        allow_url_credentials: true,
+        ..Config::default()
+    };
+
+    let response = proxy(
+        config,
+        TestRequest::get().uri(&with_credentials(&upstream, "us%40er:p%3Ass")),
+    )
+    .await;
+
+    assert_eq!(response.status, StatusCode::OK);
+    let requests = received(&upstream).await;
+    // base64 of `us@er:p:ss`
+    assert_eq!(
+        header_values(&requests[0], "authorization"),
+        ["Basic dXNAZXI6cDpzcw=="]
+    );
+    assert_eq!(requests[0].url.username(), "");
+    assert_eq!(requests[0].url.path(), "/private");
+}